@@ -0,0 +1,89 @@
+use crate::StrResult;
+use std::io::{self, Read};
+
+const ZIP_MAGIC: &[u8] = &[0x50, 0x4B, 0x03, 0x04];
+const ZIP_EMPTY_MAGIC: &[u8] = &[0x50, 0x4B, 0x05, 0x06];
+const GZIP_MAGIC: &[u8] = &[0x1F, 0x8B];
+const SEVENZIP_MAGIC: &[u8] = &[0x37, 0x7A, 0xBC, 0xAF, 0x27, 0x1C];
+
+const ROM_EXTENSIONS: [&str; 2] = [".gb", ".gbc"];
+
+/// Returns the ROM contained in data. Zip and gzip archives are detected by their magic number
+/// and unpacked, anything else is assumed to be a raw ROM. For zip archives the entry named
+/// `entry` is used, or the first `.gb` or `.gbc` file when no entry is given.
+pub fn extract_rom(data: Vec<u8>, entry: Option<&str>) -> StrResult<Vec<u8>> {
+    if data.starts_with(ZIP_MAGIC) || data.starts_with(ZIP_EMPTY_MAGIC) {
+        extract_zip(&data, entry)
+    }
+    else if data.starts_with(GZIP_MAGIC) {
+        let mut result = vec![];
+        flate2::read::GzDecoder::new(&data[..]).read_to_end(&mut result).map_err(|_| "Could not decompress gzip file")?;
+        Ok(result)
+    }
+    else if data.starts_with(SEVENZIP_MAGIC) {
+        Err("7z archives are not supported, please extract the ROM first")
+    }
+    else {
+        Ok(data)
+    }
+}
+
+fn extract_zip(data: &[u8], entry: Option<&str>) -> StrResult<Vec<u8>> {
+    let mut archive = zip::ZipArchive::new(io::Cursor::new(data)).map_err(|_| "Could not open zip file")?;
+
+    let index = (0 .. archive.len()).find(|&i| {
+        let file = match archive.by_index_raw(i) {
+            Ok(f) => f,
+            Err(_) => return false,
+        };
+        if !file.is_file() { return false; }
+        match entry {
+            // Allow both the full path within the archive and just the file name
+            Some(name) => file.name() == name || file.name().rsplit('/').next() == Some(name),
+            None => {
+                let lowername = file.name().to_lowercase();
+                ROM_EXTENSIONS.iter().any(|ext| lowername.ends_with(ext))
+            },
+        }
+    });
+
+    let mut file = match index {
+        Some(i) => archive.by_index(i).map_err(|_| "Could not read ROM from zip file")?,
+        None if entry.is_some() => return Err("Zip file does not contain the requested entry"),
+        None => return Err("Zip file does not contain a .gb or .gbc file"),
+    };
+
+    let mut result = vec![];
+    file.read_to_end(&mut result).map_err(|_| "Could not read ROM from zip file")?;
+    Ok(result)
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    const ZIPPED_ROM: &str = "test-roms/Super Mario Land 4 (J) [!].zip";
+    const ZIPPED_ROM_WITH_README: &str = "test-roms/Pokemon Red - Color Gen2 (v1.2).zip";
+
+    #[test]
+    fn raw_rom_passthrough() {
+        let data = vec![0u8; 0x150];
+        assert_eq!(super::extract_rom(data.clone(), None).unwrap(), data);
+    }
+
+    #[test]
+    fn zip_first_rom() {
+        let data = fs::read(ZIPPED_ROM).unwrap();
+        let rom = super::extract_rom(data, None).unwrap();
+        assert_eq!(rom.len(), 0x20000);
+        assert_eq!(&rom[0x134 .. 0x143], b"SUPERMARIOLAND4");
+    }
+
+    #[test]
+    fn zip_named_entry() {
+        let data = fs::read(ZIPPED_ROM_WITH_README).unwrap();
+        let readme = super::extract_rom(data.clone(), Some("README.txt")).unwrap();
+        assert_eq!(readme.len(), 4701);
+        assert!(super::extract_rom(data, Some("missing.gb")).is_err());
+    }
+}
@@ -0,0 +1,207 @@
+use crate::StrResult;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Size of the image captured by the Game Boy Camera sensor
+pub const CAMERA_W: usize = 128;
+pub const CAMERA_H: usize = 112;
+
+const PHOTO_COUNT: usize = 30;
+const PHOTO_START: usize = 0x2000;
+const PHOTO_SIZE: usize = 0x1000;
+const PHOTO_TILES_SIZE: usize = CAMERA_W * CAMERA_H / 4;
+
+/// Provides the light that falls on the Game Boy Camera sensor
+pub trait ImageSource : Send {
+    /// Returns a `CAMERA_W` x `CAMERA_H` grayscale image, one byte per pixel where 0 is black.
+    /// Called once at the start of every capture.
+    fn capture(&mut self) -> Vec<u8>;
+}
+
+/// Shows the same PNG image on every capture
+pub struct StaticImage {
+    image: Vec<u8>,
+}
+
+impl StaticImage {
+    pub fn new(path: &Path) -> StrResult<StaticImage> {
+        Ok(StaticImage { image: load_png(path)? })
+    }
+}
+
+impl ImageSource for StaticImage {
+    fn capture(&mut self) -> Vec<u8> {
+        self.image.clone()
+    }
+}
+
+/// Cycles through the PNG images in a directory in name order, advancing on every capture
+pub struct Slideshow {
+    images: Vec<Vec<u8>>,
+    next: usize,
+}
+
+impl Slideshow {
+    pub fn new(dir: &Path) -> StrResult<Slideshow> {
+        let mut paths: Vec<PathBuf> = fs::read_dir(dir).map_err(|_| "Could not read image directory")?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|p| p.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("png")))
+            .collect();
+        paths.sort();
+
+        let images = paths.iter().map(|p| load_png(p)).collect::<StrResult<Vec<_>>>()?;
+        if images.is_empty() {
+            return Err("Image directory does not contain any PNG files");
+        }
+        Ok(Slideshow { images, next: 0 })
+    }
+}
+
+impl ImageSource for Slideshow {
+    fn capture(&mut self) -> Vec<u8> {
+        let image = self.images[self.next].clone();
+        self.next = (self.next + 1) % self.images.len();
+        image
+    }
+}
+
+/// Generated image with gradients and a checkerboard, so the camera works without any input
+#[derive(Default)]
+pub struct TestPattern;
+
+impl ImageSource for TestPattern {
+    fn capture(&mut self) -> Vec<u8> {
+        let mut image = vec![0u8; CAMERA_W * CAMERA_H];
+        for y in 0 .. CAMERA_H {
+            for x in 0 .. CAMERA_W {
+                image[y * CAMERA_W + x] = match y * 4 / CAMERA_H {
+                    0 => (x * 255 / (CAMERA_W - 1)) as u8,
+                    1 => (255 - x * 255 / (CAMERA_W - 1)) as u8,
+                    2 => if (x / 8 + y / 8) % 2 == 0 { 0xFF } else { 0x00 },
+                    _ => (y * 255 / (CAMERA_H - 1)) as u8,
+                };
+            }
+        }
+        image
+    }
+}
+
+/// Opens a PNG file as a `StaticImage`, or a directory as a `Slideshow`
+pub fn image_source_from_path(path: &Path) -> StrResult<Box<dyn ImageSource>> {
+    if path.is_dir() {
+        Ok(Box::new(Slideshow::new(path)?))
+    } else {
+        Ok(Box::new(StaticImage::new(path)?))
+    }
+}
+
+// Loads a PNG as grayscale and scales it to fill the sensor, cropping to its aspect ratio
+fn load_png(path: &Path) -> StrResult<Vec<u8>> {
+    let file = fs::File::open(path).map_err(|_| "Could not open PNG image")?;
+    let mut decoder = png::Decoder::new(file);
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info().map_err(|_| "Could not decode PNG image")?;
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buf).map_err(|_| "Could not decode PNG image")?;
+
+    let (width, height) = (info.width as usize, info.height as usize);
+    let channels = info.color_type.samples();
+    let luma = |x: usize, y: usize| -> u8 {
+        let p = &buf[(y * width + x) * channels ..];
+        match info.color_type {
+            png::ColorType::Rgb | png::ColorType::Rgba => ((p[0] as u32 * 299 + p[1] as u32 * 587 + p[2] as u32 * 114) / 1000) as u8,
+            _ => p[0],
+        }
+    };
+
+    let (crop_w, crop_h) = if width * CAMERA_H > height * CAMERA_W {
+        (height * CAMERA_W / CAMERA_H, height)
+    } else {
+        (width, width * CAMERA_H / CAMERA_W)
+    };
+    if crop_w == 0 || crop_h == 0 { return Err("PNG image is too small"); }
+    let (left, top) = ((width - crop_w) / 2, (height - crop_h) / 2);
+
+    let mut image = vec![0u8; CAMERA_W * CAMERA_H];
+    for y in 0 .. CAMERA_H {
+        for x in 0 .. CAMERA_W {
+            image[y * CAMERA_W + x] = luma(left + x * crop_w / CAMERA_W, top + y * crop_h / CAMERA_H);
+        }
+    }
+    Ok(image)
+}
+
+/// Decodes the 30 photo slots from a Game Boy Camera save as grayscale images of
+/// `CAMERA_W` x `CAMERA_H`. Slots of deleted photos still contain their old image.
+pub fn decode_photos(save: &[u8]) -> StrResult<Vec<Vec<u8>>> {
+    if save.len() < PHOTO_START + PHOTO_COUNT * PHOTO_SIZE {
+        return Err("Save is too small for a Game Boy Camera");
+    }
+
+    Ok((0 .. PHOTO_COUNT).map(|n| {
+        let start = PHOTO_START + n * PHOTO_SIZE;
+        decode_tiles(&save[start .. start + PHOTO_TILES_SIZE])
+    }).collect())
+}
+
+// Converts 16 x 14 tiles in the Game Boy 2bpp format to grayscale
+fn decode_tiles(tiles: &[u8]) -> Vec<u8> {
+    const SHADES: [u8; 4] = [0xFF, 0xAA, 0x55, 0x00];
+    let mut image = vec![0u8; CAMERA_W * CAMERA_H];
+    for y in 0 .. CAMERA_H {
+        for x in 0 .. CAMERA_W {
+            let addr = ((y / 8) * (CAMERA_W / 8) + x / 8) * 16 + (y % 8) * 2;
+            let bit = 7 - (x % 8);
+            let color = ((tiles[addr] >> bit) & 1) | (((tiles[addr + 1] >> bit) & 1) << 1);
+            image[y * CAMERA_W + x] = SHADES[color as usize];
+        }
+    }
+    image
+}
+
+/// Writes a grayscale image as PNG
+pub fn save_grayscale_png(path: &Path, image: &[u8], width: usize, height: usize) -> StrResult<()> {
+    let file = fs::File::create(path).map_err(|_| "Could not create PNG file")?;
+    let mut encoder = png::Encoder::new(file, width as u32, height as u32);
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(|_| "Could not write PNG file")?;
+    writer.write_image_data(image).map_err(|_| "Could not write PNG file")
+}
+
+#[cfg(test)]
+mod test {
+    use super::{CAMERA_W, CAMERA_H};
+
+    #[test]
+    fn decode_photo_tiles() {
+        let mut save = vec![0u8; 0x20000];
+        // Photo 2, first tile row: pixel 0 black, pixel 1 dark gray
+        save[0x4000] = 0x80;
+        save[0x4001] = 0xC0;
+        let photos = super::decode_photos(&save).unwrap();
+        assert_eq!(photos.len(), 30);
+        assert_eq!(photos[2][0], 0x00);
+        assert_eq!(photos[2][1], 0x55);
+        assert_eq!(photos[2][2], 0xFF);
+        assert_eq!(photos[2].len(), CAMERA_W * CAMERA_H);
+        assert!(super::decode_photos(&save[.. 0x1000]).is_err());
+    }
+
+    #[test]
+    fn png_roundtrip_is_scaled_to_sensor() {
+        use super::{ImageSource, StaticImage};
+
+        // Twice the sensor size with a black left half
+        let (w, h) = (CAMERA_W * 2, CAMERA_H * 2);
+        let image: Vec<u8> = (0 .. w * h).map(|i| if i % w < w / 2 { 0x00 } else { 0xFF }).collect();
+        let path = std::env::temp_dir().join(format!("gb_em_camera_test_{}.png", std::process::id()));
+        super::save_grayscale_png(&path, &image, w, h).unwrap();
+
+        let captured = StaticImage::new(&path).unwrap().capture();
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(captured.len(), CAMERA_W * CAMERA_H);
+        assert_eq!(captured[CAMERA_W / 2 - 1], 0x00);
+        assert_eq!(captured[CAMERA_W / 2], 0xFF);
+    }
+}
@@ -2527,7 +2527,7 @@ mod test {
     use super::CPU;
     use crate::mbc;
 
-    const CPUINSTRS: &'static str = "roms/cpu_instrs.gb";
+    const CPUINSTRS: &'static str = "test-roms/cpu_instrs.gb.gz";
     const CPU_SERIAL: &'static [u8] = b"cpu_instrs\n\n01:ok  02:ok  03:ok  04:ok  05:ok  06:ok  07:ok  08:ok  09:ok  10:ok  11:ok  \n\nPassed all tests\n";
     const GPU_CLASSIC_CHECKSUM: u32 = 3112234583;
     const GPU_COLOR_CHECKSUM: u32 = 938267576;
@@ -2542,7 +2542,7 @@ mod test {
                 output.push(v);
                 None
             };
-            let cart = mbc::FileBackedMBC::new(CPUINSTRS.into(), false).unwrap();
+            let cart = mbc::FileBackedMBC::new(CPUINSTRS.into(), &Default::default()).unwrap();
             let mut c = match CPU::new(Box::new(cart), Some(Box::new(serial))) {
                 Err(message) => {
                     panic!("{}", message);
@@ -2579,7 +2579,7 @@ mod test {
                 output.push(v);
                 None
             };
-            let cart = mbc::FileBackedMBC::new(CPUINSTRS.into(), false).unwrap();
+            let cart = mbc::FileBackedMBC::new(CPUINSTRS.into(), &Default::default()).unwrap();
             let mut c = match CPU::new_cgb(Box::new(cart), Some(Box::new(serial))) {
                 Err(message) => {
                     panic!("{}", message);
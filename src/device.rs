@@ -1,13 +1,52 @@
+use crate::camera::ImageSource;
 use crate::cpu::CPU;
 use crate::gbmode::GbMode;
+use crate::gpu::{ColorCorrection, ColorizationKeys, DebugImage, DmgPalette, FrameBlending, Layer, OamEntry, PixelFormat, Renderer};
 use crate::keypad::KeypadKey;
 use crate::printer::GbPrinter;
-use crate::mbc;
+use crate::mbc::{self, RomOptions, RtcMode};
+use crate::mbc::header::{self, CartridgeWarning};
 use crate::sound;
 use crate::StrResult;
+use std::fs;
+use std::path::PathBuf;
 
 pub struct Device {
     cpu: CPU<'static>,
+    warnings: Vec<CartridgeWarning>,
+    settings_path: Option<PathBuf>,
+    saved_settings: Option<EmulatorSettings>,
+}
+
+/// Enhancement settings that change what a game shows, recorded with its save so it is known how
+/// the save was played. Stored as `name=value` lines.
+#[derive(Debug, Default, PartialEq, Eq, Copy, Clone)]
+pub struct EmulatorSettings {
+    /// See `Device::set_unlimited_sprites`
+    pub unlimited_sprites: bool,
+}
+
+impl EmulatorSettings {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        format!("unlimited-sprites={}\n", self.unlimited_sprites).into_bytes()
+    }
+
+    /// Reads settings written by `to_bytes`. Unknown names are skipped, and missing settings
+    /// keep their default.
+    pub fn from_bytes(data: &[u8]) -> StrResult<EmulatorSettings> {
+        let text = ::std::str::from_utf8(data).map_err(|_| "Settings are not valid text")?;
+        let mut settings = EmulatorSettings::default();
+        for line in text.lines().filter(|l| !l.trim().is_empty()) {
+            let (name, value) = line.split_once('=').ok_or("Settings line has no value")?;
+            match name.trim() {
+                "unlimited-sprites" => {
+                    settings.unlimited_sprites = value.trim().parse().map_err(|_| "Invalid unlimited-sprites setting")?;
+                },
+                _ => {},
+            }
+        }
+        Ok(settings)
+    }
 }
 
 fn stdoutprinter(v: u8) -> Option<u8> {
@@ -21,23 +60,49 @@ fn stdoutprinter(v: u8) -> Option<u8> {
 
 impl Device {
     pub fn new(romname: &str, skip_checksum: bool) -> StrResult<Device> {
-        let cart = mbc::FileBackedMBC::new(romname.into(), skip_checksum)?;
-        CPU::new(Box::new(cart), None).map(|cpu| Device { cpu: cpu })
+        Device::new_with_options(romname, &RomOptions { skip_checksum, ..Default::default() })
     }
 
     pub fn new_cgb(romname: &str, skip_checksum: bool) -> StrResult<Device> {
-        let cart = mbc::FileBackedMBC::new(romname.into(), skip_checksum)?;
-        CPU::new_cgb(Box::new(cart), None).map(|cpu| Device { cpu: cpu })
+        Device::new_cgb_with_options(romname, &RomOptions { skip_checksum, ..Default::default() })
+    }
+
+    /// Settings recorded next to the save are read here and written back when the device is
+    /// dropped, together with the save. They are not applied, see `saved_settings`.
+    pub fn new_with_options(romname: &str, options: &RomOptions) -> StrResult<Device> {
+        let cart = mbc::FileBackedMBC::new(romname.into(), options)?;
+        let warnings = cart.warnings().to_vec();
+        let settings_path = cart.settings_path();
+        let cpu = CPU::new(Box::new(cart), None)?;
+        Ok(Device::with_settings_path(cpu, warnings, settings_path))
+    }
+
+    pub fn new_cgb_with_options(romname: &str, options: &RomOptions) -> StrResult<Device> {
+        let cart = mbc::FileBackedMBC::new(romname.into(), options)?;
+        let warnings = cart.warnings().to_vec();
+        let settings_path = cart.settings_path();
+        let cpu = CPU::new_cgb(Box::new(cart), None)?;
+        Ok(Device::with_settings_path(cpu, warnings, settings_path))
     }
 
     pub fn new_from_buffer(romdata: Vec<u8>, skip_checksum: bool) -> StrResult<Device> {
+        let warnings = header::validate_rom(&romdata);
         let cart = mbc::get_mbc(romdata, skip_checksum)?;
-        CPU::new(cart, None).map(|cpu| Device { cpu: cpu })
+        CPU::new(cart, None).map(|cpu| Device::with_settings_path(cpu, warnings, None))
     }
 
     pub fn new_cgb_from_buffer(romdata: Vec<u8>, skip_checksum: bool) -> StrResult<Device> {
+        let warnings = header::validate_rom(&romdata);
         let cart = mbc::get_mbc(romdata, skip_checksum)?;
-        CPU::new_cgb(cart, None).map(|cpu| Device { cpu: cpu })
+        CPU::new_cgb(cart, None).map(|cpu| Device::with_settings_path(cpu, warnings, None))
+    }
+
+    fn with_settings_path(cpu: CPU<'static>, warnings: Vec<CartridgeWarning>, settings_path: Option<PathBuf>) -> Device {
+        // A missing or unreadable file only means nothing was recorded
+        let saved_settings = settings_path.as_ref()
+            .and_then(|path| fs::read(path).ok())
+            .and_then(|data| EmulatorSettings::from_bytes(&data).ok());
+        Device { cpu, warnings, settings_path, saved_settings }
     }
 
     pub fn do_cycle(&mut self) -> u32 {
@@ -69,10 +134,35 @@ impl Device {
         result
     }
 
+    /// The frame being drawn, in the format set with `set_pixel_format`. Read it when
+    /// `check_and_reset_gpu_updated` returns true to get a complete frame.
     pub fn get_gpu_data(&self) -> &[u8] {
         &self.cpu.mmu.gpu.data
     }
 
+    /// Like `get_gpu_data`, with one byte per pixel before the colours are applied: the shade
+    /// from 0 to 3 in DMG mode, or in CGB mode the palette index from 0 to 31 for the background
+    /// palettes and 32 to 63 for the object palettes.
+    pub fn get_raw_gpu_data(&self) -> &[u8] {
+        &self.cpu.mmu.gpu.raw
+    }
+
+    /// Exchanges the frame with `frame`, in which the next frames are drawn. Swapping two buffers
+    /// back and forth avoids copying every frame.
+    pub fn swap_gpu_data(&mut self, frame: &mut Vec<u8>) {
+        self.cpu.mmu.gpu.swap_frame(frame);
+    }
+
+    /// Selects the format of the frames returned by `get_gpu_data` and `swap_gpu_data`. Call it
+    /// right after constructing the device, as it clears the frame being drawn. Rgb888 by default.
+    pub fn set_pixel_format(&mut self, format: PixelFormat) {
+        self.cpu.mmu.gpu.set_pixel_format(format);
+    }
+
+    pub fn pixel_format(&self) -> PixelFormat {
+        self.cpu.mmu.gpu.pixel_format()
+    }
+
     pub fn enable_audio(&mut self, player: Box<dyn sound::AudioPlayer>) {
         match self.cpu.mmu.gbmode {
             GbMode::Classic => {
@@ -98,6 +188,146 @@ impl Device {
         self.cpu.mmu.keypad.keydown(key);
     }
 
+    /// Sets the tilt for cartridges with an accelerometer (MBC7), in g. Positive x tilts to the
+    /// right, positive y tilts towards the bottom of the screen.
+    pub fn set_accelerometer(&mut self, x: f32, y: f32) {
+        self.cpu.mmu.mbc.set_accelerometer(x, y);
+    }
+
+    /// Sets the images seen by the Game Boy Camera. A test pattern is used by default.
+    pub fn set_camera_source(&mut self, source: Box<dyn ImageSource>) {
+        self.cpu.mmu.mbc.set_image_source(source);
+    }
+
+    /// Selects whether the cartridge RTC follows the system clock or the emulated clock cycles
+    pub fn set_rtc_mode(&mut self, mode: RtcMode) {
+        self.cpu.mmu.mbc.set_rtc_mode(mode);
+    }
+
+    /// Sets the time of the cartridge RTC, in seconds since day 0
+    pub fn set_rtc_time(&mut self, seconds: u64) {
+        self.cpu.mmu.mbc.set_rtc_time(seconds);
+    }
+
+    /// Moves the time of the cartridge RTC forward or back by whole days
+    pub fn offset_rtc_days(&mut self, days: i64) {
+        self.cpu.mmu.mbc.offset_rtc_days(days);
+    }
+
+    /// Selects how the screen is drawn. The pixel FIFO renderer is slower, but shows register
+    /// changes made while a line is drawn.
+    pub fn set_renderer(&mut self, renderer: Renderer) {
+        self.cpu.mmu.gpu.set_renderer(renderer);
+    }
+
+    /// Sets the shades used to show the monochrome palettes in DMG mode
+    pub fn set_dmg_palette(&mut self, palette: DmgPalette) {
+        self.cpu.mmu.gpu.set_dmg_palette(palette);
+    }
+
+    /// Selects how CGB palette colours are shown. Gambatte's colour matrix is used by default.
+    pub fn set_color_correction(&mut self, correction: ColorCorrection) {
+        self.cpu.mmu.gpu.set_color_correction(correction);
+    }
+
+    /// Picks the colours of a DMG game in CGB mode, like holding these keys on the CGB boot screen.
+    /// By default the colours are chosen from the title of the game.
+    pub fn set_colorization_keys(&mut self, keys: ColorizationKeys) {
+        self.cpu.mmu.gpu.set_colorization_keys(keys);
+    }
+
+    /// Mixes each frame with the previous ones before it is handed out, to show objects that
+    /// flicker on alternate frames like the real screen does. Off by default.
+    pub fn set_frame_blending(&mut self, blending: FrameBlending) {
+        self.cpu.mmu.gpu.set_frame_blending(blending);
+    }
+
+    /// Draws every object on a line instead of only the first ten like the hardware, which stops
+    /// objects from flickering or disappearing. The timing of the line is not changed. Off by
+    /// default.
+    pub fn set_unlimited_sprites(&mut self, unlimited: bool) {
+        self.cpu.mmu.gpu.set_unlimited_sprites(unlimited);
+    }
+
+    /// Returns true when more than ten objects per line are drawn
+    pub fn unlimited_sprites(&self) -> bool {
+        self.cpu.mmu.gpu.unlimited_sprites()
+    }
+
+    /// The enhancement settings in use. Store them with `dumpram` to record how a save was played.
+    pub fn settings(&self) -> EmulatorSettings {
+        EmulatorSettings {
+            unlimited_sprites: self.unlimited_sprites(),
+        }
+    }
+
+    pub fn apply_settings(&mut self, settings: &EmulatorSettings) {
+        self.set_unlimited_sprites(settings.unlimited_sprites);
+    }
+
+    /// The settings recorded with the save file when the device was created from a file, if any
+    pub fn saved_settings(&self) -> Option<EmulatorSettings> {
+        self.saved_settings
+    }
+
+    /// Shows or hides a layer of the picture. A hidden background or window is left blank, and
+    /// sprites are still drawn over it.
+    pub fn set_layer_enabled(&mut self, layer: Layer, enabled: bool) {
+        self.cpu.mmu.gpu.set_layer_enabled(layer, enabled);
+    }
+
+    /// Tints the window, to show which part of the screen it covers
+    pub fn set_highlight_window(&mut self, highlight: bool) {
+        self.cpu.mmu.gpu.set_highlight_window(highlight);
+    }
+
+    /// Draws sprites over the background even when their priority would hide them
+    pub fn set_ignore_sprite_priority(&mut self, ignore: bool) {
+        self.cpu.mmu.gpu.set_ignore_sprite_priority(ignore);
+    }
+
+    /// All 384 tiles of both VRAM banks, side by side
+    pub fn tile_data_image(&self) -> DebugImage {
+        self.cpu.mmu.gpu.tile_data_image()
+    }
+
+    /// The tile map at 0x9800 (0) or 0x9C00 (1), with the scroll viewport outlined
+    pub fn tilemap_image(&self, map: usize) -> DebugImage {
+        self.cpu.mmu.gpu.tilemap_image(map)
+    }
+
+    /// The 40 entries of OAM with their attributes decoded
+    pub fn oam_entries(&self) -> Vec<OamEntry> {
+        self.cpu.mmu.gpu.oam_entries()
+    }
+
+    /// The 40 objects as they would be drawn
+    pub fn oam_image(&self) -> DebugImage {
+        self.cpu.mmu.gpu.oam_image()
+    }
+
+    /// The background and object palettes as rows of colours
+    pub fn palette_image(&self) -> DebugImage {
+        self.cpu.mmu.gpu.palette_image()
+    }
+
+    /// Returns true while the rumble motor of the cartridge is running. Poll this once per frame
+    /// to drive force feedback.
+    pub fn rumble_state(&self) -> bool {
+        self.cpu.mmu.mbc.rumble_on()
+    }
+
+    /// Returns true while the flash memory of the cartridge (MBC6) is not write protected
+    pub fn flash_writable(&self) -> bool {
+        self.cpu.mmu.mbc.flash_writable()
+    }
+
+    /// Problems found in the ROM while loading it. They did not stop it from loading, but may
+    /// explain why it does not run correctly.
+    pub fn cartridge_warnings(&self) -> &[CartridgeWarning] {
+        &self.warnings
+    }
+
     pub fn romname(&self) -> String {
         self.cpu.mmu.mbc.romname()
     }
@@ -117,4 +347,32 @@ impl Device {
     pub fn check_and_reset_ram_updated(&mut self) -> bool {
         self.cpu.mmu.mbc.check_and_reset_ram_updated()
     }
-}
\ No newline at end of file
+}
+
+impl Drop for Device {
+    fn drop(&mut self) {
+        if let Some(ref path) = self.settings_path {
+            let _ = fs::write(path, self.settings().to_bytes());
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Device, EmulatorSettings};
+
+    #[test]
+    fn settings() {
+        let settings = EmulatorSettings { unlimited_sprites: true };
+        assert_eq!(EmulatorSettings::from_bytes(&settings.to_bytes()), Ok(settings));
+        assert_eq!(EmulatorSettings::from_bytes(b"future=1\n"), Ok(EmulatorSettings::default()));
+        assert!(EmulatorSettings::from_bytes(b"unlimited-sprites=maybe\n").is_err());
+
+        let mut device = Device::new_from_buffer(vec![0; 0x8000], true).unwrap();
+        assert_eq!(device.settings(), EmulatorSettings::default());
+        device.apply_settings(&settings);
+        assert!(device.unlimited_sprites());
+        assert_eq!(device.settings(), settings);
+        assert_eq!(device.saved_settings(), None);
+    }
+}
@@ -0,0 +1,134 @@
+use std::str::FromStr;
+use crate::gbmode::GbMode;
+use crate::StrResult;
+use super::GPU;
+
+/// How each frame is mixed with the frames before it, like the slow LCD of the real hardware.
+/// Games that flicker objects on alternate frames rely on this to show them half transparent.
+#[derive(Debug, Default, PartialEq, Copy, Clone)]
+pub enum FrameBlending {
+    /// Every frame is shown as drawn
+    #[default]
+    Off,
+    /// Shows the average of each frame and the one before it
+    Mix,
+    /// Keeps the given fraction, between 0 and 1, of the previous output, so older frames fade
+    /// out gradually
+    Decay(f32),
+    /// Follows the response of the screen of the model: the DMG screen is slow and clears
+    /// slower than it darkens, while the CGB screen is quicker
+    Lcd,
+}
+
+// Fraction of the previous output kept by the LCD response
+const DMG_DARKEN: f32 = 0.35;
+const DMG_LIGHTEN: f32 = 0.55;
+const CGB_PERSISTENCE: f32 = 0.25;
+
+impl FromStr for FrameBlending {
+    type Err = &'static str;
+
+    /// Accepts `off`, `mix`, `lcd` or `decay:<persistence>` such as `decay:0.6`
+    fn from_str(s: &str) -> StrResult<FrameBlending> {
+        match &*s.to_lowercase() {
+            "off" => Ok(FrameBlending::Off),
+            "mix" => Ok(FrameBlending::Mix),
+            "lcd" => Ok(FrameBlending::Lcd),
+            s => match s.strip_prefix("decay:").map(|p| p.parse::<f32>()) {
+                Some(Ok(p)) if (0.0 .. 1.0).contains(&p) => Ok(FrameBlending::Decay(p)),
+                Some(_) => Err("Decay persistence must be at least 0 and below 1"),
+                None => Err("Frame blending must be off, mix, lcd or decay:<persistence>"),
+            },
+        }
+    }
+}
+
+// Rounds towards the new colour, so that a still picture is eventually shown exactly
+fn decay(color: [u8; 3], prev: [u8; 3], persistence: impl Fn(u8, u8) -> f32) -> [u8; 3] {
+    let channel = |c: u8, p: u8| (c as f32 + ((p as f32 - c as f32) * persistence(c, p)).trunc()) as u8;
+    [channel(color[0], prev[0]), channel(color[1], prev[1]), channel(color[2], prev[2])]
+}
+
+impl GPU {
+    // Mixes a pixel of the current frame with the same pixel of the previous frames
+    pub(super) fn blend(&mut self, index: usize, color: [u8; 3]) -> [u8; 3] {
+        let prev = self.blend_prev[index];
+        let (output, kept) = match self.frame_blending {
+            FrameBlending::Off => (color, color),
+            FrameBlending::Mix => {
+                let mix = |c: u8, p: u8| (c as u16 + p as u16).div_ceil(2) as u8;
+                ([mix(color[0], prev[0]), mix(color[1], prev[1]), mix(color[2], prev[2])], color)
+            },
+            FrameBlending::Decay(persistence) => {
+                let output = decay(color, prev, |_, _| persistence);
+                (output, output)
+            },
+            FrameBlending::Lcd => {
+                let output = if self.gbmode == GbMode::Classic {
+                    decay(color, prev, |c, p| if c < p { DMG_DARKEN } else { DMG_LIGHTEN })
+                } else {
+                    decay(color, prev, |_, _| CGB_PERSISTENCE)
+                };
+                (output, output)
+            },
+        };
+        self.blend_next[index] = kept;
+        output
+    }
+
+    // The frame just drawn becomes the one the next frame is blended with
+    pub(super) fn finish_blended_frame(&mut self) {
+        if self.frame_blending != FrameBlending::Off {
+            ::std::mem::swap(&mut self.blend_prev, &mut self.blend_next);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::FrameBlending;
+    use crate::gpu::GPU;
+
+    // Draws a frame that is all black or all white with the background
+    fn frame(gpu: &mut GPU, black: bool) {
+        gpu.wb(0xFF47, if black { 0xFF } else { 0x00 });
+        gpu.do_cycle(456 * 154);
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!("Mix".parse(), Ok(FrameBlending::Mix));
+        assert_eq!("decay:0.6".parse(), Ok(FrameBlending::Decay(0.6)));
+        assert!("decay:1.5".parse::<FrameBlending>().is_err());
+        assert!("decay".parse::<FrameBlending>().is_err());
+    }
+
+    #[test]
+    fn blending() {
+        let mut gpu = GPU::new();
+        gpu.wb(0xFF40, 0x91);
+        gpu.set_frame_blending(FrameBlending::Mix);
+        frame(&mut gpu, true);
+        frame(&mut gpu, false);
+        assert_eq!(gpu.data[0], 128);
+        frame(&mut gpu, false);
+        assert_eq!(gpu.data[0], 255);
+
+        gpu.set_frame_blending(FrameBlending::Decay(0.5));
+        frame(&mut gpu, true);
+        assert_eq!(gpu.data[0], 127);
+        frame(&mut gpu, true);
+        assert_eq!(gpu.data[0], 63);
+
+        // The DMG screen clears slower than it darkens
+        gpu.set_frame_blending(FrameBlending::Lcd);
+        frame(&mut gpu, false);
+        let lightened = gpu.data[0] - 63;
+        for _ in 0 .. 20 {
+            frame(&mut gpu, false);
+        }
+        assert_eq!(gpu.data[0], 255);
+        frame(&mut gpu, true);
+        assert!(255 - gpu.data[0] > lightened);
+    }
+}
@@ -0,0 +1,179 @@
+use std::str::FromStr;
+use crate::StrResult;
+
+/// The button combinations that pick the colours of a DMG game when held on the CGB boot screen
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum ColorizationKeys {
+    Right,
+    Left,
+    Up,
+    Down,
+    RightA,
+    LeftA,
+    UpA,
+    DownA,
+    RightB,
+    LeftB,
+    UpB,
+    DownB,
+}
+
+impl ColorizationKeys {
+    pub(super) fn colors(self) -> CompatColors {
+        colors_of_combination(KEY_COMBINATIONS[self as usize])
+    }
+}
+
+impl FromStr for ColorizationKeys {
+    type Err = &'static str;
+
+    /// Accepts a direction, optionally joined to A or B with a plus sign, such as `left+a`
+    fn from_str(s: &str) -> StrResult<ColorizationKeys> {
+        use self::ColorizationKeys::*;
+
+        let lower = s.to_lowercase();
+        let mut keys: Vec<&str> = lower.split('+').map(|k| k.trim()).collect();
+        keys.sort_unstable();
+        Ok(match &keys[..] {
+            ["right"] => Right,
+            ["left"] => Left,
+            ["up"] => Up,
+            ["down"] => Down,
+            ["a", "right"] => RightA,
+            ["a", "left"] => LeftA,
+            ["a", "up"] => UpA,
+            ["a", "down"] => DownA,
+            ["b", "right"] => RightB,
+            ["b", "left"] => LeftB,
+            ["b", "up"] => UpB,
+            ["b", "down"] => DownB,
+            _ => return Err("Colorization keys must be a direction, optionally with A or B"),
+        })
+    }
+}
+
+// Colours of the background and the two object palettes, as 15-bit CGB colours
+pub(super) type CompatColors = [[u16; 4]; 3];
+
+/// Picks the colours the CGB boot ROM gives a DMG game: games by Nintendo have colours chosen
+/// from the checksum of their title, everything else gets the default ones
+pub(crate) fn title_colors(header: &[u8]) -> CompatColors {
+    colors_of_combination(PALETTE_PER_CHECKSUM[title_index(header).unwrap_or(0)])
+}
+
+fn title_index(header: &[u8]) -> Option<usize> {
+    let nintendo = match header[0x14B] {
+        0x33 => &header[0x144 .. 0x146] == b"01",
+        licensee => licensee == 0x01,
+    };
+    if !nintendo { return None }
+
+    let checksum = header[0x134 .. 0x144].iter().fold(0u8, |sum, &b| sum.wrapping_add(b));
+    // Some checksums are shared, and the 4th letter of the title tells those games apart
+    TITLE_CHECKSUMS.iter().enumerate().position(|(i, &c)| {
+        c == checksum && (i < FIRST_DUPLICATE || FOURTH_LETTERS[i - FIRST_DUPLICATE] == header[0x137])
+    })
+}
+
+fn colors_of_combination(combination: u8) -> CompatColors {
+    let [obj0, obj1, bg] = COMBINATIONS[combination as usize];
+    let palette = |start: u8| {
+        let start = start as usize;
+        [COLORS[start], COLORS[start + 1], COLORS[start + 2], COLORS[start + 3]]
+    };
+    [palette(bg), palette(obj0), palette(obj1)]
+}
+
+// The tables below are those of the CGB boot ROM
+
+const FIRST_DUPLICATE: usize = 65;
+const FOURTH_LETTERS: &[u8; 29] = b"BEFAARBEKEK R-URAR INAILICE R";
+
+const TITLE_CHECKSUMS: [u8; 94] = [
+    0x00, 0x88, 0x16, 0x36, 0xD1, 0xDB, 0xF2, 0x3C, 0x8C, 0x92, 0x3D, 0x5C, 0x58, 0xC9, 0x3E, 0x70,
+    0x1D, 0x59, 0x69, 0x19, 0x35, 0xA8, 0x14, 0xAA, 0x75, 0x95, 0x99, 0x34, 0x6F, 0x15, 0xFF, 0x97,
+    0x4B, 0x90, 0x17, 0x10, 0x39, 0xF7, 0xF6, 0xA2, 0x49, 0x4E, 0x43, 0x68, 0xE0, 0x8B, 0xF0, 0xCE,
+    0x0C, 0x29, 0xE8, 0xB7, 0x86, 0x9A, 0x52, 0x01, 0x9D, 0x71, 0x9C, 0xBD, 0x5D, 0x6D, 0x67, 0x3F,
+    0x6B, 0xB3, 0x46, 0x28, 0xA5, 0xC6, 0xD3, 0x27, 0x61, 0x18, 0x66, 0x6A, 0xBF, 0x0D, 0xF4, 0xB3,
+    0x46, 0x28, 0xA5, 0xC6, 0xD3, 0x27, 0x61, 0x18, 0x66, 0x6A, 0xBF, 0x0D, 0xF4, 0xB3,
+];
+
+// Combination of palettes for each title checksum
+const PALETTE_PER_CHECKSUM: [u8; 94] = [
+     0,  4,  5, 35, 34,  3, 31, 15, 10,  5, 19, 36,  7, 37, 30, 44,
+    21, 32, 31, 20,  5, 33, 13, 14,  5, 29,  5, 18,  9,  3,  2, 26,
+    25, 25, 41, 42, 26, 45, 42, 45, 36, 38, 26, 42, 30, 41, 34, 34,
+     5, 42,  6,  5, 33, 25, 42, 42, 40,  2, 16, 25, 42, 42,  5,  0,
+    39, 36, 22, 25,  6, 32, 12, 36, 11, 39, 18, 39, 24, 31, 50, 17,
+    46,  6, 27,  0, 47, 41, 41,  0,  0, 19, 34, 23, 18, 29,
+];
+
+// First colour of the OBJ0, OBJ1 and BG palettes
+const COMBINATIONS: [[u8; 3]; 51] = [
+    [ 16,  16, 116], [ 72,  72,  72], [ 80,  80,  80], [ 96,  96,  96], [ 36,  36,  36], [  0,   0,   0],
+    [108, 108, 108], [ 20,  20,  20], [ 48,  48,  48], [104, 104, 104], [ 64,  32,  32], [ 16, 112, 112],
+    [ 16,   8,   8], [ 12,  16,  16], [ 16, 116, 116], [112,  16, 112], [  8,  68,   8], [ 64,  64,  32],
+    [ 16,  16,  28], [ 16,  16,  72], [ 16,  16,  80], [ 76,  76,  36], [ 15,  15,  44], [ 68,  68,   8],
+    [ 16,  16,   8], [ 16,  16,  12], [112, 112,   0], [ 12,  12,   0], [  0,   0,   4], [ 72,  88,  72],
+    [ 80,  88,  80], [ 96,  88,  96], [ 64,  88,  32], [ 68,  16,  52], [111,   0,  56], [111,  16,  60],
+    [ 76,  91,  36], [ 64, 112,  40], [ 16,  92, 112], [ 68,  88,   8], [ 16,   0,   8], [ 16, 112,  12],
+    [112,  12,   0], [ 12, 112,  16], [ 84, 112,  16], [ 12, 112,   0], [100,  12, 112], [  0, 112,  32],
+    [ 16,  12, 112], [112,  12,  24], [ 16, 112, 116],
+];
+
+const COLORS: [u16; 120] = [
+    0x7FFF, 0x32BF, 0x00D0, 0x0000, 0x639F, 0x4279, 0x15B0, 0x04CB,
+    0x7FFF, 0x6E31, 0x454A, 0x0000, 0x7FFF, 0x1BEF, 0x0200, 0x0000,
+    0x7FFF, 0x421F, 0x1CF2, 0x0000, 0x7FFF, 0x5294, 0x294A, 0x0000,
+    0x7FFF, 0x03FF, 0x012F, 0x0000, 0x7FFF, 0x03EF, 0x01D6, 0x0000,
+    0x7FFF, 0x42B5, 0x3DC8, 0x0000, 0x7E74, 0x03FF, 0x0180, 0x0000,
+    0x67FF, 0x77AC, 0x1A13, 0x2D6B, 0x7ED6, 0x4BFF, 0x2175, 0x0000,
+    0x53FF, 0x4A5F, 0x7E52, 0x0000, 0x4FFF, 0x7ED2, 0x3A4C, 0x1CE0,
+    0x03ED, 0x7FFF, 0x255F, 0x0000, 0x036A, 0x021F, 0x03FF, 0x7FFF,
+    0x7FFF, 0x01DF, 0x0112, 0x0000, 0x231F, 0x035F, 0x00F2, 0x0009,
+    0x7FFF, 0x03EA, 0x011F, 0x0000, 0x299F, 0x001A, 0x000C, 0x0000,
+    0x7FFF, 0x027F, 0x001F, 0x0000, 0x7FFF, 0x03E0, 0x0206, 0x0120,
+    0x7FFF, 0x7EEB, 0x001F, 0x7C00, 0x7FFF, 0x3FFF, 0x7E00, 0x001F,
+    0x7FFF, 0x03FF, 0x001F, 0x0000, 0x03FF, 0x001F, 0x000C, 0x0000,
+    0x7FFF, 0x033F, 0x0193, 0x0000, 0x0000, 0x4200, 0x037F, 0x7FFF,
+    0x7FFF, 0x7E8C, 0x7C00, 0x0000, 0x7FFF, 0x1BEF, 0x6180, 0x0000,
+];
+
+// Combination of palettes for each of the button combinations
+const KEY_COMBINATIONS: [u8; 12] = [1, 48, 5, 8, 0, 40, 43, 3, 6, 7, 28, 49];
+
+#[cfg(test)]
+mod test {
+    use super::{ColorizationKeys, title_colors};
+
+    fn header(title: &[u8], licensee: u8) -> Vec<u8> {
+        let mut header = vec![0; 0x150];
+        header[0x134 .. 0x134 + title.len()].copy_from_slice(title);
+        header[0x14B] = licensee;
+        header
+    }
+
+    #[test]
+    fn colors_from_title() {
+        // POKEMON RED gets the red background
+        let red = title_colors(&header(b"POKEMON RED", 0x01));
+        assert_eq!(red[0], [0x7FFF, 0x421F, 0x1CF2, 0x0000]);
+        // The same title from another publisher gets the default colours
+        assert_eq!(title_colors(&header(b"POKEMON RED", 0x08)), title_colors(&header(b"", 0x01)));
+
+        // SUPER MARIOLAND shares its checksum with other games
+        let mut mario = header(b"SUPER MARIOLAND", 0x33);
+        mario[0x144 .. 0x146].copy_from_slice(b"01");
+        let colors = title_colors(&mario);
+        assert_eq!(colors[0], [0x7ED6, 0x4BFF, 0x2175, 0x0000]);
+        assert_eq!(colors[1], [0x0000, 0x7FFF, 0x421F, 0x1CF2]);
+    }
+
+    #[test]
+    fn keys() {
+        assert_eq!("A+Left".parse(), Ok(ColorizationKeys::LeftA));
+        assert!("left+a+b".parse::<ColorizationKeys>().is_err());
+        assert!("a".parse::<ColorizationKeys>().is_err());
+        assert_eq!(ColorizationKeys::Up.colors(), [[0x7FFF, 0x32BF, 0x00D0, 0x0000]; 3]);
+    }
+}
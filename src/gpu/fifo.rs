@@ -0,0 +1,431 @@
+use std::collections::VecDeque;
+use crate::gbmode::GbMode;
+use super::{GPU, Layer, MAX_LINE_OBJECTS, SCREEN_W};
+
+// Dots taken by fetching the tile data of an object
+const OBJ_FETCH_DOTS: u8 = 6;
+
+#[derive(Clone, Copy)]
+struct BgPixel {
+    color: u8,
+    palette: u8,
+    priority: bool,
+    window: bool,
+}
+
+#[derive(Clone, Copy, Default)]
+struct ObjPixel {
+    color: u8,
+    palette: u8,
+    below_bg: bool,
+    oam_index: u8,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum FetchStep {
+    Tile,
+    DataLow,
+    DataHigh,
+    Push,
+}
+
+// An object on the current line, found by the OAM scan
+#[derive(Clone, Copy)]
+struct LineObject {
+    x: u8,
+    y: u8,
+    index: u8,
+    fetched: bool,
+}
+
+/// State of the pixel FIFO renderer while a line is drawn. The background fetcher takes two dots
+/// for each of its steps and pushes eight pixels at once when the background FIFO is empty. One
+/// pixel is shifted out per dot, mixed with the object FIFO. Objects pause the output while they
+/// are fetched, and the window restarts the fetcher when it is reached.
+pub(super) struct PixelFifo {
+    bg: VecDeque<BgPixel>,
+    obj: VecDeque<ObjPixel>,
+    step: FetchStep,
+    step_dots: u8,
+    // Tile column of the next fetch, relative to the start of the background or window
+    fetch_x: u8,
+    window: bool,
+    first_fetch: bool,
+    tile_number: u8,
+    tile_attrs: u8,
+    tile_row: u16,
+    data_low: u8,
+    data_high: u8,
+    // Next pixel to be drawn, and the number of fetched pixels still to be dropped
+    lx: u8,
+    discard: u8,
+    objects: Vec<LineObject>,
+    obj_fetch: Option<usize>,
+    obj_fetch_dots: u8,
+}
+
+impl PixelFifo {
+    pub(super) fn new() -> PixelFifo {
+        PixelFifo {
+            bg: VecDeque::with_capacity(16),
+            obj: VecDeque::with_capacity(16),
+            step: FetchStep::Tile,
+            step_dots: 0,
+            fetch_x: 0,
+            window: false,
+            first_fetch: true,
+            tile_number: 0,
+            tile_attrs: 0,
+            tile_row: 0,
+            data_low: 0,
+            data_high: 0,
+            lx: 0,
+            discard: 0,
+            objects: Vec::with_capacity(40),
+            obj_fetch: None,
+            obj_fetch_dots: 0,
+        }
+    }
+}
+
+impl GPU {
+    // Resets the FIFOs and does the OAM scan at the start of mode 3
+    pub(super) fn fifo_start_line(&mut self) {
+        let line = self.line as i32;
+        let sprite_size = self.sprite_size as i32;
+        let limit = self.line_object_limit();
+
+        let fifo = &mut self.fifo;
+        fifo.bg.clear();
+        fifo.obj.clear();
+        fifo.step = FetchStep::Tile;
+        fifo.step_dots = 0;
+        fifo.fetch_x = 0;
+        fifo.window = false;
+        fifo.first_fetch = true;
+        fifo.lx = 0;
+        fifo.discard = self.scx & 0x07;
+        fifo.obj_fetch = None;
+        fifo.obj_fetch_dots = 0;
+
+        fifo.objects.clear();
+        for index in 0 .. 40 {
+            let y = self.voam[index * 4];
+            let spritey = y as i32 - 16;
+            if line < spritey || line >= spritey + sprite_size { continue }
+            fifo.objects.push(LineObject { x: self.voam[index * 4 + 1], y, index: index as u8, fetched: false });
+            if fifo.objects.len() >= limit {
+                break;
+            }
+        }
+    }
+
+    // Runs the renderer for one dot. Returns true when the line is complete.
+    pub(super) fn fifo_step(&mut self) -> bool {
+        if self.fifo.lx as usize >= SCREEN_W { return true }
+
+        if self.fifo.obj_fetch_dots > 0 {
+            self.fifo.obj_fetch_dots -= 1;
+            if self.fifo.obj_fetch_dots == 0 {
+                if let Some(i) = self.fifo.obj_fetch.take() {
+                    self.fifo_merge_object(i);
+                }
+            }
+            return false;
+        }
+
+        if self.win_on && self.wy_trigger && !self.fifo.window && self.winx <= 166 && self.fifo.lx as i32 >= self.winx as i32 - 7 {
+            self.wy_pos += 1;
+            let fifo = &mut self.fifo;
+            fifo.window = true;
+            fifo.bg.clear();
+            fifo.step = FetchStep::Tile;
+            fifo.step_dots = 0;
+            fifo.fetch_x = 0;
+            // A window left of the screen edge starts part way into its first tile
+            fifo.discard = 7u8.saturating_sub(self.winx);
+        }
+
+        while self.sprite_on {
+            let lx = self.fifo.lx as u32;
+            let next = self.fifo.objects.iter().enumerate()
+                .filter(|(_, o)| !o.fetched && o.x as u32 <= lx + 8)
+                .min_by_key(|(_, o)| (o.x, o.index))
+                .map(|(i, _)| i);
+            match next {
+                // Objects over the hardware limit are drawn without taking any time
+                Some(i) if i >= MAX_LINE_OBJECTS => {
+                    self.fifo.objects[i].fetched = true;
+                    self.fifo_merge_object(i);
+                },
+                Some(i) => {
+                    // The object is fetched once the background fetcher has its data ready
+                    if self.fifo.step == FetchStep::Push && !self.fifo.bg.is_empty() {
+                        self.fifo.objects[i].fetched = true;
+                        self.fifo.obj_fetch = Some(i);
+                        self.fifo.obj_fetch_dots = OBJ_FETCH_DOTS;
+                    } else {
+                        self.fifo_fetch();
+                    }
+                    return false;
+                },
+                None => break,
+            }
+        }
+
+        // Pixels pushed by the fetcher are shifted out from the next dot
+        if let Some(bg) = self.fifo.bg.pop_front() {
+            if self.fifo.discard > 0 {
+                self.fifo.discard -= 1;
+            } else {
+                let obj = self.fifo.obj.pop_front();
+                self.fifo_output(bg, obj);
+                self.fifo.lx += 1;
+            }
+        }
+        self.fifo_fetch();
+        self.fifo.lx as usize >= SCREEN_W
+    }
+
+    fn fifo_fetch(&mut self) {
+        if self.fifo.step != FetchStep::Push {
+            self.fifo.step_dots += 1;
+            if self.fifo.step_dots < 2 { return }
+            self.fifo.step_dots = 0;
+        }
+
+        match self.fifo.step {
+            FetchStep::Tile => {
+                let (tilemap, tilex, y) = if self.fifo.window {
+                    (self.win_tilemap, self.fifo.fetch_x as u16 & 31, self.wy_pos as u8)
+                } else {
+                    (self.bg_tilemap, ((self.scx >> 3) as u16 + self.fifo.fetch_x as u16) & 31, self.scy.wrapping_add(self.line))
+                };
+                let address = tilemap + ((y as u16 >> 3) & 31) * 32 + tilex;
+                self.fifo.tile_number = self.rbvram0(address);
+                self.fifo.tile_attrs = if self.gbmode == GbMode::Color { self.rbvram1(address) } else { 0 };
+                self.fifo.tile_row = y as u16 & 0x07;
+                self.fifo.step = FetchStep::DataLow;
+            },
+            FetchStep::DataLow => {
+                self.fifo.data_low = self.fifo_tile_data(0);
+                self.fifo.step = FetchStep::DataHigh;
+            },
+            FetchStep::DataHigh => {
+                self.fifo.data_high = self.fifo_tile_data(1);
+                self.fifo.step = FetchStep::Push;
+                self.fifo_push();
+            },
+            FetchStep::Push => self.fifo_push(),
+        }
+    }
+
+    // Reads a byte of the fetched tile, using the tile data area selected at this moment
+    fn fifo_tile_data(&self, offset: u16) -> u8 {
+        let tilenr = self.fifo.tile_number;
+        let tileaddress = self.tilebase
+            + (if self.tilebase == 0x8000 {
+                tilenr as u16
+            } else {
+                (tilenr as i8 as i16 + 128) as u16
+            }) * 16;
+        let row = if self.fifo.tile_attrs & (1 << 6) != 0 { 7 - self.fifo.tile_row } else { self.fifo.tile_row };
+        let address = tileaddress + row * 2 + offset;
+        if self.fifo.tile_attrs & (1 << 3) != 0 { self.rbvram1(address) } else { self.rbvram0(address) }
+    }
+
+    fn fifo_push(&mut self) {
+        let fifo = &mut self.fifo;
+        // The first fetch of a line is thrown away
+        if fifo.first_fetch {
+            fifo.first_fetch = false;
+            fifo.step = FetchStep::Tile;
+            return;
+        }
+        if !fifo.bg.is_empty() { return }
+
+        let xflip = fifo.tile_attrs & (1 << 5) != 0;
+        for i in 0 .. 8 {
+            let bit = if xflip { i } else { 7 - i };
+            let color = ((fifo.data_low >> bit) & 1) | (((fifo.data_high >> bit) & 1) << 1);
+            fifo.bg.push_back(BgPixel {
+                color,
+                palette: fifo.tile_attrs & 0x07,
+                priority: fifo.tile_attrs & (1 << 7) != 0,
+                window: fifo.window,
+            });
+        }
+        fifo.fetch_x = fifo.fetch_x.wrapping_add(1);
+        fifo.step = FetchStep::Tile;
+    }
+
+    fn fifo_merge_object(&mut self, i: usize) {
+        let object = self.fifo.objects[i];
+        let spriteaddr = object.index as usize * 4;
+        let tilenum = (self.voam[spriteaddr + 2] & (if self.sprite_size == 16 { 0xFE } else { 0xFF })) as u16;
+        let flags = self.voam[spriteaddr + 3];
+        let xflip = flags & (1 << 5) != 0;
+        let yflip = flags & (1 << 6) != 0;
+        let is_color = self.gbmode == GbMode::Color;
+
+        let size = self.sprite_size as u16;
+        let row = (self.line as u16).wrapping_sub(object.y as u16).wrapping_add(16) & (size - 1);
+        let row = if yflip { size - 1 - row } else { row };
+        let tileaddress = 0x8000 + tilenum * 16 + row * 2;
+        let (b1, b2) = if is_color && flags & (1 << 3) != 0 {
+            (self.rbvram1(tileaddress), self.rbvram1(tileaddress + 1))
+        } else {
+            (self.rbvram0(tileaddress), self.rbvram0(tileaddress + 1))
+        };
+
+        let fifo = &mut self.fifo;
+        for x in 0 .. 8 {
+            let pos = object.x as i32 - 8 + x - fifo.lx as i32;
+            if pos < 0 { continue }
+            let pos = pos as usize;
+            let bit = if xflip { x } else { 7 - x };
+            let color = ((b1 >> bit) & 1) | (((b2 >> bit) & 1) << 1);
+            while fifo.obj.len() <= pos {
+                fifo.obj.push_back(ObjPixel::default());
+            }
+            // Earlier objects win, except that on CGB the lowest OAM index wins
+            let old = fifo.obj[pos];
+            if color != 0 && (old.color == 0 || (is_color && object.index < old.oam_index)) {
+                fifo.obj[pos] = ObjPixel {
+                    color,
+                    palette: if is_color { flags & 0x07 } else { (flags >> 4) & 1 },
+                    below_bg: flags & (1 << 7) != 0,
+                    oam_index: object.index,
+                };
+            }
+        }
+    }
+
+    fn fifo_output(&mut self, bg: BgPixel, obj: Option<ObjPixel>) {
+        let x = self.fifo.lx as usize;
+        let obj = obj.filter(|o| o.color != 0 && self.sprite_on && self.layer_enabled(Layer::Sprites));
+        // A hidden layer is left blank, with sprites drawn over it
+        let shown = self.layer_enabled(if bg.window { Layer::Window } else { Layer::Background });
+
+        if self.gbmode == GbMode::Color {
+            let bgcolor = if shown { bg.color } else { 0 };
+            let (color, raw) = match obj {
+                Some(o) if self.ignore_sprite_priority || !(self.lcdc0 && bgcolor != 0 && (bg.priority || o.below_bg)) => {
+                    let [r, g, b] = self.csprit[o.palette as usize][o.color as usize];
+                    (self.cgb_color(r, g, b), 32 + o.palette * 4 + o.color)
+                },
+                _ if shown => {
+                    let [r, g, b] = self.cbgpal[bg.palette as usize][bg.color as usize];
+                    let color = self.cgb_color(r, g, b);
+                    (if bg.window { self.window_tint(color) } else { color }, bg.palette * 4 + bg.color)
+                },
+                _ => (self.white(), 0),
+            };
+            self.setcolor(x, color, raw);
+        } else {
+            // Without LCDC bit 0 the background and window are blank
+            let shown = shown && self.lcdc0;
+            let bgcolor = if shown { bg.color } else { 0 };
+            let (color, shade) = match obj {
+                Some(o) if self.ignore_sprite_priority || !(o.below_bg && bgcolor != 0) => {
+                    if o.palette == 1 {
+                        (self.pal1[o.color as usize], (self.pal1r >> (o.color * 2)) & 0x03)
+                    } else {
+                        (self.pal0[o.color as usize], (self.pal0r >> (o.color * 2)) & 0x03)
+                    }
+                },
+                _ if shown => {
+                    let color = self.palb[bgcolor as usize];
+                    (if bg.window { self.window_tint(color) } else { color }, (self.palbr >> (bgcolor * 2)) & 0x03)
+                },
+                _ => (self.white(), 0),
+            };
+            self.setcolor(x, color, shade);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::{GPU, Layer, Renderer, SCREEN_W};
+
+    // A DMG screen with a scrolled background, the window and overlapping objects
+    fn gpu(renderer: Renderer) -> GPU {
+        let mut gpu = GPU::new();
+        gpu.set_renderer(renderer);
+        for tile in 0 .. 4u16 {
+            for row in 0 .. 8u16 {
+                gpu.wb(0x8000 + tile * 16 + row * 2, (0x5A_u16.rotate_left((tile + row) as u32) & 0xFF) as u8);
+                gpu.wb(0x8001 + tile * 16 + row * 2, (0xC3_u16 >> (tile as u32)) as u8 ^ row as u8);
+            }
+        }
+        for i in 0 .. 0x800u16 {
+            gpu.wb(0x9800 + i, ((i * 7 + i / 32) % 4) as u8);
+        }
+        let objects = [(20, 12, 1, 0x00), (24, 16, 2, 0x20), (24, 16, 3, 0x80), (40, 5, 1, 0x50), (100, 170, 2, 0x00), (60, 0, 3, 0x00)];
+        for (i, &(y, x, tile, flags)) in objects.iter().enumerate() {
+            gpu.wb(0xFE00 + i as u16 * 4, y);
+            gpu.wb(0xFE01 + i as u16 * 4, x);
+            gpu.wb(0xFE02 + i as u16 * 4, tile);
+            gpu.wb(0xFE03 + i as u16 * 4, flags);
+        }
+        gpu.wb(0xFF42, 3);
+        gpu.wb(0xFF43, 13);
+        gpu.wb(0xFF47, 0xE4);
+        gpu.wb(0xFF48, 0x1B);
+        gpu.wb(0xFF49, 0x93);
+        gpu.wb(0xFF4A, 90);
+        gpu.wb(0xFF4B, 84);
+        gpu.wb(0xFF40, 0xF3);
+        gpu
+    }
+
+    #[test]
+    fn matches_scanline_renderer() {
+        let mut scanline = gpu(Renderer::Scanline);
+        let mut fifo = gpu(Renderer::PixelFifo);
+        scanline.do_cycle(456 * 154);
+        fifo.do_cycle(456 * 154);
+        assert!(scanline.data == fifo.data);
+        assert!(scanline.raw == fifo.raw);
+    }
+
+    #[test]
+    fn debug_options_match_scanline_renderer() {
+        for layer in [Layer::Background, Layer::Window, Layer::Sprites] {
+            let mut renderers = [gpu(Renderer::Scanline), gpu(Renderer::PixelFifo)];
+            for gpu in renderers.iter_mut() {
+                gpu.set_layer_enabled(layer, false);
+                gpu.set_highlight_window(true);
+                gpu.set_ignore_sprite_priority(true);
+                gpu.do_cycle(456 * 154);
+            }
+            assert!(renderers[0].data == renderers[1].data);
+            assert!(renderers[0].raw == renderers[1].raw);
+        }
+    }
+
+    #[test]
+    fn hidden_layers() {
+        let mut gpu = gpu(Renderer::Scanline);
+        gpu.set_layer_enabled(Layer::Background, false);
+        gpu.set_layer_enabled(Layer::Window, false);
+        gpu.set_layer_enabled(Layer::Sprites, false);
+        gpu.do_cycle(456 * 154);
+        assert!(gpu.data.iter().all(|&c| c == 255));
+    }
+
+    #[test]
+    fn mid_line_palette_change() {
+        let mut gpu = gpu(Renderer::PixelFifo);
+        gpu.wb(0xFF40, 0x00);
+        gpu.wb(0xFF40, 0x81);
+        gpu.wb(0xFF47, 0x00);
+        // Halfway through drawing line 0
+        gpu.do_cycle(80 + 12 + 80);
+        gpu.wb(0xFF47, 0xFF);
+        gpu.do_cycle(456 - 80 - 12 - 80);
+        assert_eq!(gpu.data[0], 255);
+        assert_eq!(gpu.data[(SCREEN_W - 1) * 3], 0);
+    }
+}
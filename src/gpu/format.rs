@@ -0,0 +1,60 @@
+/// Layout of the pixels in the frame buffer. The frame is stored row by row from the top left,
+/// without padding.
+#[derive(Debug, Default, PartialEq, Eq, Copy, Clone)]
+pub enum PixelFormat {
+    /// Three bytes per pixel: red, green, blue
+    #[default]
+    Rgb888,
+    /// Four bytes per pixel: red, green, blue and an opaque alpha
+    Rgba8888,
+    /// Four bytes per pixel: blue, green, red and an opaque alpha
+    Bgra8888,
+    /// A native endian `u32` per pixel holding `0xFFRRGGBB`, as used by libretro
+    Xrgb8888,
+    /// A native endian `u16` per pixel with 5 bits of red, 6 of green and 5 of blue
+    Rgb565,
+}
+
+impl PixelFormat {
+    pub fn bytes_per_pixel(self) -> usize {
+        match self {
+            PixelFormat::Rgb888 => 3,
+            PixelFormat::Rgba8888 | PixelFormat::Bgra8888 | PixelFormat::Xrgb8888 => 4,
+            PixelFormat::Rgb565 => 2,
+        }
+    }
+
+    // Writes an RGB colour at the start of pixel
+    pub(super) fn write(self, pixel: &mut [u8], [r, g, b]: [u8; 3]) {
+        match self {
+            PixelFormat::Rgb888 => pixel[.. 3].copy_from_slice(&[r, g, b]),
+            PixelFormat::Rgba8888 => pixel[.. 4].copy_from_slice(&[r, g, b, 0xFF]),
+            PixelFormat::Bgra8888 => pixel[.. 4].copy_from_slice(&[b, g, r, 0xFF]),
+            PixelFormat::Xrgb8888 => {
+                let value = 0xFF00_0000 | (r as u32) << 16 | (g as u32) << 8 | b as u32;
+                pixel[.. 4].copy_from_slice(&value.to_ne_bytes());
+            },
+            PixelFormat::Rgb565 => {
+                let value = (r as u16 >> 3) << 11 | (g as u16 >> 2) << 5 | b as u16 >> 3;
+                pixel[.. 2].copy_from_slice(&value.to_ne_bytes());
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::PixelFormat;
+
+    #[test]
+    fn formats() {
+        let color = [0xFF, 0x80, 0x10];
+        let mut pixel = [0; 4];
+        PixelFormat::Bgra8888.write(&mut pixel, color);
+        assert_eq!(pixel, [0x10, 0x80, 0xFF, 0xFF]);
+        PixelFormat::Xrgb8888.write(&mut pixel, color);
+        assert_eq!(u32::from_ne_bytes(pixel), 0xFFFF8010);
+        PixelFormat::Rgb565.write(&mut pixel, color);
+        assert_eq!(u16::from_ne_bytes([pixel[0], pixel[1]]), (0b11111 << 11) | (0b100000 << 5) | 0b00010);
+    }
+}
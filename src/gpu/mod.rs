@@ -0,0 +1,1032 @@
+use std::cmp::Ordering;
+use crate::gbmode::GbMode;
+
+mod blend;
+mod colorize;
+mod fifo;
+mod format;
+mod palette;
+mod viewer;
+
+pub use self::blend::FrameBlending;
+pub use self::colorize::ColorizationKeys;
+pub use self::format::PixelFormat;
+pub use self::palette::{ColorCorrection, DmgPalette, Shades};
+pub use self::viewer::{DebugImage, OamEntry};
+pub(crate) use self::colorize::title_colors;
+
+const VRAM_SIZE: usize = 0x4000;
+const VOAM_SIZE: usize = 0xA0;
+pub const SCREEN_W: usize = 160;
+pub const SCREEN_H: usize = 144;
+// Objects the hardware draws on one line
+const MAX_LINE_OBJECTS: usize = 10;
+
+/// How the picture is drawn
+#[derive(Debug, Default, PartialEq, Eq, Copy, Clone)]
+pub enum Renderer {
+    /// Draws every line at once at the start of HBlank. Fast, but changes to the registers while
+    /// a line is drawn are not visible.
+    #[default]
+    Scanline,
+    /// Draws one pixel per dot through the background and object FIFOs like the hardware does,
+    /// so raster effects that change registers in the middle of a line are shown
+    PixelFifo,
+}
+
+/// A layer of the picture that can be hidden, to see what is drawn on each layer
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum Layer {
+    Background,
+    Window,
+    Sprites,
+}
+
+#[derive(PartialEq, Copy, Clone)]
+enum PrioType {
+    Color0,
+    PrioFlag,
+    Normal,
+}
+
+pub struct GPU {
+    mode: u8,
+    modeclock: u32,
+    line: u8,
+    lyc: u8,
+    lcd_on: bool,
+    win_tilemap: u16,
+    win_on: bool,
+    tilebase: u16,
+    bg_tilemap: u16,
+    sprite_size: u32,
+    sprite_on: bool,
+    lcdc0: bool,
+    lyc_inte: bool,
+    m0_inte: bool,
+    m1_inte: bool,
+    m2_inte: bool,
+    scy: u8,
+    scx: u8,
+    winy: u8,
+    winx: u8,
+    wy_trigger: bool,
+    wy_pos: i32,
+    palbr: u8,
+    pal0r: u8,
+    pal1r: u8,
+    palb: [[u8; 3]; 4],
+    pal0: [[u8; 3]; 4],
+    pal1: [[u8; 3]; 4],
+    dmg_palette: DmgPalette,
+    color_table: Vec<[u8; 3]>,
+    compat_colors: colorize::CompatColors,
+    vram: [u8; VRAM_SIZE],
+    voam: [u8; VOAM_SIZE],
+    cbgpal_inc: bool,
+    cbgpal_ind: u8,
+    cbgpal: [[[u8; 3]; 4]; 8],
+    csprit_inc: bool,
+    csprit_ind: u8,
+    csprit: [[[u8; 3]; 4]; 8],
+    vrambank: usize,
+    pub data: Vec<u8>,
+    pub raw: Vec<u8>,
+    pixel_format: PixelFormat,
+    bgprio: [PrioType; SCREEN_W],
+    pub updated: bool,
+    pub interrupt: u8,
+    pub gbmode: GbMode,
+    hblanking: bool,
+    mode3_len: u32,
+    stat_line: bool,
+    lyc_match: bool,
+    lcd_starting: bool,
+    renderer: Renderer,
+    fifo: fifo::PixelFifo,
+    layers: [bool; 3],
+    highlight_window: bool,
+    ignore_sprite_priority: bool,
+    frame_blending: FrameBlending,
+    unlimited_sprites: bool,
+    blend_prev: Vec<[u8; 3]>,
+    blend_next: Vec<[u8; 3]>,
+}
+
+impl GPU {
+    pub fn new() -> GPU {
+        GPU {
+            mode: 0,
+            modeclock: 0,
+            line: 0,
+            lyc: 0,
+            lcd_on: false,
+            win_tilemap: 0x9C00,
+            win_on: false,
+            tilebase: 0x8000,
+            bg_tilemap: 0x9C00,
+            sprite_size: 8,
+            sprite_on: false,
+            lcdc0: false,
+            lyc_inte: false,
+            m2_inte: false,
+            m1_inte: false,
+            m0_inte: false,
+            scy: 0,
+            scx: 0,
+            winy: 0,
+            winx: 0,
+            wy_trigger: false,
+            wy_pos: -1,
+            palbr: 0,
+            pal0r: 0,
+            pal1r: 1,
+            palb: [[0; 3]; 4],
+            pal0: [[0; 3]; 4],
+            pal1: [[0; 3]; 4],
+            dmg_palette: DmgPalette::default(),
+            color_table: ColorCorrection::default().table(),
+            compat_colors: colorize::title_colors(&[0; 0x150]),
+            vram: [0; VRAM_SIZE],
+            voam: [0; VOAM_SIZE],
+            data: vec![0; SCREEN_W * SCREEN_H * 3],
+            raw: vec![0; SCREEN_W * SCREEN_H],
+            pixel_format: PixelFormat::Rgb888,
+            bgprio: [PrioType::Normal; SCREEN_W],
+            updated: false,
+            interrupt: 0,
+            gbmode: GbMode::Classic,
+            cbgpal_inc: false,
+            cbgpal_ind: 0,
+            cbgpal: [[[0u8; 3]; 4]; 8],
+            csprit_inc: false,
+            csprit_ind: 0,
+            csprit: [[[0u8; 3]; 4]; 8],
+            vrambank: 0,
+            hblanking: false,
+            mode3_len: 172,
+            stat_line: false,
+            lyc_match: false,
+            lcd_starting: false,
+            renderer: Renderer::Scanline,
+            fifo: fifo::PixelFifo::new(),
+            layers: [true; 3],
+            highlight_window: false,
+            ignore_sprite_priority: false,
+            frame_blending: FrameBlending::Off,
+            unlimited_sprites: false,
+            blend_prev: vec![[255; 3]; SCREEN_W * SCREEN_H],
+            blend_next: vec![[255; 3]; SCREEN_W * SCREEN_H],
+        }
+    }
+
+    pub fn new_cgb() -> GPU {
+        GPU::new()
+    }
+
+    pub fn set_renderer(&mut self, renderer: Renderer) {
+        self.renderer = renderer;
+    }
+
+    pub fn set_pixel_format(&mut self, format: PixelFormat) {
+        self.pixel_format = format;
+        self.data = vec![0; SCREEN_W * SCREEN_H * format.bytes_per_pixel()];
+        self.clear_screen();
+    }
+
+    pub fn pixel_format(&self) -> PixelFormat {
+        self.pixel_format
+    }
+
+    // Hands out the frame drawn so far, and continues drawing in the given buffer
+    pub fn swap_frame(&mut self, frame: &mut Vec<u8>) {
+        frame.resize(self.data.len(), 0);
+        ::std::mem::swap(&mut self.data, frame);
+    }
+
+    pub fn set_dmg_palette(&mut self, palette: DmgPalette) {
+        self.dmg_palette = palette;
+        self.update_pal();
+    }
+
+    pub fn set_color_correction(&mut self, correction: ColorCorrection) {
+        self.color_table = correction.table();
+        self.update_pal();
+    }
+
+    // Sets the colours used for a DMG game in CGB mode
+    pub fn set_compat_colors(&mut self, colors: colorize::CompatColors) {
+        self.compat_colors = colors;
+        self.update_pal();
+    }
+
+    pub fn set_colorization_keys(&mut self, keys: ColorizationKeys) {
+        self.set_compat_colors(keys.colors());
+    }
+
+    pub fn set_layer_enabled(&mut self, layer: Layer, enabled: bool) {
+        self.layers[layer as usize] = enabled;
+    }
+
+    fn layer_enabled(&self, layer: Layer) -> bool {
+        self.layers[layer as usize]
+    }
+
+    // Tints the pixels of the window, to show where it is drawn
+    pub fn set_highlight_window(&mut self, highlight: bool) {
+        self.highlight_window = highlight;
+    }
+
+    // Draws all sprites on top of the background, including those it would hide
+    pub fn set_ignore_sprite_priority(&mut self, ignore: bool) {
+        self.ignore_sprite_priority = ignore;
+    }
+
+    pub fn set_frame_blending(&mut self, blending: FrameBlending) {
+        self.frame_blending = blending;
+    }
+
+    // Draws all objects on a line instead of the first ten. The timing of the line stays the same.
+    pub fn set_unlimited_sprites(&mut self, unlimited: bool) {
+        self.unlimited_sprites = unlimited;
+    }
+
+    pub fn unlimited_sprites(&self) -> bool {
+        self.unlimited_sprites
+    }
+
+    fn line_object_limit(&self) -> usize {
+        if self.unlimited_sprites { 40 } else { MAX_LINE_OBJECTS }
+    }
+
+    pub fn do_cycle(&mut self, ticks: u32) {
+        if !self.lcd_on { return }
+        self.hblanking = false;
+
+        let mut ticksleft = ticks;
+
+        while ticksleft > 0 {
+            // The pixel FIFO draws mode 3 one dot at a time, and ends it when the line is done
+            if self.mode == 3 && self.renderer == Renderer::PixelFifo {
+                self.modeclock += 1;
+                ticksleft -= 1;
+                if self.fifo_step() {
+                    self.change_mode(0);
+                }
+                continue;
+            }
+
+            // Advance to the next mode change, or the end of the line
+            let next: u32 = match (self.line < 144, self.mode) {
+                (true, 2) => 80 + 1,
+                (true, 3) => 80 + self.mode3_len + 1,
+                _ => 456,
+            };
+            // LY and its comparison with LYC change a few dots into the line
+            let next = match self.modeclock {
+                0 ..= 3 => 4,
+                4 ..= 7 if self.line == 153 => 8,
+                _ => next,
+            };
+            let curticks = ::std::cmp::min(ticksleft, ::std::cmp::max(1, next.saturating_sub(self.modeclock)));
+            self.modeclock += curticks;
+            ticksleft -= curticks;
+
+            // Full line takes 114 ticks
+            if self.modeclock >= 456 {
+                self.modeclock -= 456;
+                self.line = (self.line + 1) % 154;
+
+                // This is a VBlank line
+                if self.line >= 144 && self.mode != 1 {
+                    self.change_mode(1);
+                }
+            }
+
+            // This is a normal line
+            if self.line < 144 {
+                if self.modeclock <= 80 {
+                    if self.mode != 2 { self.change_mode(2); }
+                } else if self.mode == 2 || (self.mode == 3 && self.modeclock <= 80 + self.mode3_len) {
+                    if self.mode != 3 { self.change_mode(3); }
+                } else { // the remaining 204
+                    if self.mode != 0 { self.change_mode(0); }
+                }
+            }
+
+            self.update_stat();
+        }
+    }
+
+    // LY as read by the CPU. Line 153 reads as 0 after its first cycle.
+    fn ly(&self) -> u8 {
+        if self.line == 153 && self.modeclock >= 4 { 0 } else { self.line }
+    }
+
+    // The value compared with LYC, which follows LY one cycle late. Nothing matches while the
+    // comparison is being updated.
+    fn ly_compare(&self) -> Option<u8> {
+        match (self.line, self.modeclock) {
+            (0, _) => Some(0),
+            (_, 0 ..= 3) => None,
+            (153, 4 ..= 7) => Some(153),
+            (153, _) => Some(0),
+            (line, _) => Some(line),
+        }
+    }
+
+    // All STAT interrupt sources are ORed into a single line, and the interrupt is only requested
+    // when that line goes from low to high. A source that becomes active while another one is
+    // still active does not request it again.
+    fn update_stat(&mut self) {
+        self.lyc_match = self.lcd_on && self.ly_compare() == Some(self.lyc);
+
+        let mode2 = (self.mode == 2 && !self.lcd_starting)
+            || (self.mode == 1 && self.line == 144 && self.modeclock < 4);
+        let stat_line = self.lcd_on && (
+            (self.lyc_inte && self.lyc_match) ||
+            (self.m0_inte && self.mode == 0) ||
+            (self.m1_inte && self.mode == 1) ||
+            (self.m2_inte && mode2)
+        );
+
+        if stat_line && !self.stat_line {
+            self.interrupt |= 0x02;
+        }
+        self.stat_line = stat_line;
+    }
+
+    fn change_mode(&mut self, mode: u8) {
+        self.mode = mode;
+
+        match self.mode {
+            0 => {
+                if self.renderer == Renderer::Scanline {
+                    self.renderscan();
+                }
+                self.hblanking = true;
+            },
+            1 => { // Vertical blank
+                self.wy_trigger = false;
+                self.interrupt |= 0x01;
+                self.finish_blended_frame();
+                self.updated = true;
+            },
+            3 => {
+                self.lcd_starting = false;
+                if self.win_on && self.wy_trigger == false && self.line == self.winy {
+                    self.wy_trigger = true;
+                    self.wy_pos = -1;
+                }
+                match self.renderer {
+                    Renderer::Scanline => self.mode3_len = self.mode3_length(),
+                    Renderer::PixelFifo => self.fifo_start_line(),
+                }
+            }
+            _ => {},
+        }
+
+        self.update_stat();
+    }
+
+    pub fn rb(&self, a: u16) -> u8 {
+        match a {
+            0x8000 ..= 0x9FFF => self.vram[(self.vrambank * 0x2000) | (a as usize & 0x1FFF)],
+            0xFE00 ..= 0xFE9F => self.voam[a as usize - 0xFE00],
+            0xFF40 => {
+                (if self.lcd_on { 0x80 } else { 0 }) |
+                (if self.win_tilemap == 0x9C00 { 0x40 } else { 0 }) |
+                (if self.win_on { 0x20 } else { 0 }) |
+                (if self.tilebase == 0x8000 { 0x10 } else { 0 }) |
+                (if self.bg_tilemap == 0x9C00 { 0x08 } else { 0 }) |
+                (if self.sprite_size == 16 { 0x04 } else { 0 }) |
+                (if self.sprite_on { 0x02 } else { 0 }) |
+                (if self.lcdc0 { 0x01 } else { 0 })
+            },
+            0xFF41 => {
+                0x80 |
+                (if self.lyc_inte { 0x40 } else { 0 }) |
+                (if self.m2_inte { 0x20 } else { 0 }) |
+                (if self.m1_inte { 0x10 } else { 0 }) |
+                (if self.m0_inte { 0x08 } else { 0 }) |
+                (if self.lyc_match { 0x04 } else { 0 }) |
+                (if self.lcd_starting { 0 } else { self.mode })
+            },
+            0xFF42 => self.scy,
+            0xFF43 => self.scx,
+            0xFF44 => self.ly(),
+            0xFF45 => self.lyc,
+            0xFF46 => 0, // Write only
+            0xFF47 => self.palbr,
+            0xFF48 => self.pal0r,
+            0xFF49 => self.pal1r,
+            0xFF4A => self.winy,
+            0xFF4B => self.winx,
+            0xFF4C => 0xFF,
+            0xFF4E => 0xFF,
+            0xFF4F ..= 0xFF6B if self.gbmode != GbMode::Color => { 0xFF },
+            0xFF4F => self.vrambank as u8 | 0xFE,
+            0xFF68 => { 0x40 | self.cbgpal_ind | (if self.cbgpal_inc { 0x80 } else { 0 }) },
+            0xFF69 => {
+                let palnum = (self.cbgpal_ind >> 3) as usize;
+                let colnum = ((self.cbgpal_ind >> 1) & 0x3) as usize;
+                if self.cbgpal_ind & 0x01 == 0x00 {
+                    self.cbgpal[palnum][colnum][0] | ((self.cbgpal[palnum][colnum][1] & 0x07) << 5)
+                } else {
+                    ((self.cbgpal[palnum][colnum][1] & 0x18) >> 3) | (self.cbgpal[palnum][colnum][2] << 2)
+                }
+            },
+            0xFF6A => { 0x40 | self.csprit_ind | (if self.csprit_inc { 0x80 } else { 0 }) },
+            0xFF6B => {
+                let palnum = (self.csprit_ind >> 3) as usize;
+                let colnum = ((self.csprit_ind >> 1) & 0x3) as usize;
+                if self.csprit_ind & 0x01 == 0x00 {
+                    self.csprit[palnum][colnum][0] | ((self.csprit[palnum][colnum][1] & 0x07) << 5)
+                } else {
+                    ((self.csprit[palnum][colnum][1] & 0x18) >> 3) | (self.csprit[palnum][colnum][2] << 2)
+                }
+            },
+            _ => 0xFF,
+        }
+    }
+
+    fn rbvram0(&self, a: u16) -> u8 {
+        if a < 0x8000 || a >= 0xA000 { panic!("Shouldn't have used rbvram0"); }
+        self.vram[a as usize & 0x1FFF]
+    }
+    fn rbvram1(&self, a: u16) -> u8 {
+        if a < 0x8000 || a >= 0xA000 { panic!("Shouldn't have used rbvram1"); }
+        self.vram[0x2000 + (a as usize & 0x1FFF)]
+    }
+
+    pub fn wb(&mut self, a: u16, v: u8) {
+        match a {
+            0x8000 ..= 0x9FFF => self.vram[(self.vrambank * 0x2000) | (a as usize & 0x1FFF)] = v,
+            0xFE00 ..= 0xFE9F => self.voam[a as usize - 0xFE00] = v,
+            0xFF40 => {
+                let orig_lcd_on = self.lcd_on;
+                self.lcd_on = v & 0x80 == 0x80;
+                self.win_tilemap = if v & 0x40 == 0x40 { 0x9C00 } else { 0x9800 };
+                self.win_on = v & 0x20 == 0x20;
+                self.tilebase = if v & 0x10 == 0x10 { 0x8000 } else { 0x8800 };
+                self.bg_tilemap = if v & 0x08 == 0x08 { 0x9C00 } else { 0x9800 };
+                self.sprite_size = if v & 0x04 == 0x04 { 16 } else { 8 };
+                self.sprite_on = v & 0x02 == 0x02;
+                self.lcdc0 = v & 0x01 == 0x01;
+                if orig_lcd_on && !self.lcd_on {
+                    self.modeclock = 0;
+                    self.line = 0;
+                    self.mode = 0;
+                    self.wy_trigger = false;
+                    self.clear_screen();
+                    self.update_stat();
+                }
+                // The first line after the LCD is turned on is a little shorter, and reports
+                // mode 0 instead of doing an OAM scan
+                if !orig_lcd_on && self.lcd_on {
+                    self.lcd_starting = true;
+                    self.modeclock = 4;
+                    self.change_mode(2);
+                }
+            },
+            0xFF41 => {
+                self.lyc_inte = v & 0x40 == 0x40;
+                self.m2_inte = v & 0x20 == 0x20;
+                self.m1_inte = v & 0x10 == 0x10;
+                self.m0_inte = v & 0x08 == 0x08;
+                self.update_stat();
+            },
+            0xFF42 => self.scy = v,
+            0xFF43 => self.scx = v,
+            0xFF44 => {}, // Read-only
+            0xFF45 => {
+                self.lyc = v;
+                self.update_stat();
+            },
+            0xFF46 => panic!("0xFF46 should be handled by MMU"),
+            0xFF47 => { self.palbr = v; self.update_pal(); },
+            0xFF48 => { self.pal0r = v; self.update_pal(); },
+            0xFF49 => { self.pal1r = v; self.update_pal(); },
+            0xFF4A => self.winy = v,
+            0xFF4B => self.winx = v,
+            0xFF4C => {},
+            0xFF4E => {},
+            0xFF4F ..= 0xFF6B if self.gbmode != GbMode::Color => {},
+            0xFF4F => self.vrambank = (v & 0x01) as usize,
+            0xFF68 => { self.cbgpal_ind = v & 0x3F; self.cbgpal_inc = v & 0x80 == 0x80; },
+            0xFF69 => {
+                let palnum = (self.cbgpal_ind >> 3) as usize;
+                let colnum = ((self.cbgpal_ind >> 1) & 0x03) as usize;
+                if self.cbgpal_ind & 0x01 == 0x00 {
+                    self.cbgpal[palnum][colnum][0] = v & 0x1F;
+                    self.cbgpal[palnum][colnum][1] = (self.cbgpal[palnum][colnum][1] & 0x18) | (v >> 5);
+                } else {
+                    self.cbgpal[palnum][colnum][1] = (self.cbgpal[palnum][colnum][1] & 0x07) | ((v & 0x3) << 3);
+                    self.cbgpal[palnum][colnum][2] = (v >> 2) & 0x1F;
+                }
+                if self.cbgpal_inc { self.cbgpal_ind = (self.cbgpal_ind + 1) & 0x3F; };
+            },
+            0xFF6A => { self.csprit_ind = v & 0x3F; self.csprit_inc = v & 0x80 == 0x80; },
+            0xFF6B => {
+                let palnum = (self.csprit_ind >> 3) as usize;
+                let colnum = ((self.csprit_ind >> 1) & 0x03) as usize;
+                if self.csprit_ind & 0x01 == 0x00 {
+                    self.csprit[palnum][colnum][0] = v & 0x1F;
+                    self.csprit[palnum][colnum][1] = (self.csprit[palnum][colnum][1] & 0x18) | (v >> 5);
+                } else {
+                    self.csprit[palnum][colnum][1] = (self.csprit[palnum][colnum][1] & 0x07) | ((v & 0x3) << 3);
+                    self.csprit[palnum][colnum][2] = (v >> 2) & 0x1F;
+                }
+                if self.csprit_inc { self.csprit_ind = (self.csprit_ind + 1) & 0x3F; };
+            },
+            _ => panic!("GPU does not handle write {:04X}", a),
+        }
+    }
+
+    fn clear_screen(&mut self) {
+        let white = self.white();
+        let format = self.pixel_format;
+        for pixel in self.data.chunks_exact_mut(format.bytes_per_pixel()) {
+            format.write(pixel, white);
+        }
+        for v in self.raw.iter_mut() {
+            *v = 0;
+        }
+        self.updated = true;
+    }
+
+    fn update_pal(&mut self) {
+        for i in 0 .. 4 {
+            if self.gbmode == GbMode::ColorAsClassic {
+                self.palb[i] = self.get_compat_pal_val(0, self.palbr, i);
+                self.pal0[i] = self.get_compat_pal_val(1, self.pal0r, i);
+                self.pal1[i] = self.get_compat_pal_val(2, self.pal1r, i);
+            } else {
+                self.palb[i] = GPU::get_monochrome_pal_val(&self.dmg_palette.bg, self.palbr, i);
+                self.pal0[i] = GPU::get_monochrome_pal_val(&self.dmg_palette.obj0, self.pal0r, i);
+                self.pal1[i] = GPU::get_monochrome_pal_val(&self.dmg_palette.obj1, self.pal1r, i);
+            }
+        }
+    }
+
+    fn get_compat_pal_val(&self, palette: usize, value: u8, index: usize) -> [u8; 3] {
+        let color = self.compat_colors[palette][((value >> (2 * index)) & 0x03) as usize];
+        self.color_table[color as usize]
+    }
+
+    fn get_monochrome_pal_val(shades: &Shades, value: u8, index: usize) -> [u8; 3] {
+        shades.0[((value >> 2*index) & 0x03) as usize]
+    }
+
+    // Colour of a blank screen
+    fn white(&self) -> [u8; 3] {
+        match self.gbmode {
+            GbMode::Color => [255; 3],
+            GbMode::ColorAsClassic => self.color_table[self.compat_colors[0][0] as usize],
+            GbMode::Classic => self.dmg_palette.bg.0[0],
+        }
+    }
+
+    // Length of mode 3 for the current line, in dots. The fetcher pauses while it drops the
+    // pixels scrolled off by SCX, restarts when the window begins and stops to fetch every
+    // object, waiting first for the background tile under the object to be fetched.
+    fn mode3_length(&self) -> u32 {
+        let mut length = 172 + (self.scx & 0x07) as u32;
+
+        let winx = self.winx as i32 - 7;
+        let window = self.win_on && self.wy_trigger && self.winx <= 166;
+        if window {
+            length += 6;
+        }
+
+        if !self.sprite_on { return length }
+
+        let line = self.line as i32;
+        let sprite_size = self.sprite_size as i32;
+        let mut objects = Vec::with_capacity(MAX_LINE_OBJECTS);
+        for index in 0 .. 40 {
+            let spritey = self.voam[index * 4] as i32 - 16;
+            if line < spritey || line >= spritey + sprite_size { continue }
+            objects.push(self.voam[index * 4 + 1] as i32);
+            if objects.len() >= MAX_LINE_OBJECTS {
+                break;
+            }
+        }
+        objects.sort_unstable();
+
+        let mut fetched_tiles = Vec::with_capacity(10);
+        for x in objects {
+            if x >= 168 { continue }
+            if x == 0 {
+                length += 11;
+                continue;
+            }
+
+            // The tile under the leftmost pixel of the object
+            let screenx = x - 8;
+            let (tile, offset) = if window && screenx >= winx {
+                ((true, (screenx - winx) >> 3), (screenx - winx) & 0x07)
+            } else {
+                let bgx = screenx + self.scx as i32;
+                ((false, bgx.div_euclid(8)), bgx.rem_euclid(8))
+            };
+            length += 6;
+            if !fetched_tiles.contains(&tile) {
+                fetched_tiles.push(tile);
+                length += ::std::cmp::max(0, 5 - offset) as u32;
+            }
+        }
+
+        length
+    }
+
+    fn renderscan(&mut self) {
+        let white = self.white();
+        for x in 0 .. SCREEN_W {
+            self.setcolor(x, white, 0);
+            self.bgprio[x] = PrioType::Normal;
+        }
+        self.draw_bg();
+        self.draw_sprites();
+    }
+
+    // Sets a pixel of the current line. The raw value is the shade on DMG, or the palette
+    // index on CGB: 0 to 31 for the background palettes and 32 to 63 for the object palettes.
+    fn setcolor(&mut self, x: usize, color: [u8; 3], raw: u8) {
+        let index = self.line as usize * SCREEN_W + x;
+        let color = if self.frame_blending == FrameBlending::Off { color } else { self.blend(index, color) };
+        let bytes = self.pixel_format.bytes_per_pixel();
+        self.pixel_format.write(&mut self.data[index * bytes ..], color);
+        self.raw[index] = raw;
+    }
+
+    fn setrgb(&mut self, x: usize, r: u8, g: u8, b: u8, raw: u8) {
+        let color = self.cgb_color(r, g, b);
+        self.setcolor(x, color, raw);
+    }
+
+    fn cgb_color(&self, r: u8, g: u8, b: u8) -> [u8; 3] {
+        // Assume r, g and b are between 0 and 1F
+        self.color_table[r as usize | (g as usize) << 5 | (b as usize) << 10]
+    }
+
+    // Mixes window pixels with magenta when the window is highlighted
+    fn window_tint(&self, color: [u8; 3]) -> [u8; 3] {
+        if !self.highlight_window { return color }
+        [((color[0] as u16 + 255) / 2) as u8, color[1] / 2, ((color[2] as u16 + 255) / 2) as u8]
+    }
+
+    fn draw_bg(&mut self) {
+        let drawbg = self.gbmode == GbMode::Color || self.lcdc0;
+
+        let wx_trigger = self.winx <= 166;
+        let winy = if self.win_on && self.wy_trigger && wx_trigger {
+            self.wy_pos += 1;
+            self.wy_pos
+        }
+        else {
+            -1
+        };
+
+        if winy < 0 && drawbg == false {
+            return;
+        }
+
+        let wintiley = (winy as u16 >> 3) & 31;
+
+        let bgy = self.scy.wrapping_add(self.line);
+        let bgtiley = (bgy as u16 >> 3) & 31;
+
+        for x in 0 .. SCREEN_W {
+            let winx = - ((self.winx as i32) - 7) + (x as i32);
+            let bgx = self.scx as u32 + x as u32;
+
+            let window = winy >= 0 && winx >= 0;
+            let (tilemapbase, tiley, tilex, pixely, pixelx) = if window {
+                (self.win_tilemap,
+                wintiley,
+                (winx as u16 >> 3),
+                winy as u16 & 0x07,
+                winx as u8 & 0x07)
+            } else if drawbg {
+                (self.bg_tilemap,
+                bgtiley,
+                (bgx as u16 >> 3) & 31,
+                bgy as u16 & 0x07,
+                bgx as u8 & 0x07)
+            } else {
+                continue;
+            };
+
+            // A hidden layer is left blank, with sprites drawn over it
+            if !self.layer_enabled(if window { Layer::Window } else { Layer::Background }) {
+                self.bgprio[x] = PrioType::Color0;
+                continue;
+            }
+
+            let tilenr: u8 = self.rbvram0(tilemapbase + tiley * 32 + tilex);
+
+            let (palnr, vram1, xflip, yflip, prio) = if self.gbmode == GbMode::Color {
+                let flags = self.rbvram1(tilemapbase + tiley * 32 + tilex) as usize;
+                (flags & 0x07,
+                flags & (1 << 3) != 0,
+                flags & (1 << 5) != 0,
+                flags & (1 << 6) != 0,
+                flags & (1 << 7) != 0)
+            } else {
+                (0, false, false, false, false)
+            };
+
+            let tileaddress = self.tilebase
+            + (if self.tilebase == 0x8000 {
+                tilenr as u16
+            } else {
+                (tilenr as i8 as i16 + 128) as u16
+            }) * 16;
+
+            let a0 = match yflip {
+                false => tileaddress + (pixely * 2),
+                true => tileaddress + (14 - (pixely * 2)),
+            };
+
+            let (b1, b2) = match vram1 {
+                false => (self.rbvram0(a0), self.rbvram0(a0 + 1)),
+                true => (self.rbvram1(a0), self.rbvram1(a0 + 1)),
+            };
+
+            let xbit = match xflip {
+                true => pixelx,
+                false => 7 - pixelx,
+            } as u32;
+            let colnr = if b1 & (1 << xbit) != 0 { 1 } else { 0 }
+                | if b2 & (1 << xbit) != 0 { 2 } else { 0 };
+
+            self.bgprio[x] =
+                if colnr == 0 { PrioType::Color0 }
+                else if prio { PrioType::PrioFlag }
+                else { PrioType::Normal };
+            let (color, raw) = if self.gbmode == GbMode::Color {
+                let [r, g, b] = self.cbgpal[palnr][colnr];
+                (self.cgb_color(r, g, b), (palnr * 4 + colnr) as u8)
+            } else {
+                (self.palb[colnr], (self.palbr >> (colnr * 2)) & 0x03)
+            };
+            let color = if window { self.window_tint(color) } else { color };
+            self.setcolor(x, color, raw);
+        }
+    }
+
+    fn draw_sprites(&mut self) {
+        if !self.sprite_on || !self.layer_enabled(Layer::Sprites) { return }
+
+        let line = self.line as i32;
+        let sprite_size = self.sprite_size as i32;
+
+        let limit = self.line_object_limit();
+        let mut sprites_to_draw = [(0, 0, 0); 40];
+        let mut sidx = 0;
+        for index in 0 .. 40 {
+            let spriteaddr = 0xFE00 + (index as u16) * 4;
+            let spritey = self.rb(spriteaddr + 0) as u16 as i32 - 16;
+            if line < spritey || line >= spritey + sprite_size { continue }
+            let spritex = self.rb(spriteaddr + 1) as u16 as i32 - 8;
+            sprites_to_draw[sidx] = (spritex, spritey, index);
+            sidx += 1;
+            if sidx >= limit {
+                break;
+            }
+        }
+        if self.gbmode == GbMode::Color {
+            sprites_to_draw[..sidx].sort_unstable_by(cgb_sprite_order);
+        }
+        else {
+            sprites_to_draw[..sidx].sort_unstable_by(dmg_sprite_order);
+        }
+
+        for &(spritex, spritey, i) in &sprites_to_draw[..sidx] {
+            if spritex < -7 || spritex >= (SCREEN_W as i32) { continue }
+
+            let spriteaddr = 0xFE00 + (i as u16) * 4;
+            let tilenum = (self.rb(spriteaddr + 2) & (if self.sprite_size == 16 { 0xFE } else { 0xFF })) as u16;
+            let flags = self.rb(spriteaddr + 3) as usize;
+            let usepal1: bool = flags & (1 << 4) != 0;
+            let xflip: bool = flags & (1 << 5) != 0;
+            let yflip: bool = flags & (1 << 6) != 0;
+            let belowbg: bool = flags & (1 << 7) != 0;
+            let c_palnr = flags & 0x07;
+            let c_vram1: bool = flags & (1 << 3) != 0;
+
+            let tiley: u16 = if yflip {
+                (sprite_size - 1 - (line - spritey)) as u16
+            } else {
+                (line - spritey) as u16
+            };
+
+            let tileaddress = 0x8000u16 + tilenum * 16 + tiley * 2;
+            let (b1, b2) = if c_vram1 && self.gbmode == GbMode::Color {
+                (self.rbvram1(tileaddress), self.rbvram1(tileaddress + 1))
+            } else {
+                (self.rbvram0(tileaddress), self.rbvram0(tileaddress + 1))
+            };
+
+            'xloop: for x in 0 .. 8 {
+                if spritex + x < 0 || spritex + x >= (SCREEN_W as i32) { continue }
+
+                let xbit = 1 << (if xflip { x } else { 7 - x } as u32);
+                let colnr = (if b1 & xbit != 0 { 1 } else { 0 }) |
+                    (if b2 & xbit != 0 { 2 } else { 0 });
+                if colnr == 0 { continue }
+
+                if self.gbmode == GbMode::Color {
+                    if !self.ignore_sprite_priority && self.lcdc0 && (self.bgprio[(spritex + x) as usize] == PrioType::PrioFlag || (belowbg && self.bgprio[(spritex + x) as usize] != PrioType::Color0)) {
+                        continue 'xloop
+                    }
+                    let r = self.csprit[c_palnr][colnr][0];
+                    let g = self.csprit[c_palnr][colnr][1];
+                    let b = self.csprit[c_palnr][colnr][2];
+                    self.setrgb((spritex + x) as usize, r, g, b, (32 + c_palnr * 4 + colnr) as u8);
+                } else {
+                    if !self.ignore_sprite_priority && belowbg && self.bgprio[(spritex + x) as usize] != PrioType::Color0 { continue 'xloop }
+                    let color = if usepal1 { self.pal1[colnr] } else { self.pal0[colnr] };
+                    let shade = (if usepal1 { self.pal1r } else { self.pal0r } >> (colnr * 2)) & 0x03;
+                    self.setcolor((spritex + x) as usize, color, shade);
+                }
+            }
+        }
+    }
+
+    pub fn may_hdma(&self) -> bool {
+        return self.hblanking;
+    }
+}
+
+// Functions to determine the order of sprites. Input is a tuple x-coord, OAM position
+// These function ensures that sprites with a higher priority are 'larger'
+fn dmg_sprite_order(a: &(i32, i32, u8), b: &(i32, i32, u8)) -> Ordering {
+    // DMG order: prioritize on x-coord, and then by OAM position.
+    if a.0 != b.0 {
+        return b.0.cmp(&a.0);
+    }
+    return b.2.cmp(&a.2);
+}
+
+fn cgb_sprite_order(a: &(i32, i32, u8), b: &(i32, i32, u8)) -> Ordering {
+    // CGB order: only prioritize based on OAM position.
+    return b.2.cmp(&a.2);
+}
+
+#[cfg(test)]
+mod test {
+    use super::{GPU, PixelFormat, Renderer, SCREEN_H, SCREEN_W};
+
+    // Length of mode 3 on the first line, in dots
+    fn line_mode3_length(renderer: Renderer, lcdc: u8, setup: fn(&mut GPU)) -> u32 {
+        let mut gpu = GPU::new();
+        gpu.set_renderer(renderer);
+        setup(&mut gpu);
+        gpu.wb(0xFF40, lcdc);
+        while gpu.mode != 3 {
+            gpu.do_cycle(1);
+        }
+        let mut dots = 0;
+        while gpu.mode == 3 {
+            gpu.do_cycle(1);
+            dots += 1;
+        }
+        dots
+    }
+
+    #[test]
+    fn mode3_length() {
+        assert_eq!(line_mode3_length(Renderer::Scanline, 0x83, |_| {}), 172);
+        assert_eq!(line_mode3_length(Renderer::Scanline, 0x83, |gpu| gpu.wb(0xFF43, 0x03)), 175);
+
+        // Two objects on the same background tile, the first one waiting for the tile
+        let objects = |gpu: &mut GPU| {
+            gpu.wb(0xFE00, 16);
+            gpu.wb(0xFE01, 8);
+            gpu.wb(0xFE04, 16);
+            gpu.wb(0xFE05, 10);
+        };
+        assert_eq!(line_mode3_length(Renderer::Scanline, 0x83, objects), 172 + 11 + 6);
+
+        // The scanline and FIFO renderers agree without objects
+        let window = |gpu: &mut GPU| {
+            gpu.wb(0xFF43, 0x06);
+            gpu.wb(0xFF4B, 0x30);
+            gpu.wb(0xFF4A, 0x00);
+        };
+        assert_eq!(line_mode3_length(Renderer::Scanline, 0xA3, window), 172 + 6 + 6);
+        assert_eq!(line_mode3_length(Renderer::PixelFifo, 0xA3, window), 172 + 6 + 6);
+    }
+
+    // Number of STAT interrupts requested during one frame
+    fn stat_interrupts(stat: u8, lyc: u8) -> u32 {
+        let mut gpu = GPU::new();
+        gpu.wb(0xFF40, 0x80);
+        gpu.wb(0xFF41, stat);
+        gpu.wb(0xFF45, lyc);
+        gpu.interrupt = 0;
+        let mut count = 0;
+        for _ in 0 .. 154 * 456 {
+            gpu.do_cycle(1);
+            if gpu.interrupt & 0x02 != 0 {
+                count += 1;
+            }
+            gpu.interrupt = 0;
+        }
+        count
+    }
+
+    #[test]
+    fn stat_irq_blocking() {
+        assert_eq!(stat_interrupts(0x08, 0xFF), 144);
+        // VBlank starts while the HBlank source of line 143 is still active
+        assert_eq!(stat_interrupts(0x18, 0xFF), 144);
+        // The LYC match on line 10 starts during the HBlank of line 9
+        assert_eq!(stat_interrupts(0x48, 10), 144);
+        assert_eq!(stat_interrupts(0x40, 10), 1);
+    }
+
+    #[test]
+    fn line_153() {
+        let mut gpu = GPU::new();
+        gpu.wb(0xFF40, 0x80);
+        gpu.wb(0xFF45, 153);
+        gpu.do_cycle(153 * 456 - 4);
+        assert_eq!(gpu.rb(0xFF44), 153);
+        gpu.do_cycle(2);
+        assert_eq!(gpu.rb(0xFF44), 153);
+        assert_eq!(gpu.rb(0xFF41) & 0x04, 0);
+        gpu.do_cycle(4);
+        assert_eq!(gpu.rb(0xFF44), 0);
+        assert_eq!(gpu.rb(0xFF41) & 0x04, 0x04);
+        gpu.do_cycle(4);
+        assert_eq!(gpu.rb(0xFF41) & 0x04, 0);
+        gpu.wb(0xFF45, 0);
+        assert_eq!(gpu.rb(0xFF41) & 0x04, 0x04);
+    }
+
+    #[test]
+    fn first_line_after_lcd_on() {
+        let mut gpu = GPU::new();
+        gpu.wb(0xFF40, 0x80);
+        assert_eq!(gpu.rb(0xFF41) & 0x03, 0);
+        gpu.do_cycle(77);
+        assert_eq!(gpu.rb(0xFF41) & 0x03, 3);
+        gpu.do_cycle(456 - 77 - 4);
+        assert_eq!(gpu.rb(0xFF44), 1);
+        assert_eq!(gpu.rb(0xFF41) & 0x03, 2);
+    }
+
+    #[test]
+    fn unlimited_sprites() {
+        // Twelve objects side by side on the first line, using a tile with a black first row
+        fn setup(gpu: &mut GPU) {
+            gpu.wb(0x8010, 0xFF);
+            gpu.wb(0x8011, 0xFF);
+            for i in 0 .. 12u16 {
+                gpu.wb(0xFE00 + i * 4, 16);
+                gpu.wb(0xFE01 + i * 4, 8 + i as u8 * 8);
+                gpu.wb(0xFE02 + i * 4, 1);
+            }
+            gpu.wb(0xFF47, 0xE4);
+            gpu.wb(0xFF48, 0xE4);
+        }
+        for renderer in [Renderer::Scanline, Renderer::PixelFifo] {
+            let mut limited = GPU::new();
+            limited.set_renderer(renderer);
+            setup(&mut limited);
+            let mut unlimited = GPU::new();
+            unlimited.set_renderer(renderer);
+            unlimited.set_unlimited_sprites(true);
+            setup(&mut unlimited);
+            for gpu in [&mut limited, &mut unlimited] {
+                gpu.wb(0xFF40, 0x83);
+                gpu.do_cycle(456);
+            }
+            assert_eq!(limited.raw[79], 3);
+            assert_eq!(limited.raw[80], 0);
+            assert!(unlimited.raw[.. 96].iter().all(|&shade| shade == 3));
+        }
+        // The extra objects take no time
+        assert_eq!(line_mode3_length(Renderer::PixelFifo, 0x83, |gpu| { setup(gpu); gpu.set_unlimited_sprites(true); }),
+            line_mode3_length(Renderer::PixelFifo, 0x83, setup));
+    }
+
+    #[test]
+    fn swap_frame() {
+        let mut gpu = GPU::new();
+        gpu.set_pixel_format(PixelFormat::Rgba8888);
+        gpu.wb(0xFF47, 0x1B);
+        gpu.wb(0xFF40, 0x91);
+        gpu.do_cycle(456 * 154);
+
+        let mut frame = Vec::new();
+        gpu.swap_frame(&mut frame);
+        assert_eq!(frame.len(), SCREEN_W * SCREEN_H * 4);
+        assert_eq!(&frame[0 .. 4], &[0, 0, 0, 255]);
+        assert_eq!(gpu.raw[0], 3);
+        assert_eq!(gpu.data.len(), frame.len());
+    }
+}
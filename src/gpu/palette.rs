@@ -0,0 +1,169 @@
+use std::str::FromStr;
+use crate::StrResult;
+
+/// The four shades of a DMG palette as RGB, from colour 0 (the lightest) to colour 3
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct Shades(pub [[u8; 3]; 4]);
+
+impl Shades {
+    /// Plain greys
+    pub const GREY: Shades = Shades([[255, 255, 255], [192, 192, 192], [96, 96, 96], [0, 0, 0]]);
+    /// The green screen of the original Game Boy
+    pub const GREEN: Shades = Shades([[0x9B, 0xBC, 0x0F], [0x8B, 0xAC, 0x0F], [0x30, 0x62, 0x30], [0x0F, 0x38, 0x0F]]);
+    /// The greyish screen of the Game Boy Pocket
+    pub const POCKET: Shades = Shades([[0xC4, 0xCF, 0xA1], [0x8B, 0x95, 0x6D], [0x4D, 0x53, 0x3C], [0x1F, 0x1F, 0x1F]]);
+    /// The blue-green backlight of the Game Boy Light
+    pub const LIGHT: Shades = Shades([[0x00, 0xB5, 0x81], [0x00, 0x9A, 0x71], [0x00, 0x69, 0x4A], [0x00, 0x4F, 0x3B]]);
+    /// Greys spread further apart than the default ones
+    pub const HIGH_CONTRAST: Shades = Shades([[255, 255, 255], [160, 160, 160], [64, 64, 64], [0, 0, 0]]);
+    /// Shades that differ in both brightness and hue, and stay distinct with red-green colour
+    /// blindness
+    pub const COLORBLIND: Shades = Shades([[0xFF, 0xFF, 0xFF], [0xE6, 0x9F, 0x00], [0x00, 0x72, 0xB2], [0x00, 0x00, 0x00]]);
+}
+
+impl FromStr for Shades {
+    type Err = &'static str;
+
+    /// Accepts the name of a preset, or four hex colours separated by commas such as
+    /// `e0f8d0,88c070,346856,081820`
+    fn from_str(s: &str) -> StrResult<Shades> {
+        match &*s.to_lowercase() {
+            "grey" | "gray" => return Ok(Shades::GREY),
+            "green" | "dmg" => return Ok(Shades::GREEN),
+            "pocket" => return Ok(Shades::POCKET),
+            "light" => return Ok(Shades::LIGHT),
+            "high-contrast" => return Ok(Shades::HIGH_CONTRAST),
+            "colorblind" => return Ok(Shades::COLORBLIND),
+            _ => {},
+        }
+
+        let colors: Vec<&str> = s.split(',').map(|c| c.trim().trim_start_matches('#')).collect();
+        if colors.len() != 4 {
+            return Err("Palette must be a preset or four hex colours");
+        }
+        let mut shades = [[0; 3]; 4];
+        for (shade, color) in shades.iter_mut().zip(colors) {
+            if color.len() != 6 {
+                return Err("Palette colours must have six hex digits");
+            }
+            let value = u32::from_str_radix(color, 16).map_err(|_| "Palette colours must have six hex digits")?;
+            *shade = [(value >> 16) as u8, (value >> 8) as u8, value as u8];
+        }
+        Ok(Shades(shades))
+    }
+}
+
+/// The shades used in DMG mode for the background and window, and for objects using OBP0 or OBP1
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct DmgPalette {
+    pub bg: Shades,
+    pub obj0: Shades,
+    pub obj1: Shades,
+}
+
+impl DmgPalette {
+    /// Uses the same shades for the background and both object palettes
+    pub fn uniform(shades: Shades) -> DmgPalette {
+        DmgPalette { bg: shades, obj0: shades, obj1: shades }
+    }
+}
+
+impl Default for DmgPalette {
+    fn default() -> DmgPalette {
+        DmgPalette::uniform(Shades::GREY)
+    }
+}
+
+/// How the 15-bit colours of CGB palettes are turned into the colours on screen
+#[derive(Debug, Default, PartialEq, Eq, Copy, Clone)]
+pub enum ColorCorrection {
+    /// Scales each channel from 5 to 8 bits, keeping the exact palette values
+    Raw,
+    /// The colour matrix used by Gambatte
+    #[default]
+    Gambatte,
+    /// The brightness curve of the CGB screen, with the green channel mixed with blue in linear
+    /// light as on the real LCD
+    Accurate,
+    /// The darker curve of a GBA screen, as seen when playing CGB games on a GBA
+    Gba,
+}
+
+// Brightness of each 5-bit level on the CGB and GBA screens
+const CGB_CURVE: [u8; 32] = [0, 6, 12, 20, 28, 36, 45, 56, 66, 76, 88, 100, 113, 125, 137, 149, 161, 172, 182, 192, 202, 210, 218, 225, 232, 238, 243, 247, 250, 252, 254, 255];
+const GBA_CURVE: [u8; 32] = [0, 3, 8, 14, 20, 26, 33, 40, 47, 54, 62, 70, 78, 86, 94, 103, 112, 120, 129, 138, 147, 157, 166, 176, 185, 195, 205, 215, 225, 235, 245, 255];
+
+impl ColorCorrection {
+    /// Converts a colour with 5-bit channels
+    pub fn convert(self, r: u8, g: u8, b: u8) -> [u8; 3] {
+        match self {
+            ColorCorrection::Raw => [(r << 3) | (r >> 2), (g << 3) | (g >> 2), (b << 3) | (b >> 2)],
+            ColorCorrection::Gambatte => {
+                let (r, g, b) = (r as u32, g as u32, b as u32);
+                [((r * 13 + g * 2 + b) >> 1) as u8, ((g * 3 + b) << 1) as u8, ((r * 3 + g * 2 + b * 11) >> 1) as u8]
+            },
+            ColorCorrection::Accurate => ColorCorrection::mix(&CGB_CURVE, 3.0, r, g, b),
+            ColorCorrection::Gba => ColorCorrection::mix(&GBA_CURVE, 5.0, r, g, b),
+        }
+    }
+
+    // Applies the curve, then mixes some blue into green with a gamma of 2.2
+    fn mix(curve: &[u8; 32], green_weight: f64, r: u8, g: u8, b: u8) -> [u8; 3] {
+        const GAMMA: f64 = 2.2;
+        let (r, g, b) = (curve[r as usize], curve[g as usize], curve[b as usize]);
+        let linear = |c: u8| (c as f64 / 255.0).powf(GAMMA);
+        let mixed = ((linear(g) * green_weight + linear(b)) / (green_weight + 1.0)).powf(1.0 / GAMMA);
+        [r, (mixed * 255.0).round() as u8, b]
+    }
+
+    // Screen colours of all 32768 palette colours, indexed like the palette RAM
+    pub(super) fn table(self) -> Vec<[u8; 3]> {
+        (0 .. 0x8000u16).map(|c| self.convert((c & 0x1F) as u8, ((c >> 5) & 0x1F) as u8, (c >> 10) as u8)).collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ColorCorrection, DmgPalette, Shades};
+    use crate::gpu::GPU;
+
+    #[test]
+    fn parse_shades() {
+        assert_eq!("Pocket".parse(), Ok(Shades::POCKET));
+        assert_eq!("e0f8d0, #88c070,346856,081820".parse(),
+            Ok(Shades([[0xE0, 0xF8, 0xD0], [0x88, 0xC0, 0x70], [0x34, 0x68, 0x56], [0x08, 0x18, 0x20]])));
+        assert!("e0f8d0,88c070,346856".parse::<Shades>().is_err());
+        assert!("e0f8d0,88c070,346856,08182".parse::<Shades>().is_err());
+        assert!("sepia".parse::<Shades>().is_err());
+    }
+
+    #[test]
+    fn separate_object_palettes() {
+        let mut gpu = GPU::new();
+        gpu.set_dmg_palette(DmgPalette { bg: Shades::GREEN, obj0: Shades::GREY, obj1: Shades::LIGHT });
+        gpu.wb(0xFF47, 0xE4);
+        gpu.wb(0xFF48, 0xE4);
+        gpu.wb(0xFF49, 0xE4);
+        assert_eq!(gpu.palb[1], Shades::GREEN.0[1]);
+        assert_eq!(gpu.pal0[2], Shades::GREY.0[2]);
+        assert_eq!(gpu.pal1[3], Shades::LIGHT.0[3]);
+
+        // The screen is blank with the lightest background shade while the LCD is off
+        gpu.wb(0xFF40, 0x80);
+        gpu.wb(0xFF40, 0x00);
+        assert_eq!(&gpu.data[0 .. 3], &Shades::GREEN.0[0]);
+    }
+
+    #[test]
+    fn color_correction() {
+        assert_eq!(ColorCorrection::Raw.convert(0x1F, 0x10, 0x00), [255, 132, 0]);
+        assert_eq!(ColorCorrection::Gambatte.convert(0x1F, 0x1F, 0x1F), [248, 248, 248]);
+        for mode in [ColorCorrection::Accurate, ColorCorrection::Gba] {
+            assert_eq!(mode.convert(0x1F, 0x1F, 0x1F), [255, 255, 255]);
+            assert_eq!(mode.convert(0, 0, 0), [0, 0, 0]);
+            // Pure blue lights up some of the green subpixels
+            assert!(mode.convert(0, 0, 0x1F)[1] > 0);
+        }
+        assert!(ColorCorrection::Gba.convert(0x10, 0, 0)[0] < ColorCorrection::Accurate.convert(0x10, 0, 0)[0]);
+    }
+}
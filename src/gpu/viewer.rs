@@ -0,0 +1,251 @@
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use crate::gbmode::GbMode;
+use crate::StrResult;
+use super::{GPU, Shades};
+
+/// An RGBA image of some part of the GPU state, for debugging
+#[derive(Debug, Clone)]
+pub struct DebugImage {
+    pub width: usize,
+    pub height: usize,
+    /// Four bytes per pixel, row by row
+    pub pixels: Vec<u8>,
+}
+
+impl DebugImage {
+    fn new(width: usize, height: usize) -> DebugImage {
+        DebugImage { width, height, pixels: vec![0; width * height * 4] }
+    }
+
+    fn set(&mut self, x: usize, y: usize, color: [u8; 3]) {
+        let index = (y * self.width + x) * 4;
+        self.pixels[index .. index + 3].copy_from_slice(&color);
+        self.pixels[index + 3] = 255;
+    }
+
+    /// Writes the image as PNG
+    pub fn save_png(&self, path: &Path) -> StrResult<()> {
+        let file = fs::File::create(path).map_err(|_| "Could not create PNG file")?;
+        let mut encoder = png::Encoder::new(file, self.width as u32, self.height as u32);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header().map_err(|_| "Could not write PNG file")?;
+        writer.write_image_data(&self.pixels).map_err(|_| "Could not write PNG file")
+    }
+}
+
+/// A decoded OAM entry
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct OamEntry {
+    /// Position as stored in OAM, so (8, 16) is the top left corner of the screen
+    pub x: u8,
+    pub y: u8,
+    pub tile: u8,
+    /// OBP0 or OBP1 on DMG, or one of the eight object palettes on CGB
+    pub palette: u8,
+    /// VRAM bank of the tile data, only used on CGB
+    pub bank: u8,
+    pub xflip: bool,
+    pub yflip: bool,
+    pub below_bg: bool,
+}
+
+impl fmt::Display for OamEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "x={:3} y={:3} tile={:02X} palette={} bank={}", self.x, self.y, self.tile, self.palette, self.bank)?;
+        if self.xflip { write!(f, " xflip")?; }
+        if self.yflip { write!(f, " yflip")?; }
+        if self.below_bg { write!(f, " below-bg")?; }
+        Ok(())
+    }
+}
+
+// Tiles per row in the tile data view, and rows per VRAM bank
+const TILES_W: usize = 16;
+const TILES_H: usize = 24;
+// Objects per row in the OAM view
+const OAM_W: usize = 8;
+// Size of a colour in the palette view
+const SWATCH: usize = 8;
+
+impl GPU {
+    // Colour number of a pixel in the tile at the given address of a VRAM bank
+    fn tile_pixel(&self, bank: usize, address: usize, x: usize, y: usize) -> usize {
+        let b1 = self.vram[bank * 0x2000 + address + y * 2];
+        let b2 = self.vram[bank * 0x2000 + address + y * 2 + 1];
+        let bit = 7 - x;
+        (((b1 >> bit) & 1) | (((b2 >> bit) & 1) << 1)) as usize
+    }
+
+    /// All 384 tiles of both VRAM banks in grey, bank 0 on the left and bank 1 on the right
+    pub fn tile_data_image(&self) -> DebugImage {
+        let mut image = DebugImage::new(TILES_W * 8 * 2, TILES_H * 8);
+        for bank in 0 .. 2 {
+            for tile in 0 .. TILES_W * TILES_H {
+                let (tx, ty) = (bank * TILES_W + tile % TILES_W, tile / TILES_W);
+                for y in 0 .. 8 {
+                    for x in 0 .. 8 {
+                        let color = self.tile_pixel(bank, tile * 16, x, y);
+                        image.set(tx * 8 + x, ty * 8 + y, Shades::GREY.0[color]);
+                    }
+                }
+            }
+        }
+        image
+    }
+
+    /// The tile map at 0x9800 (0) or 0x9C00 (1) with the current tile data and palettes. The
+    /// area shown by the background scroll registers is outlined in red.
+    pub fn tilemap_image(&self, map: usize) -> DebugImage {
+        let mut image = DebugImage::new(256, 256);
+        let base = if map == 0 { 0x1800 } else { 0x1C00 };
+        for tile in 0 .. 32 * 32 {
+            let tilenr = self.vram[base + tile];
+            let address = if self.tilebase == 0x8000 {
+                tilenr as usize * 16
+            } else {
+                (0x1000 + tilenr as i8 as isize * 16) as usize
+            };
+            let flags = if self.gbmode == GbMode::Color { self.vram[0x2000 + base + tile] as usize } else { 0 };
+            let bank = (flags >> 3) & 1;
+            for y in 0 .. 8 {
+                for x in 0 .. 8 {
+                    let tx = if flags & (1 << 5) != 0 { 7 - x } else { x };
+                    let ty = if flags & (1 << 6) != 0 { 7 - y } else { y };
+                    let colnr = self.tile_pixel(bank, address, tx, ty);
+                    let color = if self.gbmode == GbMode::Color {
+                        let [r, g, b] = self.cbgpal[flags & 0x07][colnr];
+                        self.cgb_color(r, g, b)
+                    } else {
+                        self.palb[colnr]
+                    };
+                    image.set((tile % 32) * 8 + x, (tile / 32) * 8 + y, color);
+                }
+            }
+        }
+
+        for i in 0 .. 160 {
+            let x = self.scx.wrapping_add(i as u8) as usize;
+            image.set(x, self.scy as usize, [255, 0, 0]);
+            image.set(x, self.scy.wrapping_add(143) as usize, [255, 0, 0]);
+        }
+        for i in 0 .. 144 {
+            let y = self.scy.wrapping_add(i as u8) as usize;
+            image.set(self.scx as usize, y, [255, 0, 0]);
+            image.set(self.scx.wrapping_add(159) as usize, y, [255, 0, 0]);
+        }
+        image
+    }
+
+    /// The 40 entries of OAM
+    pub fn oam_entries(&self) -> Vec<OamEntry> {
+        self.voam.chunks(4).map(|entry| {
+            let flags = entry[3];
+            let is_color = self.gbmode == GbMode::Color;
+            OamEntry {
+                y: entry[0],
+                x: entry[1],
+                tile: entry[2],
+                palette: if is_color { flags & 0x07 } else { (flags >> 4) & 1 },
+                bank: if is_color { (flags >> 3) & 1 } else { 0 },
+                xflip: flags & (1 << 5) != 0,
+                yflip: flags & (1 << 6) != 0,
+                below_bg: flags & (1 << 7) != 0,
+            }
+        }).collect()
+    }
+
+    /// The objects in OAM order, eight per row, with their palettes and flips. Each object gets
+    /// an 8x16 cell, and colour 0 is transparent.
+    pub fn oam_image(&self) -> DebugImage {
+        let mut image = DebugImage::new(OAM_W * 8, 40 / OAM_W * 16);
+        let size = self.sprite_size as usize;
+        for (i, entry) in self.oam_entries().iter().enumerate() {
+            let tile = if size == 16 { entry.tile & 0xFE } else { entry.tile } as usize;
+            for y in 0 .. size {
+                for x in 0 .. 8 {
+                    let tx = if entry.xflip { 7 - x } else { x };
+                    let ty = if entry.yflip { size - 1 - y } else { y };
+                    let colnr = self.tile_pixel(entry.bank as usize, tile * 16 + (ty / 8) * 16, tx, ty % 8);
+                    if colnr == 0 { continue }
+                    let color = if self.gbmode == GbMode::Color {
+                        let [r, g, b] = self.csprit[entry.palette as usize][colnr];
+                        self.cgb_color(r, g, b)
+                    } else if entry.palette == 1 {
+                        self.pal1[colnr]
+                    } else {
+                        self.pal0[colnr]
+                    };
+                    image.set((i % OAM_W) * 8 + x, (i / OAM_W) * 16 + y, color);
+                }
+            }
+        }
+        image
+    }
+
+    /// One row of four colours per palette. On CGB these are the eight background palettes
+    /// followed by the eight object palettes, otherwise BGP, OBP0 and OBP1.
+    pub fn palette_image(&self) -> DebugImage {
+        let palettes: Vec<[[u8; 3]; 4]> = if self.gbmode == GbMode::Color {
+            self.cbgpal.iter().chain(self.csprit.iter())
+                .map(|pal| pal.map(|[r, g, b]| self.cgb_color(r, g, b)))
+                .collect()
+        } else {
+            vec![self.palb, self.pal0, self.pal1]
+        };
+
+        let mut image = DebugImage::new(4 * SWATCH, palettes.len() * SWATCH);
+        for (row, pal) in palettes.iter().enumerate() {
+            for y in 0 .. SWATCH {
+                for x in 0 .. 4 * SWATCH {
+                    image.set(x, row * SWATCH + y, pal[x / SWATCH]);
+                }
+            }
+        }
+        image
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::gbmode::GbMode;
+    use crate::gpu::GPU;
+
+    #[test]
+    fn viewers() {
+        let mut gpu = GPU::new();
+        gpu.wb(0xFF47, 0xE4);
+        // Tile 1 has a black first row
+        gpu.wb(0x8010, 0xFF);
+        gpu.wb(0x8011, 0xFF);
+        gpu.wb(0x9C00, 0x01);
+        gpu.wb(0xFF42, 250);
+        gpu.wb(0xFE00, 16);
+        gpu.wb(0xFE01, 8);
+        gpu.wb(0xFE02, 0x01);
+        gpu.wb(0xFE03, 0x30);
+        gpu.wb(0xFF40, 0x91);
+
+        let tiles = gpu.tile_data_image();
+        assert_eq!((tiles.width, tiles.height), (256, 192));
+        assert_eq!(&tiles.pixels[8 * 4 .. 8 * 4 + 4], &[0, 0, 0, 255]);
+
+        let map = gpu.tilemap_image(1);
+        assert_eq!(&map.pixels[4 * 4 .. 4 * 4 + 3], &gpu.palb[3]);
+        // The viewport wraps around to the bottom of the map
+        assert_eq!(&map.pixels[(250 * 256 + 4) * 4 .. (250 * 256 + 4) * 4 + 3], &[255, 0, 0]);
+        assert_eq!(&map.pixels[(137 * 256 + 4) * 4 .. (137 * 256 + 4) * 4 + 3], &[255, 0, 0]);
+
+        let entry = gpu.oam_entries()[0];
+        assert_eq!((entry.x, entry.y, entry.tile, entry.palette, entry.xflip), (8, 16, 1, 1, true));
+        let oam = gpu.oam_image();
+        assert_eq!(&oam.pixels[0 .. 4], &[gpu.pal1[3][0], gpu.pal1[3][1], gpu.pal1[3][2], 255]);
+        assert_eq!(oam.pixels[(8 * oam.width) * 4 + 3], 0);
+
+        assert_eq!(gpu.palette_image().height, 3 * 8);
+        gpu.gbmode = GbMode::Color;
+        assert_eq!(gpu.palette_image().height, 16 * 8);
+    }
+}
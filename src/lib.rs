@@ -2,17 +2,22 @@
 #![crate_type = "lib" ]
 
 pub use crate::keypad::KeypadKey;
-pub use crate::gpu::{SCREEN_W, SCREEN_H};
+pub use crate::gpu::{ColorCorrection, ColorizationKeys, DebugImage, DmgPalette, FrameBlending, Layer, OamEntry, PixelFormat, Renderer, Shades, SCREEN_W, SCREEN_H};
 pub use crate::sound::AudioPlayer;
+pub use crate::mbc::{RomOptions, RtcMode, read_rom, detect_mapper};
+pub use crate::mbc::header::{CartridgeHeader, CartridgeFeatures, CartridgeWarning, CgbSupport, Destination, Mapper, validate_rom};
 
+pub mod camera;
 pub mod device;
 
+mod archive;
 mod cpu;
 mod gbmode;
 mod gpu;
 mod keypad;
 mod mbc;
 mod mmu;
+mod patch;
 mod printer;
 mod register;
 mod serial;
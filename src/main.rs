@@ -4,6 +4,7 @@ use gb_em::device::Device;
 use std::io::{self, Read};
 use std::sync::mpsc::{self, Receiver, SyncSender, TryRecvError, TrySendError};
 use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::thread;
 use cpal::traits::{HostTrait, DeviceTrait, StreamTrait};
 use cpal::{Sample, FromSample};
@@ -15,6 +16,8 @@ const EXITCODE_CPULOADFAILS : i32 = 2;
 #[derive(Default)]
 struct RenderOptions {
     pub linear_interpolation: bool,
+    // Horizontal offset of the image in Game Boy pixels, to shake it while the cartridge rumbles
+    pub shake: i32,
 }
 
 enum GBEvent {
@@ -22,6 +25,39 @@ enum GBEvent {
     KeyDown(gb_em::KeypadKey),
     SpeedUp,
     SpeedDown,
+    Tilt(f32, f32),
+    SetLayer(gb_em::Layer, bool),
+    HighlightWindow(bool),
+    IgnoreSpritePriority(bool),
+    DumpGpuState,
+}
+
+#[derive(Default)]
+struct TiltKeys {
+    up: bool,
+    down: bool,
+    left: bool,
+    right: bool,
+}
+
+impl TiltKeys {
+    // Returns true when the key is one of the tilt keys
+    fn update(&mut self, key: winit::keyboard::Key<&str>, pressed: bool) -> bool {
+        use winit::keyboard::Key;
+        match key {
+            Key::Character("I" | "i") => self.up = pressed,
+            Key::Character("K" | "k") => self.down = pressed,
+            Key::Character("J" | "j") => self.left = pressed,
+            Key::Character("L" | "l") => self.right = pressed,
+            _ => return false,
+        }
+        true
+    }
+
+    fn tilt(&self) -> (f32, f32) {
+        let axis = |neg: bool, pos: bool| (pos as i32 - neg as i32) as f32;
+        (axis(self.left, self.right), axis(self.up, self.down))
+    }
 }
 
 #[cfg(target_os = "windows")]
@@ -68,6 +104,34 @@ fn parse_scale_var(arg: &str) -> Result<u32, ArgParseError> {
     }
 }
 
+// Arguments that determine how the ROM file is read, shared by the emulator and the info command
+fn rom_option_args() -> [clap::Arg; 3] {
+    [
+        clap::Arg::new("patch")
+             .help("Applies an IPS, UPS or BPS patch to the ROM. Default: <filename>.ips/.ups/.bps if present")
+             .long("patch")
+             .value_name("patchfile"),
+        clap::Arg::new("entry")
+             .help("Selects the ROM to load from a zip archive. Default: the first .gb or .gbc file")
+             .long("entry")
+             .value_name("name"),
+        clap::Arg::new("mapper")
+             .help("Overrides the detected mapper: rom, mbc1, mbc2, mmm01, mbc3, mbc5, mbc6, mbc7, huc1, huc3, tama5, camera, wisdom-tree, m161 or mbc1-scrambled:<bit order>")
+             .long("mapper")
+             .value_name("mapper")
+             .value_parser(|s: &str| s.parse::<gb_em::Mapper>()),
+    ]
+}
+
+fn rom_options_from_matches(matches: &clap::ArgMatches) -> gb_em::RomOptions {
+    gb_em::RomOptions {
+        patch: matches.get_one::<String>("patch").map(|p| p.into()),
+        archive_entry: matches.get_one::<String>("entry").cloned(),
+        mapper: matches.get_one::<gb_em::Mapper>("mapper").copied(),
+        ..Default::default()
+    }
+}
+
 fn main() {
     let exit_status = real_main();
     if exit_status != EXITCODE_SUCCESS {
@@ -80,6 +144,21 @@ fn real_main() -> i32 {
         .version("0.1")
         .author("Mathijs van de Nes")
         .about("A Gameboy Colour emulator written in Rust")
+        .args_conflicts_with_subcommands(true)
+        .subcommand_negates_reqs(true)
+        .subcommand(clap::Command::new("info")
+             .about("Prints the cartridge header of a ROM")
+             .arg(clap::Arg::new("filename")
+                  .help("Sets the ROM file to inspect")
+                  .required(true))
+             .args(rom_option_args()))
+        .subcommand(clap::Command::new("camera-export")
+             .about("Exports the photos in a Game Boy Camera save as PNG files")
+             .arg(clap::Arg::new("savefile")
+                  .help("Sets the save file to read")
+                  .required(true))
+             .arg(clap::Arg::new("outdir")
+                  .help("Sets the directory to write photo_01.png to photo_30.png to. Default: the current directory")))
         .arg(clap::Arg::new("filename")
              .help("Sets the ROM file to load")
              .required(true))
@@ -112,12 +191,78 @@ fn real_main() -> i32 {
              .help("Skips verification of the cartridge checksum")
              .long("skip-checksum")
              .action(clap::ArgAction::SetTrue))
+        .args(rom_option_args())
+        .arg(clap::Arg::new("camera")
+             .help("Sets the images seen by the Game Boy Camera: a PNG file, a directory of PNG files or 'test'. Default: test")
+             .long("camera")
+             .value_name("source"))
+        .arg(clap::Arg::new("rtc")
+             .help("Sets the clock followed by the cartridge RTC: 'wall' for the system clock or 'emulated' for the emulated clock cycles. Default: wall")
+             .long("rtc")
+             .value_name("clock")
+             .value_parser(["wall", "emulated"]))
+        .arg(clap::Arg::new("rtc-days")
+             .help("Moves the cartridge RTC forward (or back when negative) by a number of days")
+             .long("rtc-days")
+             .value_name("days")
+             .allow_negative_numbers(true)
+             .value_parser(clap::value_parser!(i64)))
+        .arg(clap::Arg::new("renderer")
+             .help("Sets the renderer: 'scanline' draws each line at once, 'fifo' emulates the pixel FIFO of the PPU. Default: scanline")
+             .long("renderer")
+             .value_name("renderer")
+             .value_parser(["scanline", "fifo"]))
+        .arg(clap::Arg::new("palette")
+             .help("Sets the DMG palette: grey, green, pocket, light, high-contrast, colorblind or four hex colours such as e0f8d0,88c070,346856,081820. Default: grey")
+             .long("palette")
+             .value_name("palette")
+             .value_parser(|s: &str| s.parse::<gb_em::Shades>()))
+        .arg(clap::Arg::new("palette-obj0")
+             .help("Sets the DMG palette of objects using OBP0. Default: the --palette value")
+             .long("palette-obj0")
+             .value_name("palette")
+             .value_parser(|s: &str| s.parse::<gb_em::Shades>()))
+        .arg(clap::Arg::new("palette-obj1")
+             .help("Sets the DMG palette of objects using OBP1. Default: the --palette value")
+             .long("palette-obj1")
+             .value_name("palette")
+             .value_parser(|s: &str| s.parse::<gb_em::Shades>()))
+        .arg(clap::Arg::new("color-correction")
+             .help("Sets the colour correction of CGB colours: 'raw' shows the palette values unchanged, 'gambatte' uses the colour matrix of Gambatte, 'accurate' emulates the CGB screen and 'gba' a GBA screen. Default: gambatte")
+             .long("color-correction")
+             .value_name("mode")
+             .value_parser(["raw", "gambatte", "accurate", "gba"]))
+        .arg(clap::Arg::new("colorize")
+             .help("Picks the colours of a DMG game in CGB mode like the keys held on the CGB boot screen: a direction, optionally with +a or +b. Default: chosen from the title")
+             .long("colorize")
+             .value_name("keys")
+             .value_parser(|s: &str| s.parse::<gb_em::ColorizationKeys>()))
+        .arg(clap::Arg::new("frame-blending")
+             .help("Mixes each frame with the previous ones like the slow LCD: 'off', 'mix' averages two frames, 'decay:<persistence>' keeps a fraction between 0 and 1 of the previous output, 'lcd' follows the DMG or CGB screen. Default: off")
+             .long("frame-blending")
+             .value_name("mode")
+             .value_parser(|s: &str| s.parse::<gb_em::FrameBlending>()))
+        .arg(clap::Arg::new("unlimited-sprites")
+             .help("Draws all sprites on a line instead of the first ten, to stop sprites from flickering")
+             .long("unlimited-sprites")
+             .action(clap::ArgAction::SetTrue))
         .arg(clap::Arg::new("test-mode")
              .help("Starts the emulator in a special test mode")
              .long("test-mode")
              .action(clap::ArgAction::SetTrue))
         .get_matches();
 
+    if let Some(info_matches) = matches.subcommand_matches("info") {
+        let filename = info_matches.get_one::<String>("filename").unwrap();
+        return run_info(filename, &rom_options_from_matches(info_matches));
+    }
+
+    if let Some(export_matches) = matches.subcommand_matches("camera-export") {
+        let savefile = export_matches.get_one::<String>("savefile").unwrap();
+        let outdir = export_matches.get_one::<String>("outdir").map_or(".", |s| s.as_str());
+        return run_camera_export(savefile, outdir);
+    }
+
     let test_mode = matches.get_one::<bool>("test-mode").copied().unwrap();
     let opt_serial = matches.get_one::<bool>("serial").copied().unwrap();
     let opt_printer = matches.get_one::<bool>("printer").copied().unwrap();
@@ -127,14 +272,70 @@ fn real_main() -> i32 {
     let filename = matches.get_one::<String>("filename").unwrap();
     let scale = matches.get_one::<u32>("scale").copied().unwrap_or(2);
 
+    let rtc_mode = match matches.get_one::<String>("rtc").map(|s| s.as_str()) {
+        Some("emulated") => gb_em::RtcMode::Emulated,
+        _ => gb_em::RtcMode::WallClock,
+    };
+
+    let rom_options = gb_em::RomOptions {
+        skip_checksum: opt_skip_checksum,
+        rtc_mode,
+        ..rom_options_from_matches(&matches)
+    };
+
+    let frame_blending = matches.get_one::<gb_em::FrameBlending>("frame-blending").copied().unwrap_or_default();
+
     if test_mode {
-        return run_test_mode(filename, opt_classic, opt_skip_checksum);
+        return run_test_mode(filename, opt_classic, &rom_options, frame_blending);
     }
 
-    let cpu = construct_cpu(filename, opt_classic, opt_serial, opt_printer, opt_skip_checksum);
+    let cpu = construct_cpu(filename, opt_classic, opt_serial, opt_printer, &rom_options);
     if cpu.is_none() { return EXITCODE_CPULOADFAILS; }
     let mut cpu = cpu.unwrap();
 
+    if let Some(&days) = matches.get_one::<i64>("rtc-days") {
+        cpu.offset_rtc_days(days);
+    }
+
+    cpu.set_frame_blending(frame_blending);
+    cpu.set_unlimited_sprites(matches.get_flag("unlimited-sprites"));
+    if let Some(saved) = cpu.saved_settings() {
+        if saved.unlimited_sprites != cpu.unlimited_sprites() {
+            warn(&format!("Warning: the save was last played with --unlimited-sprites {}", if saved.unlimited_sprites { "on" } else { "off" }));
+        }
+    }
+
+    if let Some("fifo") = matches.get_one::<String>("renderer").map(|s| s.as_str()) {
+        cpu.set_renderer(gb_em::Renderer::PixelFifo);
+    }
+
+    let correction = match matches.get_one::<String>("color-correction").map(|s| s.as_str()) {
+        Some("raw") => gb_em::ColorCorrection::Raw,
+        Some("accurate") => gb_em::ColorCorrection::Accurate,
+        Some("gba") => gb_em::ColorCorrection::Gba,
+        _ => gb_em::ColorCorrection::Gambatte,
+    };
+    cpu.set_color_correction(correction);
+
+    if let Some(&keys) = matches.get_one::<gb_em::ColorizationKeys>("colorize") {
+        cpu.set_colorization_keys(keys);
+    }
+
+    let shades = matches.get_one::<gb_em::Shades>("palette").copied().unwrap_or(gb_em::Shades::GREY);
+    cpu.set_dmg_palette(gb_em::DmgPalette {
+        bg: shades,
+        obj0: matches.get_one::<gb_em::Shades>("palette-obj0").copied().unwrap_or(shades),
+        obj1: matches.get_one::<gb_em::Shades>("palette-obj1").copied().unwrap_or(shades),
+    });
+
+    match matches.get_one::<String>("camera").map(|s| s.as_str()) {
+        None | Some("test") => {},
+        Some(path) => match gb_em::camera::image_source_from_path(path.as_ref()) {
+            Ok(source) => cpu.set_camera_source(source),
+            Err(errmsg) => { warn(errmsg); return EXITCODE_CPULOADFAILS; },
+        },
+    }
+
     let mut cpal_audio_stream = None;
     if opt_audio {
         let player = CpalPlayer::get();
@@ -153,6 +354,7 @@ fn real_main() -> i32 {
 
     let (sender1, receiver1) = mpsc::channel();
     let (sender2, receiver2) = mpsc::sync_channel(1);
+    let (sender3, receiver3) = mpsc::channel();
 
     let mut event_loop = winit::event_loop::EventLoop::new().unwrap();
     let window_builder = create_window_builder(&romname);
@@ -168,8 +370,14 @@ fn real_main() -> i32 {
         .unwrap();
 
     let mut renderoptions = <RenderOptions as Default>::default();
+    let mut tiltkeys = TiltKeys::default();
+    let mut layers = [true; 3];
+    let mut highlight_window = false;
+    let mut ignore_sprite_priority = false;
 
-    let cputhread = thread::spawn(move|| run_cpu(cpu, sender2, receiver1));
+    let rumble = Arc::new(AtomicBool::new(false));
+    let cpu_rumble = rumble.clone();
+    let cputhread = thread::spawn(move|| run_cpu(cpu, sender2, receiver3, receiver1, cpu_rumble));
 
     event_loop.set_control_flow(winit::event_loop::ControlFlow::Poll);
     'evloop: loop {
@@ -196,6 +404,29 @@ fn real_main() -> i32 {
                             => { let _ = sender1.send(GBEvent::SpeedDown); },
                         (Pressed, Key::Character("t" | "T"))
                             => { renderoptions.linear_interpolation = !renderoptions.linear_interpolation; }
+                        (Pressed, Key::Named(key @ (NamedKey::F1 | NamedKey::F2 | NamedKey::F3))) => {
+                            let layer = match key {
+                                NamedKey::F1 => gb_em::Layer::Background,
+                                NamedKey::F2 => gb_em::Layer::Window,
+                                _ => gb_em::Layer::Sprites,
+                            };
+                            layers[layer as usize] = !layers[layer as usize];
+                            let _ = sender1.send(GBEvent::SetLayer(layer, layers[layer as usize]));
+                        },
+                        (Pressed, Key::Named(NamedKey::F4)) => {
+                            highlight_window = !highlight_window;
+                            let _ = sender1.send(GBEvent::HighlightWindow(highlight_window));
+                        },
+                        (Pressed, Key::Named(NamedKey::F5)) => {
+                            ignore_sprite_priority = !ignore_sprite_priority;
+                            let _ = sender1.send(GBEvent::IgnoreSpritePriority(ignore_sprite_priority));
+                        },
+                        (Pressed, Key::Named(NamedKey::F6))
+                            => { let _ = sender1.send(GBEvent::DumpGpuState); },
+                        (state, winitkey) if tiltkeys.update(winitkey.clone(), state == Pressed) => {
+                            let (x, y) = tiltkeys.tilt();
+                            let _ = sender1.send(GBEvent::Tilt(x, y));
+                        },
                         (Pressed, winitkey) => {
                             if let Some(key) = winit_to_keypad(winitkey) {
                                 let _ = sender1.send(GBEvent::KeyDown(key));
@@ -216,8 +447,16 @@ fn real_main() -> i32 {
         if let PumpStatus::Exit(_) = status {
             break 'evloop;
         }
+        renderoptions.shake = match (rumble.load(Ordering::Relaxed), renderoptions.shake > 0) {
+            (false, _) => 0,
+            (true, true) => -1,
+            (true, false) => 1,
+        };
         match receiver2.recv() {
-            Ok(data) => recalculate_screen(&display, &mut texture, &*data, &renderoptions),
+            Ok(data) => {
+                recalculate_screen(&display, &mut texture, &*data, &renderoptions);
+                let _ = sender3.send(data);
+            },
             Err(..) => break 'evloop, // Remote end has hung-up
         }
     }
@@ -274,14 +513,18 @@ fn recalculate_screen<T: glium::glutin::surface::SurfaceTypeTrait + glium::gluti
         rawimage2d);
 
     // We use a custom BlitTarget to transform OpenGL coordinates to row-column coordinates
-    let target = display.draw();
+    let mut target = display.draw();
     let (target_w, target_h) = target.get_dimensions();
+    if renderoptions.shake != 0 {
+        target.clear_color(0.0, 0.0, 0.0, 1.0);
+    }
+    let shake = renderoptions.shake * (target_w / gb_em::SCREEN_W as u32) as i32;
     texture.as_surface().blit_whole_color_to(
         &target,
         &glium::BlitTarget {
-            left: 0,
+            left: shake.max(0) as u32,
             bottom: target_h,
-            width: target_w as i32,
+            width: target_w as i32 - shake.abs(),
             height: -(target_h as i32)
         },
         interpolation_type);
@@ -292,16 +535,19 @@ fn warn(message: &str) {
     eprintln!("{}", message);
 }
 
-fn construct_cpu(filename: &str, classic_mode: bool, output_serial: bool, output_printer: bool, skip_checksum: bool) -> Option<Box<Device>> {
+fn construct_cpu(filename: &str, classic_mode: bool, output_serial: bool, output_printer: bool, rom_options: &gb_em::RomOptions) -> Option<Box<Device>> {
     let opt_c = match classic_mode {
-        true => Device::new(filename, skip_checksum),
-        false => Device::new_cgb(filename, skip_checksum),
+        true => Device::new_with_options(filename, rom_options),
+        false => Device::new_cgb_with_options(filename, rom_options),
     };
     let mut c = match opt_c
     {
         Ok(cpu) => { cpu },
         Err(message) => { warn(message); return None; },
     };
+    for warning in c.cartridge_warnings() {
+        warn(&format!("Warning: {}", warning));
+    }
 
     if output_printer {
         c.attach_printer();
@@ -313,25 +559,34 @@ fn construct_cpu(filename: &str, classic_mode: bool, output_serial: bool, output
     Some(Box::new(c))
 }
 
-fn run_cpu(mut cpu: Box<Device>, sender: SyncSender<Vec<u8>>, receiver: Receiver<GBEvent>) {
+fn run_cpu(mut cpu: Box<Device>, sender: SyncSender<Vec<u8>>, framereceiver: Receiver<Vec<u8>>, receiver: Receiver<GBEvent>, rumble: Arc<AtomicBool>) {
     let periodic = timer_periodic(16);
     let mut limit_speed = true;
+    // Frames are swapped with buffers that the window sends back once shown
+    let mut spareframe = None;
 
     let waitticks = (4194304f64 / 1000.0 * 16.0).round() as u32;
     let mut ticks = 0;
 
     'outer: loop {
+        // Games pulse the motor to vary its strength, so any rumble during the frame counts
+        let mut rumbling = false;
         while ticks < waitticks {
             ticks += cpu.do_cycle();
+            rumbling |= cpu.rumble_state();
             if cpu.check_and_reset_gpu_updated() {
-                let data = cpu.get_gpu_data().to_vec();
-                if let Err(TrySendError::Disconnected(..)) = sender.try_send(data) {
-                    break 'outer;
+                let mut data = spareframe.take().or_else(|| framereceiver.try_recv().ok()).unwrap_or_default();
+                cpu.swap_gpu_data(&mut data);
+                match sender.try_send(data) {
+                    Err(TrySendError::Full(data)) => spareframe = Some(data),
+                    Err(TrySendError::Disconnected(..)) => break 'outer,
+                    Ok(()) => {},
                 }
             }
         }
 
         ticks -= waitticks;
+        rumble.store(rumbling, Ordering::Relaxed);
 
         'recv: loop {
             match receiver.try_recv() {
@@ -341,6 +596,11 @@ fn run_cpu(mut cpu: Box<Device>, sender: SyncSender<Vec<u8>>, receiver: Receiver
                         GBEvent::KeyDown(key) => cpu.keydown(key),
                         GBEvent::SpeedUp => limit_speed = false,
                         GBEvent::SpeedDown => { limit_speed = true; cpu.sync_audio(); }
+                        GBEvent::Tilt(x, y) => cpu.set_accelerometer(x, y),
+                        GBEvent::SetLayer(layer, enabled) => cpu.set_layer_enabled(layer, enabled),
+                        GBEvent::HighlightWindow(highlight) => cpu.set_highlight_window(highlight),
+                        GBEvent::IgnoreSpritePriority(ignore) => cpu.set_ignore_sprite_priority(ignore),
+                        GBEvent::DumpGpuState => dump_gpu_state(&cpu),
                     }
                 },
                 Err(TryRecvError::Empty) => break 'recv,
@@ -352,6 +612,27 @@ fn run_cpu(mut cpu: Box<Device>, sender: SyncSender<Vec<u8>>, receiver: Receiver
     }
 }
 
+// Writes the VRAM, tile map, OAM and palette views to PNG files in the current directory, and
+// prints the OAM entries
+fn dump_gpu_state(cpu: &Device) {
+    let images = [
+        ("gpu_tiles.png", cpu.tile_data_image()),
+        ("gpu_tilemap0.png", cpu.tilemap_image(0)),
+        ("gpu_tilemap1.png", cpu.tilemap_image(1)),
+        ("gpu_oam.png", cpu.oam_image()),
+        ("gpu_palettes.png", cpu.palette_image()),
+    ];
+    for (filename, image) in images.iter() {
+        if let Err(errmsg) = image.save_png(std::path::Path::new(filename)) {
+            warn(errmsg);
+            return;
+        }
+    }
+    for (i, entry) in cpu.oam_entries().iter().enumerate() {
+        println!("OAM {:2}: {}", i, entry);
+    }
+}
+
 fn timer_periodic(ms: u64) -> Receiver<()> {
     let (tx, rx) = std::sync::mpsc::sync_channel(1);
     std::thread::spawn(move || {
@@ -495,10 +776,10 @@ impl gb_em::AudioPlayer for NullAudioPlayer {
     }
 }
 
-fn run_test_mode(filename: &str, classic_mode: bool, skip_checksum: bool) -> i32 {
+fn run_test_mode(filename: &str, classic_mode: bool, rom_options: &gb_em::RomOptions, frame_blending: gb_em::FrameBlending) -> i32 {
     let opt_cpu = match classic_mode {
-        true => Device::new(filename, skip_checksum),
-        false => Device::new_cgb(filename, skip_checksum),
+        true => Device::new_with_options(filename, rom_options),
+        false => Device::new_cgb_with_options(filename, rom_options),
     };
     let mut cpu = match opt_cpu {
         Err(errmsg) => { warn(errmsg); return EXITCODE_CPULOADFAILS; },
@@ -507,6 +788,7 @@ fn run_test_mode(filename: &str, classic_mode: bool, skip_checksum: bool) -> i32
 
     cpu.set_stdout(true);
     cpu.enable_audio(Box::new(NullAudioPlayer {}));
+    cpu.set_frame_blending(frame_blending);
 
     // from masonforest, https://stackoverflow.com/a/55201400 (CC BY-SA 4.0)
     let stdin_channel = spawn_stdin_channel();
@@ -534,6 +816,91 @@ fn run_test_mode(filename: &str, classic_mode: bool, skip_checksum: bool) -> i32
     EXITCODE_SUCCESS
 }
 
+fn run_info(filename: &str, rom_options: &gb_em::RomOptions) -> i32 {
+    let data = gb_em::read_rom(filename.as_ref(), rom_options);
+    let header = match data.as_ref().map_err(|&e| e).and_then(|data| gb_em::CartridgeHeader::parse(data)) {
+        Ok(h) => h,
+        Err(errmsg) => { warn(errmsg); return EXITCODE_CPULOADFAILS; },
+    };
+    let mapper = rom_options.mapper.unwrap_or_else(|| gb_em::detect_mapper(data.as_ref().unwrap()));
+
+    let or_unknown = |name: Option<&str>| name.unwrap_or("unknown").to_owned();
+    let validity = |valid: bool| if valid { "valid" } else { "invalid" };
+
+    let licensee = match header.new_licensee_code {
+        Some(ref code) => format!("{} (new licensee code, {})", code, or_unknown(header.licensee_name())),
+        None => format!("{:02X} ({})", header.old_licensee_code, or_unknown(header.licensee_name())),
+    };
+    let cgb = match header.cgb_support {
+        gb_em::CgbSupport::None => "DMG only",
+        gb_em::CgbSupport::Supported => "CGB enhanced",
+        gb_em::CgbSupport::Required => "CGB only",
+    };
+    let features = [
+            (header.features.ram, "RAM"),
+            (header.features.battery, "battery"),
+            (header.features.timer, "timer"),
+            (header.features.rumble, "rumble"),
+            (header.features.sensor, "sensor"),
+        ].iter()
+        .filter(|&&(present, _)| present)
+        .map(|&(_, name)| name)
+        .collect::<Vec<_>>();
+    let rom_size = match header.rom_size {
+        Some(size) => format!("{} KiB, {} banks", size / 1024, size / 0x4000),
+        None => "unknown".to_owned(),
+    };
+    let ram_size = match header.ram_size {
+        Some(size) => format!("{} KiB", size / 1024),
+        None => "unknown".to_owned(),
+    };
+
+    println!("Title:             {}", header.title);
+    println!("Manufacturer code: {}", header.manufacturer_code.as_deref().unwrap_or("none"));
+    println!("Licensee:          {}", licensee);
+    println!("CGB flag:          {:02X} ({})", header.cgb_flag, cgb);
+    println!("SGB flag:          {:02X} ({})", header.sgb_flag, if header.sgb_support { "SGB supported" } else { "no SGB support" });
+    println!("Cartridge type:    {:02X} ({})", header.cartridge_type, or_unknown(header.cartridge_type_name()));
+    if mapper == header.mapper {
+        println!("Mapper:            {}", header.mapper);
+    } else {
+        println!("Mapper:            {} (header declares {})", mapper, header.mapper);
+    }
+    println!("Features:          {}", if features.is_empty() { "none".to_owned() } else { features.join(", ") });
+    println!("ROM size:          {:02X} ({})", header.rom_size_code, rom_size);
+    println!("RAM size:          {:02X} ({})", header.ram_size_code, ram_size);
+    println!("Destination:       {}", match header.destination { gb_em::Destination::Japan => "Japan", gb_em::Destination::Overseas => "Overseas" });
+    println!("Version:           {}", header.version);
+    println!("Header checksum:   {:02X} ({})", header.header_checksum, validity(header.header_checksum_valid));
+    println!("Global checksum:   {:04X} ({})", header.global_checksum, validity(header.global_checksum_valid));
+    for warning in gb_em::validate_rom(data.as_ref().unwrap()) {
+        println!("Warning:           {}", warning);
+    }
+
+    EXITCODE_SUCCESS
+}
+
+fn run_camera_export(savefile: &str, outdir: &str) -> i32 {
+    let photos = match std::fs::read(savefile) {
+        Ok(data) => gb_em::camera::decode_photos(&data),
+        Err(_) => Err("Could not read save file"),
+    };
+    let photos = match photos {
+        Ok(p) => p,
+        Err(errmsg) => { warn(errmsg); return EXITCODE_CPULOADFAILS; },
+    };
+
+    for (i, photo) in photos.iter().enumerate() {
+        let path = std::path::Path::new(outdir).join(format!("photo_{:02}.png", i + 1));
+        if let Err(errmsg) = gb_em::camera::save_grayscale_png(&path, photo, gb_em::camera::CAMERA_W, gb_em::camera::CAMERA_H) {
+            warn(errmsg);
+            return EXITCODE_CPULOADFAILS;
+        }
+    }
+
+    EXITCODE_SUCCESS
+}
+
 fn spawn_stdin_channel() -> Receiver<u8> {
     let (tx, rx) = mpsc::channel::<u8>();
     thread::spawn(move || loop {
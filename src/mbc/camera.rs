@@ -0,0 +1,265 @@
+use crate::camera::{ImageSource, TestPattern, CAMERA_W, CAMERA_H};
+use crate::mbc::{MBC, rom_banks};
+use crate::StrResult;
+
+const RAM_SIZE: usize = 0x20000;
+const REGISTER_COUNT: usize = 0x36;
+const DITHER_START: usize = 0x06;
+
+// The processed image is written to the first RAM bank as 16 x 14 tiles
+const IMAGE_START: usize = 0x0100;
+
+const GAIN: [f32; 32] = [
+    0.880939, 0.9149149, 0.9457498, 0.9739758, 1.0000000, 1.0241412, 1.0466537, 1.0677433,
+    1.0875793, 1.124031, 1.1568911, 1.1868043, 1.214256, 1.2396208, 1.2743837, 1.3157323,
+    1.352519, 1.3856512, 1.4157897, 1.4434309, 1.4689574, 1.4926697, 1.5148087, 1.5355703,
+    1.5551159, 1.5735801, 1.5910762, 1.6077008, 1.6235366, 1.638655, 1.6531183, 1.6669808,
+];
+const EDGE_RATIO: [f32; 8] = [0.5, 0.75, 1.0, 1.25, 2.0, 3.0, 4.0, 5.0];
+
+/// MAC-GBD mapper of the Pocket Camera, with the M64282FP image sensor. Writing a RAM bank with
+/// bit 4 set maps the sensor registers instead of RAM:
+/// 0x00 capture start and busy flag, 0x01 gain and edge mode, 0x02-0x03 exposure time,
+/// 0x04 edge ratio, 0x06-0x35 the 4x4 dither matrix with three thresholds per pixel.
+pub struct PocketCamera {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    rombank: usize,
+    rombanks: usize,
+    rambank: usize,
+    ram_on: bool,
+    registers_mapped: bool,
+    registers: [u8; REGISTER_COUNT],
+    capture_ticks: u32,
+    capture_image: Vec<u8>,
+    source: Box<dyn ImageSource>,
+    ram_updated: bool,
+}
+
+impl PocketCamera {
+    pub fn new(data: Vec<u8>) -> StrResult<PocketCamera> {
+        let rombanks = rom_banks(data[0x148]);
+        if rombanks == 0 { return Err("Unsupported ROM size for Pocket Camera"); }
+
+        let res = PocketCamera {
+            rom: data,
+            ram: vec![0u8; RAM_SIZE],
+            rombank: 1,
+            rombanks,
+            rambank: 0,
+            ram_on: false,
+            registers_mapped: false,
+            registers: [0; REGISTER_COUNT],
+            capture_ticks: 0,
+            capture_image: vec![],
+            source: Box::new(TestPattern),
+            ram_updated: false,
+        };
+
+        Ok(res)
+    }
+
+    fn busy(&self) -> bool {
+        self.registers[0] & 0x01 == 0x01
+    }
+
+    fn write_register(&mut self, a: u16, v: u8) {
+        let reg = (a & 0x7F) as usize;
+        if reg == 0 {
+            if v & 0x01 == 0x01 && !self.busy() {
+                self.start_capture();
+            }
+            // A capture can not be cancelled
+            self.registers[0] = (v & 0x06) | (self.registers[0] & 0x01);
+        }
+        else if reg < REGISTER_COUNT {
+            self.registers[reg] = v;
+        }
+    }
+
+    fn start_capture(&mut self) {
+        // Capture time in clock cycles, which depends on the exposure time
+        let exposure = ((self.registers[2] as u32) << 8) | self.registers[3] as u32;
+        let n_bit = if self.registers[1] & 0x80 == 0x80 { 0 } else { 2048 };
+        self.capture_ticks = 129784 + n_bit + exposure * 64;
+        self.registers[0] |= 0x01;
+
+        self.capture_image = self.source.capture();
+        self.capture_image.resize(CAMERA_W * CAMERA_H, 0);
+    }
+
+    fn finish_capture(&mut self) {
+        for y in 0 .. CAMERA_H {
+            for x in 0 .. CAMERA_W {
+                let color = self.processed_pixel(x, y);
+                let addr = IMAGE_START + ((y / 8) * (CAMERA_W / 8) + x / 8) * 16 + (y % 8) * 2;
+                let bit = 7 - (x % 8);
+                self.ram[addr] = (self.ram[addr] & !(1 << bit)) | ((color & 1) << bit);
+                self.ram[addr + 1] = (self.ram[addr + 1] & !(1 << bit)) | (((color >> 1) & 1) << bit);
+            }
+        }
+        self.registers[0] &= !0x01;
+        self.ram_updated = true;
+    }
+
+    // Sensor output after gain and exposure, for a pixel clamped to the image
+    fn sensor_value(&self, x: isize, y: isize) -> f32 {
+        let x = x.clamp(0, CAMERA_W as isize - 1) as usize;
+        let y = y.clamp(0, CAMERA_H as isize - 1) as usize;
+        let exposure = ((self.registers[2] as u32) << 8) | self.registers[3] as u32;
+        let value = self.capture_image[y * CAMERA_W + x] as f32 * GAIN[(self.registers[1] & 0x1F) as usize];
+        value * exposure as f32 / 4096.0
+    }
+
+    // Color index of a pixel after edge enhancement and dithering
+    fn processed_pixel(&self, x: usize, y: usize) -> u8 {
+        let (xi, yi) = (x as isize, y as isize);
+        let mut value = self.sensor_value(xi, yi);
+        if self.registers[1] & 0xE0 == 0xE0 {
+            let ratio = EDGE_RATIO[((self.registers[4] >> 4) & 0x7) as usize];
+            let neighbours = self.sensor_value(xi - 1, yi) + self.sensor_value(xi + 1, yi)
+                + self.sensor_value(xi, yi - 1) + self.sensor_value(xi, yi + 1);
+            value += (value * 4.0 - neighbours) * ratio;
+        }
+
+        let thresholds = &self.registers[DITHER_START + ((x & 3) + (y & 3) * 4) * 3 ..];
+        match thresholds.iter().take(3).position(|&t| value < t as f32) {
+            Some(i) => 3 - i as u8,
+            None => 0,
+        }
+    }
+}
+
+impl MBC for PocketCamera {
+    fn readrom(&self, a: u16) -> u8 {
+        let idx = if a < 0x4000 { a as usize }
+        else { (self.rombank * 0x4000) | ((a as usize) & 0x3FFF) };
+        *self.rom.get(idx).unwrap_or(&0xFF)
+    }
+    fn readram(&self, a: u16) -> u8 {
+        if self.registers_mapped {
+            // Only the busy flag can be read back
+            return if a & 0x7F == 0 { self.registers[0] } else { 0x00 };
+        }
+        if !self.ram_on { return 0xFF }
+        // The sensor controller owns the RAM during a capture
+        if self.busy() { return 0x00 }
+        self.ram[(self.rambank * 0x2000) | ((a as usize) & 0x1FFF)]
+    }
+    fn writerom(&mut self, a: u16, v: u8) {
+        match a {
+            0x0000 ..= 0x1FFF => self.ram_on = v & 0x0F == 0x0A,
+            0x2000 ..= 0x3FFF => self.rombank = ((v & 0x3F) as usize) % self.rombanks,
+            0x4000 ..= 0x5FFF => {
+                self.registers_mapped = v & 0x10 == 0x10;
+                self.rambank = (v & 0x0F) as usize;
+            },
+            0x6000 ..= 0x7FFF => {},
+            _ => panic!("Could not write to {:04X} (Pocket Camera)", a),
+        }
+    }
+    fn writeram(&mut self, a: u16, v: u8) {
+        if self.registers_mapped {
+            self.write_register(a, v);
+            return;
+        }
+        if !self.ram_on || self.busy() { return }
+        self.ram[(self.rambank * 0x2000) | ((a as usize) & 0x1FFF)] = v;
+        self.ram_updated = true;
+    }
+
+    fn is_battery_backed(&self) -> bool {
+        true
+    }
+
+    fn loadram(&mut self, ramdata: &[u8]) -> StrResult<()> {
+        if ramdata.len() != self.ram.len() {
+            return Err("Loaded ram has incorrect length");
+        }
+
+        self.ram = ramdata.to_vec();
+        Ok(())
+    }
+
+    fn dumpram(&self) -> Vec<u8> {
+        self.ram.clone()
+    }
+
+    fn check_and_reset_ram_updated(&mut self) -> bool {
+        let result = self.ram_updated;
+        self.ram_updated = false;
+        result
+    }
+
+    fn do_cycle(&mut self, ticks: u32) {
+        if self.capture_ticks == 0 { return }
+        self.capture_ticks = self.capture_ticks.saturating_sub(ticks);
+        if self.capture_ticks == 0 {
+            self.finish_capture();
+        }
+    }
+
+    fn set_image_source(&mut self, source: Box<dyn ImageSource>) {
+        self.source = source;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::PocketCamera;
+    use crate::camera::ImageSource;
+    use crate::mbc::MBC;
+
+    struct Flat(u8);
+
+    impl ImageSource for Flat {
+        fn capture(&mut self) -> Vec<u8> {
+            vec![self.0; 128 * 112]
+        }
+    }
+
+    fn camera(level: u8) -> PocketCamera {
+        let mut data = vec![0u8; 0x8000];
+        data[0x147] = 0xFC;
+        let mut mbc = PocketCamera::new(data).unwrap();
+        mbc.set_image_source(Box::new(Flat(level)));
+        mbc.writerom(0x0000, 0x0A);
+        mbc.writerom(0x4000, 0x10);
+        // Unit gain and exposure, thresholds at 0x40, 0x80 and 0xC0 for every pixel
+        mbc.writeram(0xA001, 0x04);
+        mbc.writeram(0xA002, 0x10);
+        mbc.writeram(0xA003, 0x00);
+        for i in 0 .. 16 {
+            mbc.writeram(0xA006 + i * 3, 0x40);
+            mbc.writeram(0xA007 + i * 3, 0x80);
+            mbc.writeram(0xA008 + i * 3, 0xC0);
+        }
+        mbc
+    }
+
+    fn capture(mbc: &mut PocketCamera) {
+        mbc.writeram(0xA000, 0x01);
+        assert_eq!(mbc.readram(0xA000) & 0x01, 0x01);
+        mbc.do_cycle(129784);
+        assert_eq!(mbc.readram(0xA000) & 0x01, 0x01);
+        mbc.do_cycle(0x1000 * 64 + 2048);
+        assert_eq!(mbc.readram(0xA000) & 0x01, 0x00);
+        mbc.writerom(0x4000, 0x00);
+    }
+
+    #[test]
+    fn capture_is_dithered_into_ram() {
+        let mut mbc = camera(0x90);
+        capture(&mut mbc);
+        // 0x90 falls between the second and third threshold, which is color 1
+        assert_eq!(mbc.readram(0xA100), 0xFF);
+        assert_eq!(mbc.readram(0xA101), 0x00);
+        assert_eq!(mbc.readram(0xAEFF), 0x00);
+        assert!(mbc.check_and_reset_ram_updated());
+
+        let mut mbc = camera(0x10);
+        capture(&mut mbc);
+        assert_eq!(mbc.readram(0xA100), 0xFF);
+        assert_eq!(mbc.readram(0xA101), 0xFF);
+    }
+}
@@ -0,0 +1,723 @@
+use crate::mbc::{ram_banks, rom_banks};
+use crate::StrResult;
+use std::fmt;
+use std::str::FromStr;
+
+/// The logo that the boot ROM compares against the cartridge, located at 0x0104 - 0x0133
+pub const NINTENDO_LOGO: [u8; 48] = [
+    0xCE, 0xED, 0x66, 0x66, 0xCC, 0x0D, 0x00, 0x0B, 0x03, 0x73, 0x00, 0x83, 0x00, 0x0C, 0x00, 0x0D,
+    0x00, 0x08, 0x11, 0x1F, 0x88, 0x89, 0x00, 0x0E, 0xDC, 0xCC, 0x6E, 0xE6, 0xDD, 0xDD, 0xD9, 0x99,
+    0xBB, 0xBB, 0x67, 0x63, 0x6E, 0x0E, 0xEC, 0xCC, 0xDD, 0xDC, 0x99, 0x9F, 0xBB, 0xB9, 0x33, 0x3E,
+];
+pub const LOGO_START: usize = 0x104;
+
+const TITLE_START: usize = 0x134;
+const MANUFACTURER_START: usize = 0x13F;
+const CGB_FLAG: usize = 0x143;
+const NEW_LICENSEE: usize = 0x144;
+const SGB_FLAG: usize = 0x146;
+const CARTRIDGE_TYPE: usize = 0x147;
+const ROM_SIZE: usize = 0x148;
+const RAM_SIZE: usize = 0x149;
+const DESTINATION: usize = 0x14A;
+const OLD_LICENSEE: usize = 0x14B;
+const VERSION: usize = 0x14C;
+const HEADER_CHECKSUM: usize = 0x14D;
+const GLOBAL_CHECKSUM: usize = 0x14E;
+const HEADER_END: usize = 0x150;
+
+/// The memory bank controller family used by a cartridge
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mapper {
+    RomOnly,
+    MBC1,
+    MBC2,
+    MMM01,
+    MBC3,
+    MBC5,
+    MBC6,
+    MBC7,
+    PocketCamera,
+    TAMA5,
+    HuC3,
+    HuC1,
+    /// Unlicensed 32 KiB bank switching used by Wisdom Tree games
+    WisdomTree,
+    /// Unlicensed 32 KiB bank switching that locks after the first write (Mani 4 in 1)
+    M161,
+    /// Bootleg MBC1 with the ROM bank register data lines wired out of order. Entry `i` is the
+    /// bit of the written value that ends up as bit `i` of the bank number
+    ScrambledMBC1([u8; 5]),
+    Unknown,
+}
+
+impl fmt::Display for Mapper {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match *self {
+            Mapper::RomOnly => "ROM only",
+            Mapper::MBC1 => "MBC1",
+            Mapper::MBC2 => "MBC2",
+            Mapper::MMM01 => "MMM01",
+            Mapper::MBC3 => "MBC3",
+            Mapper::MBC5 => "MBC5",
+            Mapper::MBC6 => "MBC6",
+            Mapper::MBC7 => "MBC7",
+            Mapper::PocketCamera => "Pocket Camera",
+            Mapper::TAMA5 => "TAMA5",
+            Mapper::HuC3 => "HuC3",
+            Mapper::HuC1 => "HuC1",
+            Mapper::WisdomTree => "Wisdom Tree",
+            Mapper::M161 => "M161",
+            Mapper::ScrambledMBC1(order) => {
+                let order: String = order.iter().map(|b| (b'0' + b) as char).collect();
+                return write!(f, "MBC1 (scrambled, bit order {})", order);
+            },
+            Mapper::Unknown => "Unknown",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Parses the mapper names accepted on the command line, e.g. `mbc5`, `wisdom-tree` or
+/// `mbc1-scrambled:10234`
+impl FromStr for Mapper {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> StrResult<Mapper> {
+        let lower = s.to_lowercase();
+        if let Some(order) = lower.strip_prefix("mbc1-scrambled:") {
+            let digits: Vec<u8> = order.bytes().map(|b| b.wrapping_sub(b'0')).collect();
+            let mut sorted = digits.clone();
+            sorted.sort_unstable();
+            if sorted != [0, 1, 2, 3, 4] {
+                return Err("Bit order must be a permutation of 01234");
+            }
+            return Ok(Mapper::ScrambledMBC1([digits[0], digits[1], digits[2], digits[3], digits[4]]));
+        }
+
+        Ok(match &*lower {
+            "rom" | "none" => Mapper::RomOnly,
+            "mbc1" => Mapper::MBC1,
+            "mbc2" => Mapper::MBC2,
+            "mmm01" => Mapper::MMM01,
+            "mbc3" => Mapper::MBC3,
+            "mbc5" => Mapper::MBC5,
+            "mbc6" => Mapper::MBC6,
+            "mbc7" => Mapper::MBC7,
+            "huc1" => Mapper::HuC1,
+            "huc3" => Mapper::HuC3,
+            "tama5" => Mapper::TAMA5,
+            "wisdom-tree" => Mapper::WisdomTree,
+            "m161" => Mapper::M161,
+            "camera" | "pocket-camera" => Mapper::PocketCamera,
+            _ => return Err("Unknown mapper"),
+        })
+    }
+}
+
+/// Additional hardware on the cartridge, as declared by the cartridge type
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CartridgeFeatures {
+    pub ram: bool,
+    pub battery: bool,
+    pub timer: bool,
+    pub rumble: bool,
+    pub sensor: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CgbSupport {
+    /// The game only uses DMG features
+    None,
+    /// The game uses CGB features, but also works on a DMG
+    Supported,
+    /// The game only works on a CGB
+    Required,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Destination {
+    Japan,
+    Overseas,
+}
+
+/// The decoded cartridge header, located at 0x0100 - 0x014F of the ROM
+#[derive(Debug, Clone)]
+pub struct CartridgeHeader {
+    pub title: String,
+    /// Only present in newer cartridges, where it takes the last four bytes of the title area
+    pub manufacturer_code: Option<String>,
+    pub old_licensee_code: u8,
+    /// Only used when the old licensee code is 0x33
+    pub new_licensee_code: Option<String>,
+    pub cgb_flag: u8,
+    pub cgb_support: CgbSupport,
+    pub sgb_flag: u8,
+    pub sgb_support: bool,
+    pub cartridge_type: u8,
+    pub mapper: Mapper,
+    pub features: CartridgeFeatures,
+    pub rom_size_code: u8,
+    /// ROM size in bytes, or `None` if the size code is unknown
+    pub rom_size: Option<usize>,
+    pub ram_size_code: u8,
+    /// External RAM size in bytes as declared in the header, or `None` if the size code is unknown
+    pub ram_size: Option<usize>,
+    pub destination: Destination,
+    pub version: u8,
+    pub header_checksum: u8,
+    pub header_checksum_valid: bool,
+    pub global_checksum: u16,
+    pub global_checksum_valid: bool,
+}
+
+impl CartridgeHeader {
+    /// Parses the header of a ROM. Checksums are computed and reported, but invalid checksums are
+    /// not treated as an error.
+    pub fn parse(data: &[u8]) -> StrResult<CartridgeHeader> {
+        if data.len() < HEADER_END { return Err("Rom size to small"); }
+
+        let cgb_flag = data[CGB_FLAG];
+        let manufacturer = &data[MANUFACTURER_START .. CGB_FLAG];
+        let manufacturer_code = if cgb_flag & 0x80 == 0x80 && manufacturer.iter().all(|c| c.is_ascii_uppercase() || c.is_ascii_digit()) {
+            Some(String::from_utf8_lossy(manufacturer).into_owned())
+        } else {
+            None
+        };
+        let title_end = match (cgb_flag & 0x80, &manufacturer_code) {
+            (_, Some(_)) => MANUFACTURER_START,
+            (0x80, None) => CGB_FLAG,
+            _ => CGB_FLAG + 1,
+        };
+        let title = data[TITLE_START .. title_end].iter()
+            .take_while(|&&c| c != 0)
+            .map(|&c| if c.is_ascii_graphic() || c == b' ' { c as char } else { '?' })
+            .collect();
+
+        let old_licensee_code = data[OLD_LICENSEE];
+        let new_licensee_code = match old_licensee_code {
+            0x33 => Some(String::from_utf8_lossy(&data[NEW_LICENSEE .. NEW_LICENSEE + 2]).into_owned()),
+            _ => None,
+        };
+
+        let cartridge_type = data[CARTRIDGE_TYPE];
+        let (mapper, features) = decode_cartridge_type(cartridge_type);
+
+        let rom_size_code = data[ROM_SIZE];
+        let rom_size = match rom_banks(rom_size_code) {
+            0 => None,
+            n => Some(n * 0x4000),
+        };
+        let ram_size_code = data[RAM_SIZE];
+        let ram_size = match ram_size_code {
+            0 => Some(0),
+            1 => Some(0x800),
+            2 ..= 5 => Some(ram_banks(ram_size_code) * 0x2000),
+            _ => None,
+        };
+
+        let header_checksum = data[HEADER_CHECKSUM];
+        let global_checksum = ((data[GLOBAL_CHECKSUM] as u16) << 8) | (data[GLOBAL_CHECKSUM + 1] as u16);
+
+        Ok(CartridgeHeader {
+            title,
+            manufacturer_code,
+            old_licensee_code,
+            new_licensee_code,
+            cgb_flag,
+            cgb_support: match cgb_flag {
+                0xC0 => CgbSupport::Required,
+                v if v & 0x80 == 0x80 => CgbSupport::Supported,
+                _ => CgbSupport::None,
+            },
+            sgb_flag: data[SGB_FLAG],
+            sgb_support: data[SGB_FLAG] == 0x03,
+            cartridge_type,
+            mapper,
+            features,
+            rom_size_code,
+            rom_size,
+            ram_size_code,
+            ram_size,
+            destination: if data[DESTINATION] == 0x00 { Destination::Japan } else { Destination::Overseas },
+            version: data[VERSION],
+            header_checksum,
+            header_checksum_valid: compute_header_checksum(data) == header_checksum,
+            global_checksum,
+            global_checksum_valid: compute_global_checksum(data) == global_checksum,
+        })
+    }
+
+    /// Name of the publisher, if the licensee code is known
+    pub fn licensee_name(&self) -> Option<&'static str> {
+        match self.new_licensee_code {
+            Some(ref code) => new_licensee_name(code),
+            None => old_licensee_name(self.old_licensee_code),
+        }
+    }
+
+    /// Name of the cartridge type as listed in the documentation, e.g. "MBC3+RAM+BATTERY"
+    pub fn cartridge_type_name(&self) -> Option<&'static str> {
+        cartridge_type_name(self.cartridge_type)
+    }
+}
+
+/// A problem with a ROM that does not prevent it from being loaded
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CartridgeWarning {
+    /// The file size differs from the ROM size in the header. Banks beyond the end of the file
+    /// read as 0xFF
+    RomSizeMismatch { header: usize, file: usize },
+    UnknownRomSize(u8),
+    /// The RAM size in the header does not fit the mapper
+    RamSizeMismatch { mapper: Mapper, ram_size: usize },
+    UnknownRamSize(u8),
+    /// The Nintendo logo is not intact, so real hardware would refuse to boot the cartridge
+    InvalidLogo,
+    GlobalChecksumMismatch { header: u16, computed: u16 },
+}
+
+impl fmt::Display for CartridgeWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            CartridgeWarning::RomSizeMismatch { header, file } if file < header =>
+                write!(f, "ROM file is {} bytes, but the header declares {} bytes; the missing data reads as 0xFF", file, header),
+            CartridgeWarning::RomSizeMismatch { header, file } =>
+                write!(f, "ROM file is {} bytes, but the header declares {} bytes", file, header),
+            CartridgeWarning::UnknownRomSize(code) => write!(f, "Unknown ROM size code {:02X}", code),
+            CartridgeWarning::RamSizeMismatch { mapper, ram_size: 0 } =>
+                write!(f, "Cartridge type declares RAM, but the RAM size is 0 for {}", mapper),
+            CartridgeWarning::RamSizeMismatch { mapper, ram_size } =>
+                write!(f, "RAM size of {} KiB is not supported by {}", ram_size / 1024, mapper),
+            CartridgeWarning::UnknownRamSize(code) => write!(f, "Unknown RAM size code {:02X}", code),
+            CartridgeWarning::InvalidLogo => write!(f, "Nintendo logo is invalid"),
+            CartridgeWarning::GlobalChecksumMismatch { header, computed } =>
+                write!(f, "Global checksum is {:04X}, but the ROM sums to {:04X}", header, computed),
+        }
+    }
+}
+
+/// Checks a ROM for problems that do not stop it from running: its size, the RAM size for its
+/// mapper, the Nintendo logo and the global checksum. The header checksum is checked on load.
+pub fn validate_rom(data: &[u8]) -> Vec<CartridgeWarning> {
+    let header = match CartridgeHeader::parse(data) {
+        Ok(h) => h,
+        Err(_) => return vec![],
+    };
+    let mut warnings = vec![];
+
+    match header.rom_size {
+        Some(size) if size != data.len() => warnings.push(CartridgeWarning::RomSizeMismatch { header: size, file: data.len() }),
+        Some(_) => {},
+        None => warnings.push(CartridgeWarning::UnknownRomSize(header.rom_size_code)),
+    }
+
+    match header.ram_size {
+        Some(ram_size) => {
+            let fits = match header.mapper {
+                // These carry their own memory and declare no RAM size
+                Mapper::MBC2 | Mapper::MBC6 | Mapper::MBC7 | Mapper::TAMA5 => ram_size == 0,
+                Mapper::MBC1 if ram_size > 0x8000 => false,
+                Mapper::Unknown => true,
+                _ => header.features.ram == (ram_size > 0),
+            };
+            if !fits {
+                warnings.push(CartridgeWarning::RamSizeMismatch { mapper: header.mapper, ram_size });
+            }
+        },
+        None => warnings.push(CartridgeWarning::UnknownRamSize(header.ram_size_code)),
+    }
+
+    if data[LOGO_START .. LOGO_START + NINTENDO_LOGO.len()] != NINTENDO_LOGO {
+        warnings.push(CartridgeWarning::InvalidLogo);
+    }
+
+    if !header.global_checksum_valid {
+        warnings.push(CartridgeWarning::GlobalChecksumMismatch { header: header.global_checksum, computed: compute_global_checksum(data) });
+    }
+
+    warnings
+}
+
+pub fn compute_header_checksum(data: &[u8]) -> u8 {
+    let mut value: u8 = 0;
+    for &v in &data[TITLE_START .. HEADER_CHECKSUM] {
+        value = value.wrapping_sub(v).wrapping_sub(1);
+    }
+    value
+}
+
+pub fn compute_global_checksum(data: &[u8]) -> u16 {
+    data.iter().enumerate()
+        .filter(|&(i, _)| i != GLOBAL_CHECKSUM && i != GLOBAL_CHECKSUM + 1)
+        .fold(0u16, |acc, (_, &v)| acc.wrapping_add(v as u16))
+}
+
+fn decode_cartridge_type(v: u8) -> (Mapper, CartridgeFeatures) {
+    let (mapper, ram, battery, timer, rumble, sensor) = match v {
+        0x00 => (Mapper::RomOnly, false, false, false, false, false),
+        0x01 => (Mapper::MBC1, false, false, false, false, false),
+        0x02 => (Mapper::MBC1, true, false, false, false, false),
+        0x03 => (Mapper::MBC1, true, true, false, false, false),
+        0x05 => (Mapper::MBC2, false, false, false, false, false),
+        0x06 => (Mapper::MBC2, false, true, false, false, false),
+        0x08 => (Mapper::RomOnly, true, false, false, false, false),
+        0x09 => (Mapper::RomOnly, true, true, false, false, false),
+        0x0B => (Mapper::MMM01, false, false, false, false, false),
+        0x0C => (Mapper::MMM01, true, false, false, false, false),
+        0x0D => (Mapper::MMM01, true, true, false, false, false),
+        0x0F => (Mapper::MBC3, false, true, true, false, false),
+        0x10 => (Mapper::MBC3, true, true, true, false, false),
+        0x11 => (Mapper::MBC3, false, false, false, false, false),
+        0x12 => (Mapper::MBC3, true, false, false, false, false),
+        0x13 => (Mapper::MBC3, true, true, false, false, false),
+        0x19 => (Mapper::MBC5, false, false, false, false, false),
+        0x1A => (Mapper::MBC5, true, false, false, false, false),
+        0x1B => (Mapper::MBC5, true, true, false, false, false),
+        0x1C => (Mapper::MBC5, false, false, false, true, false),
+        0x1D => (Mapper::MBC5, true, false, false, true, false),
+        0x1E => (Mapper::MBC5, true, true, false, true, false),
+        0x20 => (Mapper::MBC6, true, true, false, false, false),
+        0x22 => (Mapper::MBC7, true, true, false, true, true),
+        0xFC => (Mapper::PocketCamera, true, true, false, false, false),
+        0xFD => (Mapper::TAMA5, true, true, true, false, false),
+        0xFE => (Mapper::HuC3, true, true, true, false, false),
+        0xFF => (Mapper::HuC1, true, true, false, false, false),
+        _ => (Mapper::Unknown, false, false, false, false, false),
+    };
+    (mapper, CartridgeFeatures { ram, battery, timer, rumble, sensor })
+}
+
+fn cartridge_type_name(v: u8) -> Option<&'static str> {
+    Some(match v {
+        0x00 => "ROM ONLY",
+        0x01 => "MBC1",
+        0x02 => "MBC1+RAM",
+        0x03 => "MBC1+RAM+BATTERY",
+        0x05 => "MBC2",
+        0x06 => "MBC2+BATTERY",
+        0x08 => "ROM+RAM",
+        0x09 => "ROM+RAM+BATTERY",
+        0x0B => "MMM01",
+        0x0C => "MMM01+RAM",
+        0x0D => "MMM01+RAM+BATTERY",
+        0x0F => "MBC3+TIMER+BATTERY",
+        0x10 => "MBC3+TIMER+RAM+BATTERY",
+        0x11 => "MBC3",
+        0x12 => "MBC3+RAM",
+        0x13 => "MBC3+RAM+BATTERY",
+        0x19 => "MBC5",
+        0x1A => "MBC5+RAM",
+        0x1B => "MBC5+RAM+BATTERY",
+        0x1C => "MBC5+RUMBLE",
+        0x1D => "MBC5+RUMBLE+RAM",
+        0x1E => "MBC5+RUMBLE+RAM+BATTERY",
+        0x20 => "MBC6",
+        0x22 => "MBC7+SENSOR+RUMBLE+RAM+BATTERY",
+        0xFC => "POCKET CAMERA",
+        0xFD => "BANDAI TAMA5",
+        0xFE => "HuC3",
+        0xFF => "HuC1+RAM+BATTERY",
+        _ => return None,
+    })
+}
+
+fn new_licensee_name(code: &str) -> Option<&'static str> {
+    Some(match code {
+        "00" => "None",
+        "01" => "Nintendo Research & Development 1",
+        "08" => "Capcom",
+        "13" => "EA (Electronic Arts)",
+        "18" => "Hudson Soft",
+        "19" => "B-AI",
+        "20" => "KSS",
+        "22" => "Planning Office WADA",
+        "24" => "PCM Complete",
+        "25" => "San-X",
+        "28" => "Kemco",
+        "29" => "SETA Corporation",
+        "30" => "Viacom",
+        "31" => "Nintendo",
+        "32" => "Bandai",
+        "33" => "Ocean Software/Acclaim Entertainment",
+        "34" => "Konami",
+        "35" => "HectorSoft",
+        "37" => "Taito",
+        "38" => "Hudson Soft",
+        "39" => "Banpresto",
+        "41" => "Ubi Soft",
+        "42" => "Atlus",
+        "44" => "Malibu Interactive",
+        "46" => "Angel",
+        "47" => "Bullet-Proof Software",
+        "49" => "Irem",
+        "50" => "Absolute",
+        "51" => "Acclaim Entertainment",
+        "52" => "Activision",
+        "53" => "Sammy USA Corporation",
+        "54" => "Konami",
+        "55" => "Hi Tech Expressions",
+        "56" => "LJN",
+        "57" => "Matchbox",
+        "58" => "Mattel",
+        "59" => "Milton Bradley Company",
+        "60" => "Titus Interactive",
+        "61" => "Virgin Games Ltd.",
+        "64" => "Lucasfilm Games",
+        "67" => "Ocean Software",
+        "69" => "EA (Electronic Arts)",
+        "70" => "Infogrames",
+        "71" => "Interplay Entertainment",
+        "72" => "Broderbund",
+        "73" => "Sculptured Software",
+        "75" => "The Sales Curve Limited",
+        "78" => "THQ",
+        "79" => "Accolade",
+        "80" => "Misawa Entertainment",
+        "83" => "lozc",
+        "86" => "Tokuma Shoten",
+        "87" => "Tsukuda Original",
+        "91" => "Chunsoft Co.",
+        "92" => "Video System",
+        "93" => "Ocean Software/Acclaim Entertainment",
+        "95" => "Varie",
+        "96" => "Yonezawa/s'pal",
+        "97" => "Kaneko",
+        "99" => "Pack-In-Video",
+        "9H" => "Bottom Up",
+        "A4" => "Konami (Yu-Gi-Oh!)",
+        "BL" => "MTO",
+        "DK" => "Kodansha",
+        _ => return None,
+    })
+}
+
+fn old_licensee_name(code: u8) -> Option<&'static str> {
+    Some(match code {
+        0x00 => "None",
+        0x01 => "Nintendo",
+        0x08 => "Capcom",
+        0x09 => "HOT-B",
+        0x0A => "Jaleco",
+        0x0B => "Coconuts Japan",
+        0x0C => "Elite Systems",
+        0x13 => "EA (Electronic Arts)",
+        0x18 => "Hudson Soft",
+        0x19 => "ITC Entertainment",
+        0x1A => "Yanoman",
+        0x1D => "Japan Clary",
+        0x1F => "Virgin Games Ltd.",
+        0x24 => "PCM Complete",
+        0x25 => "San-X",
+        0x28 => "Kemco",
+        0x29 => "SETA Corporation",
+        0x30 => "Infogrames",
+        0x31 => "Nintendo",
+        0x32 => "Bandai",
+        0x34 => "Konami",
+        0x35 => "HectorSoft",
+        0x38 => "Capcom",
+        0x39 => "Banpresto",
+        0x3C => "Entertainment Interactive",
+        0x3E => "Gremlin",
+        0x41 => "Ubi Soft",
+        0x42 => "Atlus",
+        0x44 => "Malibu Interactive",
+        0x46 => "Angel",
+        0x47 => "Spectrum HoloByte",
+        0x49 => "Irem",
+        0x4A => "Virgin Games Ltd.",
+        0x4D => "Malibu Interactive",
+        0x4F => "U.S. Gold",
+        0x50 => "Absolute",
+        0x51 => "Acclaim Entertainment",
+        0x52 => "Activision",
+        0x53 => "Sammy USA Corporation",
+        0x54 => "GameTek",
+        0x55 => "Park Place",
+        0x56 => "LJN",
+        0x57 => "Matchbox",
+        0x59 => "Milton Bradley Company",
+        0x5A => "Mindscape",
+        0x5B => "Romstar",
+        0x5C => "Naxat Soft",
+        0x5D => "Tradewest",
+        0x60 => "Titus Interactive",
+        0x61 => "Virgin Games Ltd.",
+        0x67 => "Ocean Software",
+        0x69 => "EA (Electronic Arts)",
+        0x6E => "Elite Systems",
+        0x6F => "Electro Brain",
+        0x70 => "Infogrames",
+        0x71 => "Interplay Entertainment",
+        0x72 => "Broderbund",
+        0x73 => "Sculptured Software",
+        0x75 => "The Sales Curve Limited",
+        0x78 => "THQ",
+        0x79 => "Accolade",
+        0x7A => "Triffix Entertainment",
+        0x7C => "MicroProse",
+        0x7F => "Kemco",
+        0x80 => "Misawa Entertainment",
+        0x83 => "LOZC G.",
+        0x86 => "Tokuma Shoten",
+        0x8B => "Bullet-Proof Software",
+        0x8C => "Vic Tokai Corp.",
+        0x8E => "Ape Inc.",
+        0x8F => "I'Max",
+        0x91 => "Chunsoft Co.",
+        0x92 => "Video System",
+        0x93 => "Tsubaraya Productions",
+        0x95 => "Varie",
+        0x96 => "Yonezawa/S'Pal",
+        0x97 => "Kemco",
+        0x99 => "Arc",
+        0x9A => "Nihon Bussan",
+        0x9B => "Tecmo",
+        0x9C => "Imagineer",
+        0x9D => "Banpresto",
+        0x9F => "Nova",
+        0xA1 => "Hori Electric",
+        0xA2 => "Bandai",
+        0xA4 => "Konami",
+        0xA6 => "Kawada",
+        0xA7 => "Takara",
+        0xA9 => "Technos Japan",
+        0xAA => "Broderbund",
+        0xAC => "Toei Animation",
+        0xAD => "Toho",
+        0xAF => "Namco",
+        0xB0 => "Acclaim Entertainment",
+        0xB1 => "ASCII Corporation or Nexsoft",
+        0xB2 => "Bandai",
+        0xB4 => "Square Enix",
+        0xB6 => "HAL Laboratory",
+        0xB7 => "SNK",
+        0xB9 => "Pony Canyon",
+        0xBA => "Culture Brain",
+        0xBB => "Sunsoft",
+        0xBD => "Sony Imagesoft",
+        0xBF => "Sammy Corporation",
+        0xC0 => "Taito",
+        0xC2 => "Kemco",
+        0xC3 => "Square",
+        0xC4 => "Tokuma Shoten",
+        0xC5 => "Data East",
+        0xC6 => "Tonkin House",
+        0xC8 => "Koei",
+        0xC9 => "UFL",
+        0xCA => "Ultra Games",
+        0xCB => "VAP, Inc.",
+        0xCC => "Use Corporation",
+        0xCD => "Meldac",
+        0xCE => "Pony Canyon",
+        0xCF => "Angel",
+        0xD0 => "Taito",
+        0xD1 => "SOFEL",
+        0xD2 => "Quest",
+        0xD3 => "Sigma Enterprises",
+        0xD4 => "ASK Kodansha Co.",
+        0xD6 => "Naxat Soft",
+        0xD7 => "Copya System",
+        0xD9 => "Banpresto",
+        0xDA => "Tomy",
+        0xDB => "LJN",
+        0xDD => "Nippon Computer Systems",
+        0xDE => "Human Ent.",
+        0xDF => "Altron",
+        0xE0 => "Jaleco",
+        0xE1 => "Towa Chiki",
+        0xE2 => "Yutaka",
+        0xE3 => "Varie",
+        0xE5 => "Epoch",
+        0xE7 => "Athena",
+        0xE8 => "Asmik Ace Entertainment",
+        0xE9 => "Natsume",
+        0xEA => "King Records",
+        0xEB => "Atlus",
+        0xEC => "Epic/Sony Records",
+        0xEE => "IGS",
+        0xF0 => "A Wave",
+        0xF3 => "Extreme Entertainment",
+        0xFF => "LJN",
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{CartridgeHeader, CartridgeWarning, CgbSupport, Destination, Mapper};
+
+    fn rom() -> Vec<u8> {
+        let mut data = vec![0u8; 0x8000];
+        data[0x134 .. 0x13F].copy_from_slice(b"TESTGAME\0\0\0");
+        data[0x13F .. 0x143].copy_from_slice(b"ABCE");
+        data[0x143] = 0x80;
+        data[0x144 .. 0x146].copy_from_slice(b"01");
+        data[0x146] = 0x03;
+        data[0x147] = 0x1B;
+        data[0x148] = 0x00;
+        data[0x149] = 0x03;
+        data[0x14A] = 0x01;
+        data[0x14B] = 0x33;
+        data[0x14C] = 0x02;
+        data[0x104 .. 0x134].copy_from_slice(&super::NINTENDO_LOGO);
+        data[0x14D] = super::compute_header_checksum(&data);
+        let global = super::compute_global_checksum(&data);
+        data[0x14E] = (global >> 8) as u8;
+        data[0x14F] = global as u8;
+        data
+    }
+
+    #[test]
+    fn parse_header() {
+        let header = CartridgeHeader::parse(&rom()).unwrap();
+        assert_eq!(header.title, "TESTGAME");
+        assert_eq!(header.manufacturer_code.as_deref(), Some("ABCE"));
+        assert_eq!(header.new_licensee_code.as_deref(), Some("01"));
+        assert_eq!(header.cgb_support, CgbSupport::Supported);
+        assert!(header.sgb_support);
+        assert_eq!(header.mapper, Mapper::MBC5);
+        assert!(header.features.ram && header.features.battery && !header.features.rumble);
+        assert_eq!(header.rom_size, Some(0x8000));
+        assert_eq!(header.ram_size, Some(0x8000));
+        assert_eq!(header.destination, Destination::Overseas);
+        assert_eq!(header.version, 2);
+        assert!(header.header_checksum_valid);
+        assert!(header.global_checksum_valid);
+    }
+
+    #[test]
+    fn invalid_checksums_are_reported() {
+        let mut data = rom();
+        data[0x200] ^= 0xFF;
+        data[0x14D] ^= 0xFF;
+        let header = CartridgeHeader::parse(&data).unwrap();
+        assert!(!header.header_checksum_valid);
+        assert!(!header.global_checksum_valid);
+    }
+
+    #[test]
+    fn validation_warnings() {
+        assert_eq!(super::validate_rom(&rom()), vec![]);
+
+        let mut data = rom();
+        data.truncate(0x4000);
+        data[0x104] = 0x00;
+        data[0x147] = 0x01;
+        assert_eq!(super::validate_rom(&data), vec![
+            CartridgeWarning::RomSizeMismatch { header: 0x8000, file: 0x4000 },
+            CartridgeWarning::RamSizeMismatch { mapper: Mapper::MBC1, ram_size: 0x8000 },
+            CartridgeWarning::InvalidLogo,
+            CartridgeWarning::GlobalChecksumMismatch { header: u16::from_be_bytes([data[0x14E], data[0x14F]]), computed: super::compute_global_checksum(&data) },
+        ]);
+    }
+
+    #[test]
+    fn parse_mapper() {
+        assert_eq!("TAMA5".parse(), Ok(Mapper::TAMA5));
+        assert_eq!("pocket-camera".parse(), Ok(Mapper::PocketCamera));
+        assert_eq!("camera".parse(), Ok(Mapper::PocketCamera));
+        assert!("mbc4".parse::<Mapper>().is_err());
+    }
+}
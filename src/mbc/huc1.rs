@@ -0,0 +1,139 @@
+use crate::mbc::{MBC, ram_banks, rom_banks};
+use crate::StrResult;
+
+// Value read from the IR register when no light is received
+const IR_NO_LIGHT: u8 = 0xC0;
+
+pub struct HuC1 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    rombank: usize,
+    rombanks: usize,
+    rambank: usize,
+    rambanks: usize,
+    ir_mode: bool,
+    ir_led: bool,
+    ram_updated: bool,
+}
+
+impl HuC1 {
+    pub fn new(data: Vec<u8>) -> StrResult<HuC1> {
+        let rombanks = rom_banks(data[0x148]);
+        if rombanks == 0 { return Err("Unsupported ROM size for HuC1"); }
+        let rambanks = match data[0x149] {
+            0 => 0,
+            n => ram_banks(n),
+        };
+
+        let res = HuC1 {
+            rom: data,
+            ram: ::std::iter::repeat(0u8).take(rambanks * 0x2000).collect(),
+            rombank: 1,
+            rombanks,
+            rambank: 0,
+            rambanks,
+            ir_mode: false,
+            ir_led: false,
+            ram_updated: false,
+        };
+
+        Ok(res)
+    }
+
+    fn ramaddress(&self, a: u16) -> Option<usize> {
+        if self.rambanks == 0 { return None }
+        Some(((self.rambank % self.rambanks) * 0x2000) | ((a as usize) & 0x1FFF))
+    }
+}
+
+impl MBC for HuC1 {
+    fn readrom(&self, a: u16) -> u8 {
+        let idx = if a < 0x4000 { a as usize }
+        else { (self.rombank * 0x4000) | ((a as usize) & 0x3FFF) };
+        *self.rom.get(idx).unwrap_or(&0xFF)
+    }
+    fn readram(&self, a: u16) -> u8 {
+        if self.ir_mode { return IR_NO_LIGHT }
+        match self.ramaddress(a) {
+            Some(idx) => self.ram[idx],
+            None => 0xFF,
+        }
+    }
+    fn writerom(&mut self, a: u16, v: u8) {
+        match a {
+            // 0x0E maps the IR register, anything else maps RAM
+            0x0000 ..= 0x1FFF => self.ir_mode = v & 0x0F == 0x0E,
+            0x2000 ..= 0x3FFF => self.rombank = ((v & 0x3F) as usize) % self.rombanks,
+            0x4000 ..= 0x5FFF => self.rambank = (v & 0x03) as usize,
+            0x6000 ..= 0x7FFF => {},
+            _ => panic!("Could not write to {:04X} (HuC1)", a),
+        }
+    }
+    fn writeram(&mut self, a: u16, v: u8) {
+        if self.ir_mode {
+            self.ir_led = v & 0x01 == 0x01;
+            return;
+        }
+        if let Some(idx) = self.ramaddress(a) {
+            self.ram[idx] = v;
+            self.ram_updated = true;
+        }
+    }
+
+    fn is_battery_backed(&self) -> bool {
+        true
+    }
+
+    fn loadram(&mut self, ramdata: &[u8]) -> StrResult<()> {
+        if ramdata.len() != self.ram.len() {
+            return Err("Loaded ram has incorrect length");
+        }
+
+        self.ram = ramdata.to_vec();
+        Ok(())
+    }
+
+    fn dumpram(&self) -> Vec<u8> {
+        self.ram.clone()
+    }
+
+    fn check_and_reset_ram_updated(&mut self) -> bool {
+        let result = self.ram_updated;
+        self.ram_updated = false;
+        result
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::HuC1;
+    use crate::mbc::MBC;
+
+    #[test]
+    fn banking_and_ir() {
+        let mut data = vec![0u8; 0x10000];
+        data[0x147] = 0xFF;
+        data[0x148] = 0x01;
+        data[0x149] = 0x03;
+        data[0xC000] = 0x42;
+        let mut mbc = HuC1::new(data).unwrap();
+
+        mbc.writerom(0x2000, 0x03);
+        assert_eq!(mbc.readrom(0x4000), 0x42);
+
+        mbc.writerom(0x4000, 0x02);
+        mbc.writeram(0xA000, 0x12);
+        assert!(mbc.check_and_reset_ram_updated());
+
+        // In IR mode RAM is not accessible and nothing is received
+        mbc.writerom(0x0000, 0x0E);
+        assert_eq!(mbc.readram(0xA000), 0xC0);
+        mbc.writeram(0xA000, 0x01);
+        assert!(mbc.ir_led);
+        assert!(!mbc.check_and_reset_ram_updated());
+
+        mbc.writerom(0x0000, 0x0A);
+        assert_eq!(mbc.readram(0xA000), 0x12);
+        assert_eq!(mbc.dumpram()[0x4000], 0x12);
+    }
+}
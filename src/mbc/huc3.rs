@@ -0,0 +1,356 @@
+use crate::mbc::{MBC, ram_banks, rom_banks};
+use crate::StrResult;
+
+use std::time;
+use std::convert::TryInto;
+
+// Value read from the IR register when no light is received
+const IR_NO_LIGHT: u8 = 0xC0;
+
+const MINUTES_PER_DAY: u64 = 24 * 60;
+
+const RTC_MEM_SIZE: usize = 0x100;
+
+// Extended commands, selected by the argument of command 0x6
+const EXT_STATUS: u8 = 0x2;
+const EXT_TONE: u8 = 0xE;
+
+/// HuC3, with an RTC and a piezo speaker behind a nibble based command interface. The mode
+/// register at 0000-1FFF selects what is mapped at A000-BFFF:
+/// 0x0 RAM (read only), 0xA RAM, 0xB command, 0xC response, 0xD semaphore and 0xE IR.
+/// The save holds the unix time at which the clock read zero as 8 bytes, followed by the RTC
+/// memory, the extended command and the cartridge RAM. Saves holding only the time and the RAM
+/// are also accepted.
+pub struct HuC3 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    rombank: usize,
+    rombanks: usize,
+    rambank: usize,
+    rambanks: usize,
+    mode: u8,
+    ir_led: bool,
+    ram_updated: bool,
+    // Clock registers as last written or synced, and the unix time they were valid at
+    rtc_minutes: u64,
+    rtc_days: u64,
+    rtc_last: u64,
+    // RTC memory beyond the time registers (alarm and scratch), one nibble per address
+    rtc_mem: [u8; RTC_MEM_SIZE],
+    rtc_address: u8,
+    rtc_response: u8,
+    rtc_extended: u8,
+}
+
+impl HuC3 {
+    pub fn new(data: Vec<u8>) -> StrResult<HuC3> {
+        let rombanks = rom_banks(data[0x148]);
+        if rombanks == 0 { return Err("Unsupported ROM size for HuC3"); }
+        let rambanks = match data[0x149] {
+            0 => 0,
+            n => ram_banks(n),
+        };
+
+        let res = HuC3 {
+            rom: data,
+            ram: ::std::iter::repeat(0u8).take(rambanks * 0x2000).collect(),
+            rombank: 1,
+            rombanks,
+            rambank: 0,
+            rambanks,
+            mode: 0,
+            ir_led: false,
+            ram_updated: false,
+            rtc_minutes: 0,
+            rtc_days: 0,
+            rtc_last: unix_now(),
+            rtc_mem: [0; RTC_MEM_SIZE],
+            rtc_address: 0,
+            rtc_response: 0,
+            rtc_extended: 0,
+        };
+
+        Ok(res)
+    }
+
+    fn ramaddress(&self, a: u16) -> Option<usize> {
+        if self.rambanks == 0 { return None }
+        Some(((self.rambank % self.rambanks) * 0x2000) | ((a as usize) & 0x1FFF))
+    }
+
+    // Current minutes and days. The registers are only normalised once time has passed, so a
+    // value written one nibble at a time is not disturbed halfway through.
+    fn clock(&self) -> (u64, u64, u64) {
+        let elapsed = unix_now().saturating_sub(self.rtc_last) / 60;
+        if elapsed == 0 {
+            return (self.rtc_minutes, self.rtc_days, self.rtc_last);
+        }
+        let minutes = self.rtc_minutes + elapsed;
+        let days = (self.rtc_days + minutes / MINUTES_PER_DAY) & 0xFFFF;
+        (minutes % MINUTES_PER_DAY, days, self.rtc_last + elapsed * 60)
+    }
+
+    // Minutes within the day (12 bits) followed by the day counter (16 bits)
+    fn rtc_value(&self) -> u64 {
+        let (minutes, days, _) = self.clock();
+        (days << 12) | minutes
+    }
+
+    fn set_rtc_value(&mut self, value: u64) {
+        let (_, _, last) = self.clock();
+        self.rtc_minutes = value & 0xFFF;
+        self.rtc_days = (value >> 12) & 0xFFFF;
+        self.rtc_last = last;
+        self.ram_updated = true;
+    }
+
+    fn read_rtc_nibble(&self, address: u8) -> u8 {
+        match address {
+            0x00 ..= 0x06 => ((self.rtc_value() >> (address * 4)) & 0xF) as u8,
+            _ => self.rtc_mem[address as usize],
+        }
+    }
+
+    fn write_rtc_nibble(&mut self, address: u8, v: u8) {
+        match address {
+            0x00 ..= 0x06 => {
+                let shift = address * 4;
+                let value = (self.rtc_value() & !(0xF << shift)) | ((v as u64) << shift);
+                self.set_rtc_value(value);
+            },
+            _ => {
+                self.rtc_mem[address as usize] = v;
+                self.ram_updated = true;
+            },
+        }
+    }
+
+    fn command(&mut self, v: u8) {
+        let arg = v & 0x0F;
+        match v >> 4 {
+            // Read and increment the address
+            0x1 => {
+                self.rtc_response = self.read_rtc_nibble(self.rtc_address);
+                self.rtc_address = self.rtc_address.wrapping_add(1);
+            },
+            // Write, 0x3 also increments the address
+            0x2 | 0x3 => {
+                self.write_rtc_nibble(self.rtc_address, arg);
+                if v >> 4 == 0x3 {
+                    self.rtc_address = self.rtc_address.wrapping_add(1);
+                }
+            },
+            0x4 => self.rtc_address = (self.rtc_address & 0xF0) | arg,
+            0x5 => self.rtc_address = (self.rtc_address & 0x0F) | (arg << 4),
+            0x6 => self.rtc_extended = arg,
+            _ => {},
+        }
+    }
+}
+
+fn unix_now() -> u64 {
+    match time::SystemTime::now().duration_since(time::UNIX_EPOCH) {
+        Ok(t) => t.as_secs(),
+        Err(_) => panic!("System clock is set to a time before the unix epoch (1970-01-01)"),
+    }
+}
+
+impl MBC for HuC3 {
+    fn readrom(&self, a: u16) -> u8 {
+        let idx = if a < 0x4000 { a as usize }
+        else { (self.rombank * 0x4000) | ((a as usize) & 0x3FFF) };
+        *self.rom.get(idx).unwrap_or(&0xFF)
+    }
+    fn readram(&self, a: u16) -> u8 {
+        match self.mode {
+            0x0 | 0xA => match self.ramaddress(a) {
+                Some(idx) => self.ram[idx],
+                None => 0xFF,
+            },
+            0xC if self.rtc_extended == EXT_STATUS => 0x01,
+            0xC => self.rtc_response,
+            // Commands complete instantly, so the semaphore always reads ready
+            0xD => 0x01,
+            0xE => IR_NO_LIGHT,
+            _ => 0xFF,
+        }
+    }
+    fn writerom(&mut self, a: u16, v: u8) {
+        match a {
+            0x0000 ..= 0x1FFF => self.mode = v & 0x0F,
+            0x2000 ..= 0x3FFF => self.rombank = ((v & 0x7F) as usize) % self.rombanks,
+            0x4000 ..= 0x5FFF => self.rambank = (v & 0x03) as usize,
+            0x6000 ..= 0x7FFF => {},
+            _ => panic!("Could not write to {:04X} (HuC3)", a),
+        }
+    }
+    fn writeram(&mut self, a: u16, v: u8) {
+        match self.mode {
+            0xA => {
+                if let Some(idx) = self.ramaddress(a) {
+                    self.ram[idx] = v;
+                    self.ram_updated = true;
+                }
+            },
+            0xB => self.command(v),
+            0xE => self.ir_led = v & 0x01 == 0x01,
+            _ => {},
+        }
+    }
+
+    fn is_battery_backed(&self) -> bool {
+        true
+    }
+
+    fn loadram(&mut self, ramdata: &[u8]) -> StrResult<()> {
+        let with_rtc_mem = ramdata.len() == 8 + RTC_MEM_SIZE + 1 + self.ram.len();
+        if !with_rtc_mem && ramdata.len() != 8 + self.ram.len() {
+            return Err("Loaded ram has incorrect length");
+        }
+
+        // The clock is stored as the unix time at which it read zero, like MBC3
+        let (int_bytes, rest) = ramdata.split_at(8);
+        let rtc_zero = u64::from_be_bytes(int_bytes.try_into().unwrap());
+        let now = unix_now();
+        let elapsed = now.saturating_sub(rtc_zero);
+        self.rtc_minutes = (elapsed / 60) % MINUTES_PER_DAY;
+        self.rtc_days = (elapsed / 60 / MINUTES_PER_DAY) & 0xFFFF;
+        self.rtc_last = now - elapsed % 60;
+        let rest = if with_rtc_mem {
+            let (mem, rest) = rest.split_at(RTC_MEM_SIZE);
+            self.rtc_mem.copy_from_slice(mem);
+            self.rtc_extended = rest[0];
+            &rest[1 ..]
+        } else {
+            rest
+        };
+        self.ram = rest.to_vec();
+        Ok(())
+    }
+
+    fn dumpram(&self) -> Vec<u8> {
+        let (minutes, days, last) = self.clock();
+        let rtc_zero = last.saturating_sub((days * MINUTES_PER_DAY + minutes) * 60);
+        let mut file = rtc_zero.to_be_bytes().to_vec();
+        file.extend_from_slice(&self.rtc_mem);
+        file.push(self.rtc_extended);
+        file.extend_from_slice(&self.ram);
+        file
+    }
+
+    fn check_and_reset_ram_updated(&mut self) -> bool {
+        let result = self.ram_updated;
+        self.ram_updated = false;
+        result
+    }
+
+    fn speaker_on(&self) -> bool {
+        self.rtc_extended == EXT_TONE
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::HuC3;
+    use crate::mbc::MBC;
+
+    fn mbc() -> HuC3 {
+        let mut data = vec![0u8; 0x8000];
+        data[0x147] = 0xFE;
+        data[0x149] = 0x02;
+        HuC3::new(data).unwrap()
+    }
+
+    fn read_nibbles(mbc: &mut HuC3, address: u8, count: usize) -> u32 {
+        mbc.writerom(0x0000, 0x0B);
+        mbc.writeram(0xA000, 0x40 | (address & 0xF));
+        mbc.writeram(0xA000, 0x50 | (address >> 4));
+        let mut value = 0;
+        for i in 0 .. count {
+            mbc.writerom(0x0000, 0x0B);
+            mbc.writeram(0xA000, 0x10);
+            mbc.writerom(0x0000, 0x0C);
+            value |= ((mbc.readram(0xA000) & 0xF) as u32) << (i * 4);
+        }
+        value
+    }
+
+    #[test]
+    fn rtc_write_read_and_save() {
+        let mut mbc = mbc();
+
+        // 12:34 on day 0x123
+        let minutes = 12 * 60 + 34;
+        let value = ((0x123u32) << 12) | minutes;
+        mbc.writerom(0x0000, 0x0B);
+        mbc.writeram(0xA000, 0x40);
+        mbc.writeram(0xA000, 0x50);
+        for i in 0 .. 7 {
+            mbc.writeram(0xA000, 0x30 | ((value >> (i * 4)) & 0xF) as u8);
+        }
+        assert!(mbc.check_and_reset_ram_updated());
+
+        // Allow the minute to roll over while the test runs
+        let read = read_nibbles(&mut mbc, 0, 7);
+        assert!(read == value || read == value + 1);
+
+        let mut loaded = self::mbc();
+        loaded.loadram(&mbc.dumpram()).unwrap();
+        let read = read_nibbles(&mut loaded, 0, 7);
+        assert!(read == value || read == value + 1);
+    }
+
+    #[test]
+    fn rtc_memory_save() {
+        let mut mbc = mbc();
+        // Write 0x9 and 0x4 to the alarm nibbles at 0x10 and 0x11
+        mbc.writerom(0x0000, 0x0B);
+        mbc.writeram(0xA000, 0x40);
+        mbc.writeram(0xA000, 0x51);
+        mbc.writeram(0xA000, 0x39);
+        mbc.writeram(0xA000, 0x34);
+        assert!(mbc.check_and_reset_ram_updated());
+        mbc.writerom(0x0000, 0x0A);
+        mbc.writeram(0xA010, 0x77);
+
+        let save = mbc.dumpram();
+        let mut loaded = self::mbc();
+        loaded.loadram(&save).unwrap();
+        assert_eq!(read_nibbles(&mut loaded, 0x10, 2), 0x49);
+        loaded.writerom(0x0000, 0x0A);
+        assert_eq!(loaded.readram(0xA010), 0x77);
+
+        // Saves without the RTC memory still load
+        let mut old = save[.. 8].to_vec();
+        old.extend_from_slice(&save[8 + 0x101 ..]);
+        let mut loaded = self::mbc();
+        loaded.loadram(&old).unwrap();
+        assert_eq!(read_nibbles(&mut loaded, 0x10, 2), 0);
+        loaded.writerom(0x0000, 0x0A);
+        assert_eq!(loaded.readram(0xA010), 0x77);
+    }
+
+    #[test]
+    fn ram_modes_and_tone() {
+        let mut mbc = mbc();
+
+        // Mode 0 maps RAM read only
+        mbc.writerom(0x0000, 0x0A);
+        mbc.writeram(0xA123, 0x55);
+        mbc.writerom(0x0000, 0x00);
+        mbc.writeram(0xA123, 0x66);
+        assert_eq!(mbc.readram(0xA123), 0x55);
+
+        mbc.writerom(0x0000, 0x0D);
+        assert_eq!(mbc.readram(0xA000), 0x01);
+        mbc.writerom(0x0000, 0x0E);
+        assert_eq!(mbc.readram(0xA000), 0xC0);
+
+        assert!(!mbc.speaker_on());
+        mbc.writerom(0x0000, 0x0B);
+        mbc.writeram(0xA000, 0x6E);
+        assert!(mbc.speaker_on());
+        mbc.writeram(0xA000, 0x60);
+        assert!(!mbc.speaker_on());
+    }
+}
@@ -0,0 +1,35 @@
+use crate::StrResult;
+use crate::mbc::MBC;
+
+/// M161 mapper of the Mani 4 in 1 carts. The menu selects a 32 KiB bank with a write to
+/// 4000-5FFF, after which the mapper ignores further writes until reset.
+pub struct M161 {
+    rom: Vec<u8>,
+    bank: usize,
+    locked: bool,
+}
+
+impl M161 {
+    pub fn new(data: Vec<u8>) -> StrResult<M161> {
+        Ok(M161 { rom: data, bank: 0, locked: false })
+    }
+}
+
+impl MBC for M161 {
+    fn readrom(&self, a: u16) -> u8 {
+        *self.rom.get((self.bank * 0x8000) | (a as usize)).unwrap_or(&0xFF)
+    }
+    fn readram(&self, _a: u16) -> u8 { 0xFF }
+    fn writerom(&mut self, a: u16, v: u8) {
+        if (0x4000 ..= 0x5FFF).contains(&a) && !self.locked {
+            self.bank = (v & 0x07) as usize;
+            self.locked = true;
+        }
+    }
+    fn writeram(&mut self, _a: u16, _v: u8) {}
+
+    fn is_battery_backed(&self) -> bool { false }
+    fn loadram(&mut self, _ramdata: &[u8]) -> StrResult<()> { Ok(()) }
+    fn dumpram(&self) -> Vec<u8> { Vec::new() }
+    fn check_and_reset_ram_updated(&mut self) -> bool { false }
+}
@@ -1,4 +1,5 @@
 use crate::mbc::{MBC, ram_banks, rom_banks};
+use crate::mbc::header::{NINTENDO_LOGO, LOGO_START};
 use crate::StrResult;
 
 pub struct MBC1 {
@@ -7,11 +8,13 @@ pub struct MBC1 {
     ram_on: bool,
     ram_updated:bool,
     banking_mode: u8,
-    rombank: usize,
-    rambank: usize,
+    bank1: usize,
+    bank2: usize,
     has_battery: bool,
     rombanks: usize,
     rambanks: usize,
+    multicart: bool,
+    bit_order: Option<[u8; 5]>,
 }
 
 impl MBC1 {
@@ -21,24 +24,120 @@ impl MBC1 {
             0x03 => (true, ram_banks(data[0x149])),
             _ => (false, 0),
         };
-        let rombanks = rom_banks(data[0x148]);
+        let rombanks = match rom_banks(data[0x148]) {
+            0 => ::std::cmp::max(2, data.len() / 0x4000),
+            n => n,
+        };
         let ramsize = rambanks * 0x2000;
+        let multicart = is_multicart(&data);
 
         let res = MBC1 {
             rom: data,
             ram: ::std::iter::repeat(0u8).take(ramsize).collect(),
             ram_on: false,
             banking_mode: 0,
-            rombank: 1,
-            rambank: 0,
+            bank1: 1,
+            bank2: 0,
             ram_updated: false,
             has_battery: has_battery,
             rombanks: rombanks,
             rambanks: rambanks,
+            multicart,
+            bit_order: None,
         };
 
         Ok(res)
     }
+
+    /// Bootleg MBC1 with the data lines of the ROM bank register wired in a different order.
+    /// See `Mapper::ScrambledMBC1` for the meaning of `bit_order`.
+    pub fn new_scrambled(data: Vec<u8>, bit_order: [u8; 5]) -> StrResult<MBC1> {
+        let mut res = MBC1::new(data)?;
+        res.bit_order = Some(bit_order);
+        Ok(res)
+    }
+
+    fn unscramble(&self, v: u8) -> u8 {
+        match self.bit_order {
+            Some(order) => unscramble(order, v),
+            None => v,
+        }
+    }
+
+    // On MBC1M multicarts the BANK2 register is wired to the ROM one bit lower, and the top bit of
+    // BANK1 is not connected. Each game then gets 16 banks of its own.
+    fn bank2_shift(&self) -> usize {
+        if self.multicart { 4 } else { 5 }
+    }
+
+    fn rombank_upper(&self) -> usize {
+        self.bank2 << self.bank2_shift()
+    }
+
+    fn rombank(&self) -> usize {
+        let lower_mask = (1 << self.bank2_shift()) - 1;
+        (self.rombank_upper() | (self.bank1 & lower_mask)) % self.rombanks
+    }
+
+    fn rambank(&self) -> usize {
+        if self.banking_mode == 1 && self.rambanks > 1 { self.bank2 % self.rambanks } else { 0 }
+    }
+}
+
+// An MBC1M multicart is an 8 Mbit MBC1 cartridge with a game, and thus a Nintendo logo, at the
+// start of every 16 banks. The menu in bank 0 may be followed by up to three games.
+fn is_multicart(data: &[u8]) -> bool {
+    if rom_banks(data[0x148]) != 0x40 { return false; }
+
+    let logos = [0x10, 0x20, 0x30].iter()
+        .filter(|&&bank| {
+            let start = bank * 0x4000 + LOGO_START;
+            data.get(start .. start + NINTENDO_LOGO.len()) == Some(&NINTENDO_LOGO[..])
+        })
+        .count();
+    logos >= 2
+}
+
+fn unscramble(order: [u8; 5], v: u8) -> u8 {
+    order.iter().enumerate().fold(0, |acc, (i, &bit)| acc | (((v >> bit) & 1) << i))
+}
+
+/// Guesses the bit order of a scrambled MBC1 collection. The menu in bank 0 selects each game
+/// with `LD A,n` followed by `LD (2000-3FFF),A`, and each game starts with a Nintendo logo. The
+/// bit order that sends the most of these writes to a bank with a logo is returned, if it finds
+/// at least two games and the normal order finds fewer.
+pub fn scrambled_bit_order(data: &[u8]) -> Option<[u8; 5]> {
+    let rombanks = rom_banks(data[0x148]);
+    if rombanks < 16 || rombanks * 0x4000 != data.len() || is_multicart(data) { return None; }
+
+    let has_logo = |bank: usize| {
+        let start = bank * 0x4000 + LOGO_START;
+        data[start .. start + NINTENDO_LOGO.len()] == NINTENDO_LOGO
+    };
+    let mut writes: Vec<u8> = data[.. 0x4000].windows(5)
+        .filter(|w| w[0] == 0x3E && w[2] == 0xEA && (0x20 ..= 0x3F).contains(&w[4]))
+        .map(|w| w[1] & 0x1F)
+        .collect();
+    writes.sort_unstable();
+    writes.dedup();
+
+    let games = |order: [u8; 5]| writes.iter()
+        .map(|&v| match unscramble(order, v) { 0 => 1, n => n as usize } % rombanks)
+        .filter(|&bank| has_logo(bank))
+        .count();
+    let normal = games([0, 1, 2, 3, 4]);
+
+    // All permutations of 01234, those moving the fewest bits first
+    let mut orders: Vec<[u8; 5]> = (0 .. 5u32.pow(5))
+        .map(|n| [0, 1, 2, 3, 4].map(|i| (n / 5u32.pow(i) % 5) as u8))
+        .filter(|order| (0 .. 5).all(|bit| order.contains(&bit)))
+        .collect();
+    orders.sort_by_key(|order| order.iter().enumerate().filter(|&(i, &bit)| i as u8 != bit).count());
+
+    // max_by_key keeps the last of equal orders
+    let order = orders.into_iter().rev().max_by_key(|&order| games(order))?;
+    let found = games(order);
+    if found >= 2 && found > normal { Some(order) } else { None }
 }
 
 impl MBC for MBC1 {
@@ -48,40 +147,31 @@ impl MBC for MBC1 {
                 0
             }
             else {
-                self.rombank & 0xE0
+                self.rombank_upper() % self.rombanks
             }
         }
         else {
-            self.rombank
+            self.rombank()
         };
         let idx = bank * 0x4000 | ((a as usize) & 0x3FFF);
         *self.rom.get(idx).unwrap_or(&0xFF)
     }
     fn readram(&self, a: u16) -> u8 {
         if !self.ram_on { return 0xFF }
-        let rambank = if self.banking_mode == 1 { self.rambank } else { 0 };
-        self.ram[(rambank * 0x2000) | ((a & 0x1FFF) as usize)]
+        let address = (self.rambank() * 0x2000) | ((a & 0x1FFF) as usize);
+        *self.ram.get(address).unwrap_or(&0xFF)
     }
 
     fn writerom(&mut self, a: u16, v: u8) {
         match a {
             0x0000 ..= 0x1FFF => { self.ram_on = v & 0xF == 0xA; },
             0x2000 ..= 0x3FFF => {
-                let lower_bits = match (v as usize) & 0x1F {
+                self.bank1 = match (self.unscramble(v) as usize) & 0x1F {
                     0 => 1,
                     n => n,
                 };
-                self.rombank = ((self.rombank & 0x60) | lower_bits) % self.rombanks;
-            },
-            0x4000 ..= 0x5FFF => {
-                if self.rombanks > 0x20 {
-                    let upper_bits = (v as usize & 0x03) % (self.rombanks >> 5);
-                    self.rombank = self.rombank & 0x1F | (upper_bits << 5)
-                }
-                if self.rambanks > 1 {
-                    self.rambank = (v as usize) & 0x03;
-                }
             },
+            0x4000 ..= 0x5FFF => { self.bank2 = (v as usize) & 0x03; },
             0x6000 ..= 0x7FFF => { self.banking_mode = v & 0x01; },
             _ => panic!("Could not write to {:04X} (MBC1)", a),
         }
@@ -89,8 +179,7 @@ impl MBC for MBC1 {
 
     fn writeram(&mut self, a: u16, v: u8) {
         if !self.ram_on { return }
-        let rambank = if self.banking_mode == 1 { self.rambank } else { 0 };
-        let address = (rambank * 0x2000) | ((a & 0x1FFF) as usize);
+        let address = (self.rambank() * 0x2000) | ((a & 0x1FFF) as usize);
         if address < self.ram.len() {
             self.ram[address] = v;
             self.ram_updated = true;
@@ -121,3 +210,71 @@ impl MBC for MBC1 {
         result
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::MBC1;
+    use crate::mbc::MBC;
+    use crate::mbc::header::{NINTENDO_LOGO, LOGO_START};
+
+    // 8 Mbit ROM where every bank starts with its own bank number
+    fn rom(with_logos: bool) -> Vec<u8> {
+        let mut data = vec![0u8; 0x100000];
+        for bank in 0 .. 0x40 {
+            data[bank * 0x4000] = bank as u8;
+        }
+        data[0x147] = 0x01;
+        data[0x148] = 0x05;
+        if with_logos {
+            for &bank in &[0x00, 0x10, 0x20, 0x30] {
+                let start = bank * 0x4000 + LOGO_START;
+                data[start .. start + NINTENDO_LOGO.len()].copy_from_slice(&NINTENDO_LOGO);
+            }
+        }
+        data
+    }
+
+    #[test]
+    fn multicart_banking() {
+        let mut mbc = MBC1::new(rom(true)).unwrap();
+        assert!(mbc.multicart);
+
+        mbc.writerom(0x4000, 0x01);
+        mbc.writerom(0x2000, 0x03);
+        assert_eq!(mbc.readrom(0x4000), 0x13);
+
+        // The top bit of BANK1 is not connected, but still counts for the bank 0 check
+        mbc.writerom(0x2000, 0x10);
+        assert_eq!(mbc.readrom(0x4000), 0x10);
+
+        mbc.writerom(0x4000, 0x03);
+        mbc.writerom(0x6000, 0x01);
+        assert_eq!(mbc.readrom(0x0000), 0x30);
+    }
+
+    #[test]
+    fn regular_banking() {
+        let mut mbc = MBC1::new(rom(false)).unwrap();
+        assert!(!mbc.multicart);
+
+        mbc.writerom(0x4000, 0x01);
+        mbc.writerom(0x2000, 0x03);
+        assert_eq!(mbc.readrom(0x4000), 0x23);
+
+        mbc.writerom(0x2000, 0x00);
+        assert_eq!(mbc.readrom(0x4000), 0x21);
+
+        mbc.writerom(0x6000, 0x01);
+        assert_eq!(mbc.readrom(0x0000), 0x20);
+    }
+
+    #[test]
+    fn scrambled_bank_register() {
+        // Bits 0 and 1 swapped
+        let mut mbc = MBC1::new_scrambled(rom(false), [1, 0, 2, 3, 4]).unwrap();
+        mbc.writerom(0x2000, 0x01);
+        assert_eq!(mbc.readrom(0x4000), 0x02);
+        mbc.writerom(0x2000, 0x06);
+        assert_eq!(mbc.readrom(0x4000), 0x05);
+    }
+}
@@ -1,4 +1,4 @@
-use crate::mbc::{MBC, ram_banks};
+use crate::mbc::{MBC, RtcMode, ram_banks, rom_banks};
 use crate::StrResult;
 
 use std::io::prelude::*;
@@ -9,8 +9,11 @@ pub struct MBC3 {
     rom: Vec<u8>,
     ram: Vec<u8>,
     rombank: usize,
+    rombanks: usize,
     rambank: usize,
     rambanks: usize,
+    // MBC30 has an 8-bit ROM bank register and eight RAM banks
+    is_mbc30: bool,
     selectrtc: bool,
     ram_on: bool,
     ram_updated: bool,
@@ -18,8 +21,17 @@ pub struct MBC3 {
     rtc_ram: [u8; 5],
     rtc_ram_latch: [u8; 5],
     rtc_zero: Option<u64>,
+    rtc_mode: RtcMode,
+    rtc_ticks: u32,
 }
 
+const CLOCKS_PER_SECOND: u32 = 4194304;
+const SECONDS_PER_DAY: u64 = 3600 * 24;
+
+// Marks a save of the emulated clock, which holds the halt and carry flags in bits 54-55 and
+// the elapsed seconds in the low bits instead of a unix time
+const EMULATED_RTC: u64 = 1 << 63;
+
 impl MBC3 {
     pub fn new(data: Vec<u8>) -> StrResult<MBC3> {
         let subtype = data[0x147];
@@ -32,6 +44,12 @@ impl MBC3 {
             _ => 0,
         };
         let ramsize = rambanks * 0x2000;
+        let rombanks = match rom_banks(data[0x148]) {
+            0 => ::std::cmp::max(2, data.len() / 0x4000),
+            n => n,
+        };
+        // There is no separate cartridge type, so the MBC30 is recognised by its sizes
+        let is_mbc30 = rombanks > 128 || rambanks > 4;
         let rtc = match subtype {
             0x0F | 0x10 => Some(0),
             _ => None,
@@ -41,8 +59,10 @@ impl MBC3 {
             rom: data,
             ram: ::std::iter::repeat(0u8).take(ramsize).collect(),
             rombank: 1,
+            rombanks,
             rambank: 0,
             rambanks: rambanks,
+            is_mbc30,
             selectrtc: false,
             ram_on: false,
             ram_updated: false,
@@ -50,6 +70,8 @@ impl MBC3 {
             rtc_ram: [0u8; 5],
             rtc_ram_latch: [0u8; 5],
             rtc_zero: rtc,
+            rtc_mode: RtcMode::WallClock,
+            rtc_ticks: 0,
         };
 
         Ok(res)
@@ -63,6 +85,8 @@ impl MBC3 {
     fn calc_rtc_reg(&mut self) {
         // Do not modify regs when halted
         if self.rtc_ram[4] & 0x40 == 0x40 { return }
+        // The emulated clock keeps the registers up to date itself
+        if self.rtc_mode == RtcMode::Emulated { return }
 
         let tzero = match self.rtc_zero {
             Some(t) => time::UNIX_EPOCH + time::Duration::from_secs(t),
@@ -92,10 +116,7 @@ impl MBC3 {
 
     fn compute_difftime(&self) -> Option<u64> {
         if self.rtc_zero.is_none() { return None; }
-        let mut difftime = match time::SystemTime::now().duration_since(time::UNIX_EPOCH) {
-            Ok(t) => t.as_secs(),
-            Err(_) => panic!("System clock is set to a time before the unix epoch (1970-01-01)"),
-        };
+        let mut difftime = MBC3::unix_now();
         difftime -= self.rtc_ram[0] as u64;
         difftime -= (self.rtc_ram[1] as u64) * 60;
         difftime -= (self.rtc_ram[2] as u64) * 3600;
@@ -107,6 +128,32 @@ impl MBC3 {
     fn calc_rtc_zero(&mut self) {
         self.rtc_zero = self.compute_difftime();
     }
+
+    // Time in the registers as seconds since day 0
+    fn rtc_seconds(&self) -> u64 {
+        let days = ((self.rtc_ram[4] as u64 & 0x1) << 8) | (self.rtc_ram[3] as u64);
+        days * SECONDS_PER_DAY + (self.rtc_ram[2] as u64) * 3600 + (self.rtc_ram[1] as u64) * 60 + self.rtc_ram[0] as u64
+    }
+
+    // Sets the registers, setting the day carry when the day counter overflows
+    fn set_rtc_seconds(&mut self, seconds: u64) {
+        let days = seconds / SECONDS_PER_DAY;
+        self.rtc_ram[0] = (seconds % 60) as u8;
+        self.rtc_ram[1] = ((seconds / 60) % 60) as u8;
+        self.rtc_ram[2] = ((seconds / 3600) % 24) as u8;
+        self.rtc_ram[3] = days as u8;
+        self.rtc_ram[4] = (self.rtc_ram[4] & 0xFE) | (((days >> 8) & 0x01) as u8);
+        if days >= 512 {
+            self.rtc_ram[4] |= 0x80;
+        }
+    }
+
+    fn unix_now() -> u64 {
+        match time::SystemTime::now().duration_since(time::UNIX_EPOCH) {
+            Ok(t) => t.as_secs(),
+            Err(_) => panic!("System clock is set to a time before the unix epoch (1970-01-01)"),
+        }
+    }
 }
 
 impl MBC for MBC3 {
@@ -129,11 +176,13 @@ impl MBC for MBC3 {
         match a {
             0x0000 ..= 0x1FFF => self.ram_on = (v & 0x0F) == 0x0A,
             0x2000 ..= 0x3FFF => {
-                self.rombank = match v & 0x7F { 0 => 1, n => n as usize }
+                let mask = if self.is_mbc30 { 0xFF } else { 0x7F };
+                self.rombank = match v & mask { 0 => 1, n => n as usize } % self.rombanks
             },
             0x4000 ..= 0x5FFF => {
                 self.selectrtc = v & 0x8 == 0x8;
-                self.rambank = (v & 0x7) as usize;
+                let mask = if self.is_mbc30 || self.selectrtc { 0x7 } else { 0x3 };
+                self.rambank = (v & mask) as usize;
             },
             0x6000 ..= 0x7FFF => self.latch_rtc_reg(),
             _ => panic!("Could not write to {:04X} (MBC3)", a),
@@ -170,16 +219,27 @@ impl MBC for MBC3 {
         let (int_bytes, rest) = ramdata.split_at(8);
         let rtc = u64::from_be_bytes(int_bytes.try_into().unwrap());
         if self.rtc_zero.is_some() {
-            self.rtc_zero = Some(rtc);
+            // Either clock can continue from a save made with the other one
+            let seconds = if rtc & EMULATED_RTC == EMULATED_RTC {
+                self.rtc_ram[4] = ((rtc >> 48) & 0xC0) as u8;
+                rtc & 0xFFFF_FFFF
+            } else {
+                MBC3::unix_now().saturating_sub(rtc)
+            };
+            self.set_rtc_seconds(seconds);
+            if self.rtc_mode == RtcMode::WallClock {
+                self.rtc_zero = Some(MBC3::unix_now().saturating_sub(seconds));
+            }
         }
         self.ram = rest.to_vec();
         Ok(())
     }
 
     fn dumpram(&self) -> Vec<u8> {
-        let rtc = match self.rtc_zero {
-            Some(t) => t,
-            None => 0,
+        let rtc = match (self.rtc_zero, self.rtc_mode) {
+            (Some(_), RtcMode::Emulated) => EMULATED_RTC | (((self.rtc_ram[4] & 0xC0) as u64) << 48) | self.rtc_seconds(),
+            (Some(t), RtcMode::WallClock) => t,
+            (None, _) => 0,
         };
 
         let mut file = vec![];
@@ -196,4 +256,172 @@ impl MBC for MBC3 {
         self.ram_updated = false;
         result
     }
+
+    fn do_cycle(&mut self, ticks: u32) {
+        if self.rtc_mode != RtcMode::Emulated || self.rtc_zero.is_none() { return }
+        if self.rtc_ram[4] & 0x40 == 0x40 { return }
+
+        self.rtc_ticks += ticks;
+        while self.rtc_ticks >= CLOCKS_PER_SECOND {
+            self.rtc_ticks -= CLOCKS_PER_SECOND;
+            let seconds = self.rtc_seconds() + 1;
+            self.set_rtc_seconds(seconds);
+            self.ram_updated = true;
+        }
+    }
+
+    fn set_rtc_mode(&mut self, mode: RtcMode) {
+        if mode == self.rtc_mode { return }
+        // A wall clock that was never set or loaded counts from 1970. The emulated clock starts
+        // at day 0 instead.
+        if mode == RtcMode::Emulated && self.rtc_zero == Some(0) {
+            self.rtc_mode = mode;
+            self.rtc_ticks = 0;
+            self.rtc_ram = [0; 5];
+            return;
+        }
+        self.calc_rtc_reg();
+        self.rtc_mode = mode;
+        self.rtc_ticks = 0;
+        if self.rtc_zero.is_some() {
+            self.calc_rtc_zero();
+        }
+    }
+
+    fn set_rtc_time(&mut self, seconds: u64) {
+        if self.rtc_zero.is_none() { return }
+        self.set_rtc_seconds(seconds);
+        self.calc_rtc_zero();
+        self.ram_updated = true;
+    }
+
+    fn offset_rtc_days(&mut self, days: i64) {
+        if self.rtc_zero.is_none() { return }
+        self.calc_rtc_reg();
+        let seconds = self.rtc_seconds() as i64 + days * SECONDS_PER_DAY as i64;
+        self.set_rtc_time(seconds.max(0) as u64);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::MBC3;
+    use crate::mbc::{MBC, RtcMode};
+
+    fn mbc() -> MBC3 {
+        let mut data = vec![0u8; 0x8000];
+        data[0x147] = 0x10;
+        data[0x149] = 0x03;
+        let mut mbc = MBC3::new(data).unwrap();
+        mbc.set_rtc_mode(RtcMode::Emulated);
+        mbc.writerom(0x0000, 0x0A);
+        mbc
+    }
+
+    fn read_rtc(mbc: &mut MBC3) -> [u8; 5] {
+        mbc.writerom(0x6000, 0x00);
+        mbc.writerom(0x6000, 0x01);
+        let mut regs = [0u8; 5];
+        for (i, reg) in regs.iter_mut().enumerate() {
+            mbc.writerom(0x4000, 0x08 + i as u8);
+            *reg = mbc.readram(0xA000);
+        }
+        regs
+    }
+
+    #[test]
+    fn emulated_rtc_follows_clock_cycles() {
+        let mut mbc = mbc();
+        mbc.set_rtc_time(23 * 3600 + 59 * 60 + 58);
+        mbc.do_cycle(4194304);
+        assert_eq!(read_rtc(&mut mbc), [59, 59, 23, 0, 0]);
+        mbc.do_cycle(4194304);
+        assert_eq!(read_rtc(&mut mbc), [0, 0, 0, 1, 0]);
+
+        mbc.offset_rtc_days(2);
+        assert_eq!(read_rtc(&mut mbc), [0, 0, 0, 3, 0]);
+        mbc.offset_rtc_days(-1);
+        assert_eq!(read_rtc(&mut mbc), [0, 0, 0, 2, 0]);
+
+        mbc.set_rtc_time(512 * 86400 - 1);
+        mbc.do_cycle(4194304);
+        assert_eq!(read_rtc(&mut mbc), [0, 0, 0, 0, 0x80]);
+
+        // Halted clocks do not advance
+        mbc.writerom(0x4000, 0x0C);
+        mbc.writeram(0xA000, 0x40);
+        mbc.do_cycle(4194304 * 10);
+        assert_eq!(read_rtc(&mut mbc), [0, 0, 0, 0, 0x40]);
+    }
+
+    #[test]
+    fn new_clocks() {
+        let mut data = vec![0u8; 0x8000];
+        data[0x147] = 0x10;
+        data[0x149] = 0x03;
+
+        // The wall clock counts from 1970, so the day counter has overflowed
+        let mut wall = MBC3::new(data.clone()).unwrap();
+        wall.writerom(0x0000, 0x0A);
+        assert_eq!(read_rtc(&mut wall)[4] & 0x80, 0x80);
+
+        let mut emulated = MBC3::new(data).unwrap();
+        emulated.set_rtc_mode(RtcMode::Emulated);
+        emulated.writerom(0x0000, 0x0A);
+        assert_eq!(read_rtc(&mut emulated), [0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn mbc30_banks() {
+        let mut data = vec![0u8; 0x400000];
+        for bank in 0 .. 256 {
+            data[bank * 0x4000 + 1] = bank as u8;
+        }
+        data[0x147] = 0x13;
+        data[0x148] = 0x07;
+        data[0x149] = 0x05;
+        let mut mbc = MBC3::new(data).unwrap();
+        mbc.writerom(0x2000, 0xC1);
+        assert_eq!(mbc.readrom(0x4001), 0xC1);
+
+        mbc.writerom(0x0000, 0x0A);
+        mbc.writerom(0x4000, 0x07);
+        mbc.writeram(0xA000, 0x77);
+        assert_eq!(mbc.dumpram()[8 + 7 * 0x2000], 0x77);
+
+        // A plain MBC3 ignores the top bits
+        let mut data = vec![0u8; 0x200000];
+        data[0x4000 + 1] = 0x01;
+        data[0x147] = 0x13;
+        data[0x148] = 0x06;
+        data[0x149] = 0x03;
+        let mut mbc = MBC3::new(data).unwrap();
+        mbc.writerom(0x2000, 0x81);
+        assert_eq!(mbc.readrom(0x4001), 0x01);
+
+        // An unknown ROM size code falls back to the size of the file
+        let mut data = vec![0u8; 0x8000];
+        data[0x147] = 0x11;
+        data[0x148] = 0x52;
+        assert!(MBC3::new(data).is_ok());
+    }
+
+    #[test]
+    fn emulated_rtc_save_roundtrip() {
+        let mut mbc = mbc();
+        mbc.set_rtc_time(300 * 86400 + 3723);
+        let save = mbc.dumpram();
+
+        let mut loaded = self::mbc();
+        loaded.loadram(&save).unwrap();
+        assert_eq!(read_rtc(&mut loaded), [3, 2, 1, 44, 1]);
+
+        // The wall clock continues from the same time
+        let mut wall = self::mbc();
+        wall.set_rtc_mode(RtcMode::WallClock);
+        wall.loadram(&save).unwrap();
+        let regs = read_rtc(&mut wall);
+        assert_eq!(regs[3 ..], [44, 1]);
+        assert!(regs[0] >= 3 && regs[0] <= 4);
+    }
 }
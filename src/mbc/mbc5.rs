@@ -9,6 +9,8 @@ pub struct MBC5 {
     ram_on: bool,
     ram_updated:bool,
     has_battery: bool,
+    has_rumble: bool,
+    rumble: bool,
     rombanks: usize,
     rambanks: usize,
 }
@@ -20,6 +22,8 @@ impl MBC5 {
             0x1B | 0x1E => true,
             _ => false,
         };
+        // Rumble carts wire bit 3 of the RAM bank register to the motor
+        let has_rumble = matches!(subtype, 0x1C ..= 0x1E);
         let rambanks = match subtype {
             0x1A | 0x1B | 0x1D | 0x1E => ram_banks(data[0x149]),
             _ => 0,
@@ -35,6 +39,8 @@ impl MBC5 {
             ram_updated: false,
             ram_on: false,
             has_battery: has_battery,
+            has_rumble,
+            rumble: false,
             rombanks: rombanks,
             rambanks: rambanks,
         };
@@ -50,7 +56,7 @@ impl MBC for MBC5 {
         *self.rom.get(idx).unwrap_or(&0)
     }
     fn readram(&self, a: u16) -> u8 {
-        if !self.ram_on { return 0 }
+        if !self.ram_on || self.rambanks == 0 { return 0 }
         self.ram[self.rambank * 0x2000 | ((a as usize) & 0x1FFF)]
     }
     fn writerom(&mut self, a: u16, v: u8) {
@@ -58,13 +64,21 @@ impl MBC for MBC5 {
             0x0000 ..= 0x1FFF => self.ram_on = v & 0x0F == 0x0A,
             0x2000 ..= 0x2FFF => self.rombank = ((self.rombank & 0x100) | (v as usize)) % self.rombanks,
             0x3000 ..= 0x3FFF => self.rombank = ((self.rombank & 0x0FF) | (((v & 0x1) as usize) << 8)) % self.rombanks,
-            0x4000 ..= 0x5FFF => self.rambank = ((v & 0x0F) as usize) % self.rambanks,
+            0x4000 ..= 0x5FFF => {
+                let bank = if self.has_rumble {
+                    self.rumble = v & 0x08 == 0x08;
+                    v & 0x07
+                } else {
+                    v & 0x0F
+                };
+                self.rambank = if self.rambanks == 0 { 0 } else { (bank as usize) % self.rambanks };
+            },
             0x6000 ..= 0x7FFF => { /* ? */ },
             _ => panic!("Could not write to {:04X} (MBC5)", a),
         }
     }
     fn writeram(&mut self, a: u16, v: u8) {
-        if self.ram_on == false { return }
+        if self.ram_on == false || self.rambanks == 0 { return }
         self.ram[self.rambank * 0x2000 | ((a as usize) & 0x1FFF)] = v;
         self.ram_updated = true;
     }
@@ -92,4 +106,44 @@ impl MBC for MBC5 {
         self.ram_updated = false;
         result
     }
+
+    fn rumble_on(&self) -> bool {
+        self.rumble
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::MBC5;
+    use crate::mbc::MBC;
+
+    fn mbc(subtype: u8) -> MBC5 {
+        let mut data = vec![0u8; 0x8000];
+        data[0x147] = subtype;
+        data[0x149] = 0x03;
+        MBC5::new(data).unwrap()
+    }
+
+    #[test]
+    fn rumble_bit_is_not_a_ram_bank() {
+        let mut mbc = mbc(0x1E);
+        mbc.writerom(0x0000, 0x0A);
+        mbc.writerom(0x4000, 0x01);
+        mbc.writeram(0xA000, 0x11);
+        assert!(!mbc.rumble_on());
+
+        mbc.writerom(0x4000, 0x09);
+        assert!(mbc.rumble_on());
+        assert_eq!(mbc.readram(0xA000), 0x11);
+        mbc.writerom(0x4000, 0x01);
+        assert!(!mbc.rumble_on());
+
+        // Without rumble bit 3 selects the bank
+        let mut mbc = self::mbc(0x1B);
+        mbc.writerom(0x0000, 0x0A);
+        mbc.writerom(0x4000, 0x09);
+        mbc.writeram(0xA000, 0x22);
+        assert!(!mbc.rumble_on());
+        assert_eq!(mbc.dumpram()[0x2000], 0x22);
+    }
 }
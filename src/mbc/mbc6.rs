@@ -0,0 +1,282 @@
+use crate::mbc::MBC;
+use crate::StrResult;
+
+const RAM_SIZE: usize = 0x8000;
+const FLASH_SIZE: usize = 0x100000;
+
+// Flash ID returned in autoselect mode (Macronix MX29F008)
+const FLASH_MANUFACTURER: u8 = 0xC2;
+const FLASH_DEVICE: u8 = 0x81;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum FlashState {
+    Read,
+    Unlock1,
+    Unlock2,
+    Autoselect,
+    Program,
+    EraseUnlock,
+    EraseUnlock1,
+    EraseUnlock2,
+}
+
+/// MBC6, with two switchable 8 KiB windows at 4000-5FFF and 6000-7FFF that each map either ROM
+/// or the 1 MiB flash, and two 4 KiB RAM windows at A000-AFFF and B000-BFFF. The flash is
+/// programmed with the usual unlock sequence (AA to 5555, 55 to 2AAA, then the command), and
+/// operations complete instantly. The save holds the RAM followed by the flash.
+pub struct MBC6 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    flash: Vec<u8>,
+    rombanks: usize,
+    rombank: [usize; 2],
+    flash_mapped: [bool; 2],
+    rambank: [usize; 2],
+    ram_on: bool,
+    flash_on: bool,
+    flash_write_on: bool,
+    flash_state: FlashState,
+    ram_updated: bool,
+}
+
+impl MBC6 {
+    pub fn new(data: Vec<u8>) -> StrResult<MBC6> {
+        if data.len() < 0x4000 || !data.len().is_multiple_of(0x2000) {
+            return Err("Unsupported ROM size for MBC6");
+        }
+
+        let res = MBC6 {
+            rombanks: data.len() / 0x2000,
+            rom: data,
+            ram: vec![0u8; RAM_SIZE],
+            flash: vec![0xFF; FLASH_SIZE],
+            rombank: [0, 0],
+            flash_mapped: [false, false],
+            rambank: [0, 0],
+            ram_on: false,
+            flash_on: false,
+            flash_write_on: false,
+            flash_state: FlashState::Read,
+            ram_updated: false,
+        };
+
+        Ok(res)
+    }
+
+    fn flashaddress(&self, window: usize, a: u16) -> usize {
+        ((self.rombank[window] * 0x2000) | ((a as usize) & 0x1FFF)) % FLASH_SIZE
+    }
+
+    fn ramaddress(&self, a: u16) -> usize {
+        let window = ((a >> 12) & 1) as usize;
+        (self.rambank[window] * 0x1000) | ((a as usize) & 0x0FFF)
+    }
+
+    fn write_flash(&mut self, address: usize, v: u8) {
+        if !self.flash_on || !self.flash_write_on { return }
+
+        // The command addresses only decode the low 15 address lines
+        let command = address & 0x7FFF;
+        self.flash_state = match (self.flash_state, command, v) {
+            (_, _, 0xF0) => FlashState::Read,
+            (FlashState::Program, _, _) => {
+                // Programming can only clear bits, erasing sets them again
+                self.flash[address] &= v;
+                self.ram_updated = true;
+                FlashState::Read
+            },
+            (FlashState::Read, 0x5555, 0xAA) | (FlashState::Autoselect, 0x5555, 0xAA) => FlashState::Unlock1,
+            (FlashState::Unlock1, 0x2AAA, 0x55) => FlashState::Unlock2,
+            (FlashState::Unlock2, 0x5555, 0x90) => FlashState::Autoselect,
+            (FlashState::Unlock2, 0x5555, 0xA0) => FlashState::Program,
+            (FlashState::Unlock2, 0x5555, 0x80) => FlashState::EraseUnlock,
+            (FlashState::EraseUnlock, 0x5555, 0xAA) => FlashState::EraseUnlock1,
+            (FlashState::EraseUnlock1, 0x2AAA, 0x55) => FlashState::EraseUnlock2,
+            (FlashState::EraseUnlock2, 0x5555, 0x10) => {
+                self.flash.iter_mut().for_each(|b| *b = 0xFF);
+                self.ram_updated = true;
+                FlashState::Read
+            },
+            (FlashState::EraseUnlock2, _, 0x30) => {
+                // Sectors are the size of a bank
+                let start = address & !0x1FFF;
+                self.flash[start .. start + 0x2000].iter_mut().for_each(|b| *b = 0xFF);
+                self.ram_updated = true;
+                FlashState::Read
+            },
+            (FlashState::Autoselect, _, _) => FlashState::Autoselect,
+            _ => FlashState::Read,
+        };
+    }
+
+    fn read_flash(&self, address: usize) -> u8 {
+        if !self.flash_on { return 0xFF }
+        match self.flash_state {
+            FlashState::Autoselect => match address & 0xFF {
+                0 => FLASH_MANUFACTURER,
+                1 => FLASH_DEVICE,
+                _ => 0x00,
+            },
+            _ => self.flash[address],
+        }
+    }
+}
+
+impl MBC for MBC6 {
+    fn readrom(&self, a: u16) -> u8 {
+        if a < 0x4000 {
+            return self.rom[a as usize];
+        }
+        let window = ((a >> 13) & 1) as usize;
+        if self.flash_mapped[window] {
+            return self.read_flash(self.flashaddress(window, a));
+        }
+        let idx = ((self.rombank[window] % self.rombanks) * 0x2000) | ((a as usize) & 0x1FFF);
+        self.rom[idx]
+    }
+    fn readram(&self, a: u16) -> u8 {
+        if !self.ram_on { return 0xFF }
+        self.ram[self.ramaddress(a)]
+    }
+    fn writerom(&mut self, a: u16, v: u8) {
+        match a {
+            0x0000 ..= 0x03FF => self.ram_on = v & 0x0F == 0x0A,
+            0x0400 ..= 0x07FF => self.rambank[0] = (v & 0x07) as usize,
+            0x0800 ..= 0x0BFF => self.rambank[1] = (v & 0x07) as usize,
+            0x0C00 ..= 0x0FFF => self.flash_on = v & 0x01 == 0x01,
+            0x1000 => self.flash_write_on = v & 0x01 == 0x01,
+            0x1001 ..= 0x1FFF => {},
+            0x2000 ..= 0x27FF => self.rombank[0] = (v & 0x7F) as usize,
+            0x2800 ..= 0x2FFF => self.flash_mapped[0] = v == 0x08,
+            0x3000 ..= 0x37FF => self.rombank[1] = (v & 0x7F) as usize,
+            0x3800 ..= 0x3FFF => self.flash_mapped[1] = v == 0x08,
+            0x4000 ..= 0x7FFF => {
+                let window = ((a >> 13) & 1) as usize;
+                if self.flash_mapped[window] {
+                    let address = self.flashaddress(window, a);
+                    self.write_flash(address, v);
+                }
+            },
+            _ => panic!("Could not write to {:04X} (MBC6)", a),
+        }
+    }
+    fn writeram(&mut self, a: u16, v: u8) {
+        if !self.ram_on { return }
+        let idx = self.ramaddress(a);
+        self.ram[idx] = v;
+        self.ram_updated = true;
+    }
+
+    fn is_battery_backed(&self) -> bool {
+        true
+    }
+
+    fn loadram(&mut self, ramdata: &[u8]) -> StrResult<()> {
+        if ramdata.len() != RAM_SIZE + FLASH_SIZE {
+            return Err("Loaded ram has incorrect length");
+        }
+
+        let (ram, flash) = ramdata.split_at(RAM_SIZE);
+        self.ram = ram.to_vec();
+        self.flash = flash.to_vec();
+        Ok(())
+    }
+
+    fn dumpram(&self) -> Vec<u8> {
+        let mut file = self.ram.clone();
+        file.extend_from_slice(&self.flash);
+        file
+    }
+
+    fn check_and_reset_ram_updated(&mut self) -> bool {
+        let result = self.ram_updated;
+        self.ram_updated = false;
+        result
+    }
+
+    fn flash_writable(&self) -> bool {
+        self.flash_on && self.flash_write_on
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::MBC6;
+    use crate::mbc::MBC;
+
+    fn mbc() -> MBC6 {
+        let mut data = vec![0u8; 0x10000];
+        for bank in 0 .. 8 {
+            data[bank * 0x2000] = bank as u8;
+        }
+        data[0x147] = 0x20;
+        MBC6::new(data).unwrap()
+    }
+
+    // Runs a flash command with the unlock sequence, leaving bank 1 mapped in the first window
+    fn flash_command(mbc: &mut MBC6, command: u8) {
+        mbc.writerom(0x2000, 0x02);
+        mbc.writerom(0x5555, 0xAA);
+        mbc.writerom(0x2000, 0x01);
+        mbc.writerom(0x4AAA, 0x55);
+        mbc.writerom(0x2000, 0x02);
+        mbc.writerom(0x5555, command);
+        mbc.writerom(0x2000, 0x01);
+    }
+
+    #[test]
+    fn windows_and_ram_banks() {
+        let mut mbc = mbc();
+        mbc.writerom(0x2000, 0x03);
+        mbc.writerom(0x3000, 0x06);
+        assert_eq!(mbc.readrom(0x0000), 0);
+        assert_eq!(mbc.readrom(0x4000), 3);
+        assert_eq!(mbc.readrom(0x6000), 6);
+
+        mbc.writerom(0x0000, 0x0A);
+        mbc.writerom(0x0400, 0x02);
+        mbc.writerom(0x0800, 0x05);
+        mbc.writeram(0xA010, 0x12);
+        mbc.writeram(0xB010, 0x34);
+        assert_eq!(mbc.dumpram()[0x2010], 0x12);
+        assert_eq!(mbc.dumpram()[0x5010], 0x34);
+    }
+
+    #[test]
+    fn flash_program_and_erase() {
+        let mut mbc = mbc();
+        mbc.writerom(0x2800, 0x08);
+        mbc.writerom(0x0C00, 0x01);
+
+        // Writes are ignored while the flash is write protected
+        flash_command(&mut mbc, 0xA0);
+        mbc.writerom(0x4010, 0x5A);
+        assert!(!mbc.flash_writable());
+        assert_eq!(mbc.readrom(0x4010), 0xFF);
+
+        mbc.writerom(0x1000, 0x01);
+        assert!(mbc.flash_writable());
+        flash_command(&mut mbc, 0x90);
+        assert_eq!(mbc.readrom(0x4000), 0xC2);
+        assert_eq!(mbc.readrom(0x4001), 0x81);
+        mbc.writerom(0x4000, 0xF0);
+
+        flash_command(&mut mbc, 0xA0);
+        mbc.writerom(0x4010, 0x5A);
+        assert_eq!(mbc.readrom(0x4010), 0x5A);
+        assert!(mbc.check_and_reset_ram_updated());
+
+        let mut loaded = self::mbc();
+        loaded.loadram(&mbc.dumpram()).unwrap();
+        assert_eq!(loaded.dumpram()[0x8000 + 0x2010], 0x5A);
+
+        // Sector erase, with the sector address in the last write
+        flash_command(&mut mbc, 0x80);
+        mbc.writerom(0x2000, 0x02);
+        mbc.writerom(0x5555, 0xAA);
+        mbc.writerom(0x2000, 0x01);
+        mbc.writerom(0x4AAA, 0x55);
+        mbc.writerom(0x4000, 0x30);
+        assert_eq!(mbc.readrom(0x4010), 0xFF);
+    }
+}
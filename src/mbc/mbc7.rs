@@ -0,0 +1,358 @@
+use crate::mbc::{MBC, rom_banks};
+use crate::StrResult;
+
+// Accelerometer output at rest, and the change in output for a tilt of 1g
+const ACCEL_CENTER: f32 = 0x81D0 as f32;
+const ACCEL_GRAVITY: f32 = 0x70 as f32;
+const ACCEL_ERASED: u16 = 0x8000;
+
+const EEPROM_WORDS: usize = 128;
+
+#[derive(PartialEq, Copy, Clone)]
+enum EepromState {
+    Idle,
+    Command,
+    Read,
+    Write,
+    WriteAll,
+}
+
+// 93LC56 serial EEPROM in 16-bit organisation, accessed by bit-banging CS, CLK and DI.
+// A command is a start bit, a two bit opcode and an eight bit address, clocked in MSB first.
+struct Eeprom {
+    data: [u16; EEPROM_WORDS],
+    state: EepromState,
+    cs: bool,
+    clk: bool,
+    di: bool,
+    dout: bool,
+    shift: u32,
+    bits: u32,
+    address: usize,
+    write_enabled: bool,
+    updated: bool,
+}
+
+impl Eeprom {
+    fn new() -> Eeprom {
+        Eeprom {
+            data: [0xFFFF; EEPROM_WORDS],
+            state: EepromState::Idle,
+            cs: false,
+            clk: false,
+            di: false,
+            dout: true,
+            shift: 0,
+            bits: 0,
+            address: 0,
+            write_enabled: false,
+            updated: false,
+        }
+    }
+
+    fn rb(&self) -> u8 {
+        (if self.cs { 0x80 } else { 0 }) |
+        (if self.clk { 0x40 } else { 0 }) |
+        (if self.di { 0x02 } else { 0 }) |
+        (if self.dout { 0x01 } else { 0 })
+    }
+
+    fn wb(&mut self, v: u8) {
+        let cs = v & 0x80 == 0x80;
+        let clk = v & 0x40 == 0x40;
+        self.di = v & 0x02 == 0x02;
+
+        if !cs {
+            // Deselecting aborts any command in progress. Writes complete instantly, so the
+            // ready state is shown as soon as the chip is selected again.
+            self.state = EepromState::Idle;
+            self.dout = true;
+        }
+        else if clk && !self.clk {
+            self.rising_edge();
+        }
+
+        self.cs = cs;
+        self.clk = clk;
+    }
+
+    fn rising_edge(&mut self) {
+        let di = if self.di { 1 } else { 0 };
+        match self.state {
+            EepromState::Idle => {
+                if di == 1 {
+                    self.state = EepromState::Command;
+                    self.shift = 0;
+                    self.bits = 0;
+                }
+            },
+            EepromState::Command => {
+                self.shift = (self.shift << 1) | di;
+                self.bits += 1;
+                if self.bits == 10 {
+                    self.command();
+                }
+            },
+            EepromState::Read => {
+                self.dout = self.shift & 0x8000 == 0x8000;
+                self.shift <<= 1;
+                self.bits += 1;
+                if self.bits == 16 {
+                    // Sequential read continues with the next word
+                    self.address = (self.address + 1) % EEPROM_WORDS;
+                    self.shift = self.data[self.address] as u32;
+                    self.bits = 0;
+                }
+            },
+            EepromState::Write | EepromState::WriteAll => {
+                self.shift = (self.shift << 1) | di;
+                self.bits += 1;
+                if self.bits == 16 {
+                    let value = self.shift as u16;
+                    if self.write_enabled {
+                        if self.state == EepromState::WriteAll {
+                            self.data = [value; EEPROM_WORDS];
+                        } else {
+                            self.data[self.address] = value;
+                        }
+                        self.updated = true;
+                    }
+                    self.state = EepromState::Idle;
+                    self.dout = true;
+                }
+            },
+        }
+    }
+
+    fn command(&mut self) {
+        let opcode = (self.shift >> 8) & 0x3;
+        let address = (self.shift & 0xFF) as usize;
+        self.address = address % EEPROM_WORDS;
+        self.shift = 0;
+        self.bits = 0;
+        self.state = EepromState::Idle;
+
+        match opcode {
+            0b10 => {
+                // A dummy zero bit precedes the data
+                self.state = EepromState::Read;
+                self.shift = self.data[self.address] as u32;
+                self.dout = false;
+            },
+            0b01 => self.state = EepromState::Write,
+            0b11 => {
+                if self.write_enabled {
+                    self.data[self.address] = 0xFFFF;
+                    self.updated = true;
+                }
+            },
+            _ => match address >> 6 {
+                0b00 => self.write_enabled = false,
+                0b01 => self.state = EepromState::WriteAll,
+                0b10 => {
+                    if self.write_enabled {
+                        self.data = [0xFFFF; EEPROM_WORDS];
+                        self.updated = true;
+                    }
+                },
+                _ => self.write_enabled = true,
+            },
+        }
+    }
+}
+
+pub struct MBC7 {
+    rom: Vec<u8>,
+    rombank: usize,
+    rombanks: usize,
+    ram_on: bool,
+    ram_on2: bool,
+    accel_x: f32,
+    accel_y: f32,
+    latch_erased: bool,
+    latch_x: u16,
+    latch_y: u16,
+    eeprom: Eeprom,
+}
+
+impl MBC7 {
+    pub fn new(data: Vec<u8>) -> StrResult<MBC7> {
+        let rombanks = rom_banks(data[0x148]);
+        if rombanks == 0 { return Err("Unsupported ROM size for MBC7"); }
+
+        let res = MBC7 {
+            rom: data,
+            rombank: 1,
+            rombanks,
+            ram_on: false,
+            ram_on2: false,
+            accel_x: 0.0,
+            accel_y: 0.0,
+            latch_erased: false,
+            latch_x: ACCEL_ERASED,
+            latch_y: ACCEL_ERASED,
+            eeprom: Eeprom::new(),
+        };
+
+        Ok(res)
+    }
+
+    fn latch_accelerometer(&mut self) {
+        let to_reg = |g: f32| (ACCEL_CENTER + g * ACCEL_GRAVITY) as u16;
+        self.latch_x = to_reg(self.accel_x);
+        self.latch_y = to_reg(self.accel_y);
+    }
+}
+
+impl MBC for MBC7 {
+    fn readrom(&self, a: u16) -> u8 {
+        let idx = if a < 0x4000 { a as usize }
+        else { (self.rombank * 0x4000) | ((a as usize) & 0x3FFF) };
+        *self.rom.get(idx).unwrap_or(&0xFF)
+    }
+    fn readram(&self, a: u16) -> u8 {
+        if !self.ram_on || !self.ram_on2 || a >= 0xB000 { return 0xFF }
+        match a & 0xF0 {
+            0x20 => self.latch_x as u8,
+            0x30 => (self.latch_x >> 8) as u8,
+            0x40 => self.latch_y as u8,
+            0x50 => (self.latch_y >> 8) as u8,
+            0x60 => 0x00,
+            0x80 => self.eeprom.rb(),
+            _ => 0xFF,
+        }
+    }
+    fn writerom(&mut self, a: u16, v: u8) {
+        match a {
+            0x0000 ..= 0x1FFF => self.ram_on = v & 0x0F == 0x0A,
+            0x2000 ..= 0x3FFF => self.rombank = (v as usize) % self.rombanks,
+            0x4000 ..= 0x5FFF => self.ram_on2 = v == 0x40,
+            0x6000 ..= 0x7FFF => {},
+            _ => panic!("Could not write to {:04X} (MBC7)", a),
+        }
+    }
+    fn writeram(&mut self, a: u16, v: u8) {
+        if !self.ram_on || !self.ram_on2 || a >= 0xB000 { return }
+        match a & 0xF0 {
+            0x00 if v == 0x55 => {
+                self.latch_erased = true;
+                self.latch_x = ACCEL_ERASED;
+                self.latch_y = ACCEL_ERASED;
+            },
+            0x10 if v == 0xAA && self.latch_erased => {
+                self.latch_erased = false;
+                self.latch_accelerometer();
+            },
+            0x80 => self.eeprom.wb(v),
+            _ => {},
+        }
+    }
+
+    fn is_battery_backed(&self) -> bool {
+        true
+    }
+
+    fn loadram(&mut self, ramdata: &[u8]) -> StrResult<()> {
+        if ramdata.len() != EEPROM_WORDS * 2 {
+            return Err("Loaded EEPROM has incorrect length");
+        }
+
+        for (word, bytes) in self.eeprom.data.iter_mut().zip(ramdata.chunks(2)) {
+            *word = u16::from_le_bytes([bytes[0], bytes[1]]);
+        }
+
+        Ok(())
+    }
+
+    fn dumpram(&self) -> Vec<u8> {
+        self.eeprom.data.iter().flat_map(|w| w.to_le_bytes()).collect()
+    }
+
+    fn check_and_reset_ram_updated(&mut self) -> bool {
+        let result = self.eeprom.updated;
+        self.eeprom.updated = false;
+        result
+    }
+
+    fn set_accelerometer(&mut self, x: f32, y: f32) {
+        self.accel_x = x;
+        self.accel_y = y;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::MBC7;
+    use crate::mbc::MBC;
+
+    const CS: u8 = 0x80;
+    const CLK: u8 = 0x40;
+
+    fn mbc() -> MBC7 {
+        let mut data = vec![0u8; 0x8000];
+        data[0x147] = 0x22;
+        let mut mbc = MBC7::new(data).unwrap();
+        mbc.writerom(0x0000, 0x0A);
+        mbc.writerom(0x4000, 0x40);
+        mbc
+    }
+
+    fn clock_bit(mbc: &mut MBC7, bit: u32) -> u8 {
+        let di = if bit != 0 { 0x02 } else { 0 };
+        mbc.writeram(0xA080, CS | di);
+        mbc.writeram(0xA080, CS | CLK | di);
+        mbc.readram(0xA080) & 0x01
+    }
+
+    // Start bit, opcode and address
+    fn command(opcode: u32, address: u32) -> u32 {
+        (1 << 10) | (opcode << 8) | address
+    }
+
+    fn send(mbc: &mut MBC7, value: u32, bits: u32) {
+        mbc.writeram(0xA080, 0x00);
+        for i in (0 .. bits).rev() {
+            clock_bit(mbc, (value >> i) & 1);
+        }
+    }
+
+    #[test]
+    fn eeprom_write_and_read() {
+        let mut mbc = mbc();
+
+        // EWEN, then WRITE 0x1234 to address 5
+        send(&mut mbc, command(0b00, 0xC0), 11);
+        send(&mut mbc, (command(0b01, 5) << 16) | 0x1234, 27);
+        assert!(mbc.check_and_reset_ram_updated());
+
+        // READ address 5, the first bit out is a dummy zero
+        send(&mut mbc, command(0b10, 5), 11);
+        assert_eq!(mbc.readram(0xA080) & 0x01, 0);
+        let mut value = 0;
+        for _ in 0 .. 16 {
+            value = (value << 1) | clock_bit(&mut mbc, 0) as u16;
+        }
+        assert_eq!(value, 0x1234);
+
+        let saved = mbc.dumpram();
+        assert_eq!(&saved[10 .. 12], &[0x34, 0x12]);
+    }
+
+    #[test]
+    fn accelerometer_latch() {
+        let mut mbc = mbc();
+        mbc.set_accelerometer(1.0, -1.0);
+
+        // Latching without erasing first has no effect
+        mbc.writeram(0xA010, 0xAA);
+        assert_eq!(mbc.readram(0xA030), 0x80);
+        assert_eq!(mbc.readram(0xA020), 0x00);
+
+        mbc.writeram(0xA000, 0x55);
+        mbc.writeram(0xA010, 0xAA);
+        let x = mbc.readram(0xA020) as u16 | ((mbc.readram(0xA030) as u16) << 8);
+        let y = mbc.readram(0xA040) as u16 | ((mbc.readram(0xA050) as u16) << 8);
+        assert_eq!(x, 0x81D0 + 0x70);
+        assert_eq!(y, 0x81D0 - 0x70);
+    }
+}
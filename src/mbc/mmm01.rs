@@ -0,0 +1,222 @@
+use crate::mbc::ram_banks;
+use crate::mbc::MBC;
+use crate::StrResult;
+
+/// MMM01 multicart. After reset the last 32 KiB, holding the menu, is mapped. The menu then
+/// selects the base bank and bank sizes of a game and locks the mapper, after which it behaves
+/// like an MBC1 confined to that game.
+pub struct MMM01 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    rombanks: usize,
+    rambanks: usize,
+    ram_on: bool,
+    ram_updated: bool,
+    has_battery: bool,
+    locked: bool,
+    rom_low: u8,
+    rom_mid: u8,
+    rom_high: u8,
+    rom_mask: u8,
+    ram_low: u8,
+    ram_high: u8,
+    ram_mask: u8,
+    mbc1_mode: bool,
+    mbc1_mode_disable: bool,
+    multiplex: bool,
+}
+
+impl MMM01 {
+    pub fn new(mut data: Vec<u8>) -> StrResult<MMM01> {
+        if data.len() < 0x10000 || !data.len().is_multiple_of(0x4000) {
+            return Err("Unsupported ROM size for MMM01");
+        }
+
+        // Dumps either start with the menu, or have it at the end where the hardware expects it
+        if is_mmm01_type(data[0x147]) {
+            data.rotate_left(0x8000);
+        }
+        let menu = data.len() - 0x8000;
+        let has_battery = data[menu + 0x147] == 0x0D;
+        let rambanks = match data[menu + 0x149] {
+            0 => 0,
+            n => ram_banks(n),
+        };
+
+        let res = MMM01 {
+            rombanks: data.len() / 0x4000,
+            rom: data,
+            ram: vec![0; rambanks * 0x2000],
+            rambanks,
+            ram_on: false,
+            ram_updated: false,
+            has_battery,
+            locked: false,
+            rom_low: 0,
+            rom_mid: 0,
+            rom_high: 0,
+            rom_mask: 0,
+            ram_low: 0,
+            ram_high: 0,
+            ram_mask: 0x3,
+            mbc1_mode: false,
+            mbc1_mode_disable: false,
+            multiplex: false,
+        };
+
+        Ok(res)
+    }
+
+    // Banks mapped at 0000-3FFF and 4000-7FFF
+    fn rombanks_mapped(&self) -> (usize, usize) {
+        if !self.locked {
+            return (self.rombanks - 2, self.rombanks - 1);
+        }
+
+        let low = self.rom_low as usize;
+        let fixed_low = low & ((self.rom_mask as usize) << 1);
+        let high = (self.rom_high as usize) << 7;
+        let (rom0, rom) = if self.multiplex {
+            let mid0 = if self.mbc1_mode { 0 } else { self.ram_low as usize };
+            (fixed_low | (mid0 << 5) | high, low | ((self.ram_low as usize) << 5) | high)
+        } else {
+            let mid = (self.rom_mid as usize) << 5;
+            (fixed_low | mid | high, low | mid | high)
+        };
+        let rom = if rom == rom0 { rom + 1 } else { rom };
+        (rom0 % self.rombanks, rom % self.rombanks)
+    }
+
+    fn ramaddress(&self, a: u16) -> Option<usize> {
+        if !self.ram_on || self.rambanks == 0 { return None }
+        let bank = if self.multiplex {
+            self.rom_mid | (self.ram_high << 2)
+        } else {
+            self.ram_low | (self.ram_high << 2)
+        } as usize;
+        Some(((bank % self.rambanks) * 0x2000) | ((a as usize) & 0x1FFF))
+    }
+}
+
+/// Returns true for the cartridge types of the MMM01
+pub fn is_mmm01_type(v: u8) -> bool {
+    (0x0B ..= 0x0D).contains(&v)
+}
+
+impl MBC for MMM01 {
+    fn readrom(&self, a: u16) -> u8 {
+        let (rom0, rom) = self.rombanks_mapped();
+        let bank = if a < 0x4000 { rom0 } else { rom };
+        *self.rom.get((bank * 0x4000) | ((a as usize) & 0x3FFF)).unwrap_or(&0xFF)
+    }
+    fn readram(&self, a: u16) -> u8 {
+        match self.ramaddress(a) {
+            Some(idx) => self.ram[idx],
+            None => 0xFF,
+        }
+    }
+    fn writerom(&mut self, a: u16, v: u8) {
+        match a {
+            0x0000 ..= 0x1FFF => {
+                self.ram_on = v & 0x0F == 0x0A;
+                if !self.locked {
+                    self.ram_mask = (v >> 4) & 0x3;
+                    self.locked = v & 0x40 == 0x40;
+                }
+            },
+            0x2000 ..= 0x3FFF => {
+                if !self.locked {
+                    self.rom_mid = (v >> 5) & 0x3;
+                }
+                // Bits covered by the mask keep the value selected by the menu
+                let mask = self.rom_mask << 1;
+                self.rom_low = ((self.rom_low & mask) | (v & !mask)) & 0x1F;
+            },
+            0x4000 ..= 0x5FFF => {
+                self.ram_low = (v | !self.ram_mask) & 0x3;
+                if !self.locked {
+                    self.ram_high = (v >> 2) & 0x3;
+                    self.rom_high = (v >> 4) & 0x3;
+                    self.mbc1_mode_disable = v & 0x40 == 0x40;
+                }
+            },
+            0x6000 ..= 0x7FFF => {
+                if !self.mbc1_mode_disable {
+                    self.mbc1_mode = v & 0x01 == 0x01;
+                }
+                if !self.locked {
+                    self.rom_mask = (v >> 2) & 0x0F;
+                    self.multiplex = v & 0x40 == 0x40;
+                }
+            },
+            _ => panic!("Could not write to {:04X} (MMM01)", a),
+        }
+    }
+    fn writeram(&mut self, a: u16, v: u8) {
+        if let Some(idx) = self.ramaddress(a) {
+            self.ram[idx] = v;
+            self.ram_updated = true;
+        }
+    }
+
+    fn is_battery_backed(&self) -> bool {
+        self.has_battery
+    }
+
+    fn loadram(&mut self, ramdata: &[u8]) -> StrResult<()> {
+        if ramdata.len() != self.ram.len() {
+            return Err("Loaded ram has incorrect length");
+        }
+
+        self.ram = ramdata.to_vec();
+        Ok(())
+    }
+
+    fn dumpram(&self) -> Vec<u8> {
+        self.ram.clone()
+    }
+
+    fn check_and_reset_ram_updated(&mut self) -> bool {
+        let result = self.ram_updated;
+        self.ram_updated = false;
+        result
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::MMM01;
+    use crate::mbc::MBC;
+
+    // Sixteen banks, each starting with its own number once the menu is moved to the end
+    fn rom() -> Vec<u8> {
+        let mut data = vec![0u8; 0x40000];
+        for bank in 0 .. 16 {
+            data[((bank + 2) % 16) * 0x4000] = bank as u8;
+        }
+        data[0x147] = 0x0B;
+        data
+    }
+
+    #[test]
+    fn menu_then_lock() {
+        let mut mbc = MMM01::new(rom()).unwrap();
+        assert_eq!(mbc.readrom(0x0000), 14);
+        assert_eq!(mbc.readrom(0x4000), 15);
+
+        // Select the game at bank 4 with a 32 KiB size, then lock
+        mbc.writerom(0x2000, 0x04);
+        mbc.writerom(0x6000, 0x3C);
+        mbc.writerom(0x0000, 0x40);
+        assert_eq!(mbc.readrom(0x0000), 4);
+        assert_eq!(mbc.readrom(0x4000), 5);
+
+        // The game can no longer leave its banks or change the mask
+        mbc.writerom(0x2000, 0x03);
+        assert_eq!(mbc.readrom(0x4000), 5);
+        mbc.writerom(0x6000, 0x00);
+        mbc.writerom(0x2000, 0x08);
+        assert_eq!(mbc.readrom(0x0000), 4);
+        assert_eq!(mbc.readrom(0x4000), 5);
+    }
+}
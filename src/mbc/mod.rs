@@ -1,14 +1,29 @@
+use crate::archive;
+use crate::camera::ImageSource;
+use crate::mbc::header::{CartridgeWarning, Mapper, NINTENDO_LOGO, LOGO_START};
+use crate::patch;
 use crate::StrResult;
 use std::io;
 use std::io::prelude::*;
 use std::fs::{self, File};
 use std::path;
 
+pub mod header;
+
 mod mbc0;
 mod mbc1;
 mod mbc2;
 mod mbc3;
 mod mbc5;
+mod mbc6;
+mod mbc7;
+mod huc1;
+mod huc3;
+mod mmm01;
+mod wisdomtree;
+mod m161;
+mod camera;
+mod tama5;
 
 pub trait MBC : Send {
     fn readrom(&self, a: u16) -> u8;
@@ -21,6 +36,33 @@ pub trait MBC : Send {
     fn loadram(&mut self, ramdata: &[u8]) -> StrResult<()>;
     fn dumpram(&self) -> Vec<u8>;
 
+    /// Sets the tilt of the cartridge in g along both axes, for cartridges with an accelerometer
+    fn set_accelerometer(&mut self, _x: f32, _y: f32) {}
+
+    /// Returns true while a speaker on the cartridge is sounding
+    fn speaker_on(&self) -> bool { false }
+
+    /// Selects whether a cartridge RTC follows the system clock or the emulated clock cycles
+    fn set_rtc_mode(&mut self, _mode: RtcMode) {}
+
+    /// Sets the time of a cartridge RTC, in seconds since day 0
+    fn set_rtc_time(&mut self, _seconds: u64) {}
+
+    /// Moves the time of a cartridge RTC forward or back by whole days
+    fn offset_rtc_days(&mut self, _days: i64) {}
+
+    /// Returns true while the rumble motor of the cartridge is running
+    fn rumble_on(&self) -> bool { false }
+
+    /// Advances hardware on the cartridge that runs on the system clock
+    fn do_cycle(&mut self, _ticks: u32) {}
+
+    /// Sets where a cartridge with a camera gets its images from
+    fn set_image_source(&mut self, _source: Box<dyn ImageSource>) {}
+
+    /// Returns true while flash memory on the cartridge accepts writes
+    fn flash_writable(&self) -> bool { false }
+
     fn romname(&self) -> String {
         const TITLE_START : u16 = 0x134;
         const CGB_FLAG : u16 = 0x143;
@@ -44,32 +86,104 @@ pub trait MBC : Send {
 }
 
 pub fn get_mbc(data: Vec<u8>, skip_checksum: bool) -> StrResult<Box<dyn MBC+'static>> {
+    get_mbc_with_mapper(data, skip_checksum, None)
+}
+
+/// Creates the MBC for a cartridge. When `mapper` is not given it is detected from the header
+/// using `detect_mapper`.
+pub fn get_mbc_with_mapper(data: Vec<u8>, skip_checksum: bool, mapper: Option<Mapper>) -> StrResult<Box<dyn MBC+'static>> {
     if data.len() < 0x150 { return Err("Rom size to small"); }
     if !skip_checksum {
         check_checksum(&data)?;
     }
-    match data[0x147] {
-        0x00 => mbc0::MBC0::new(data).map(|v| Box::new(v) as Box<dyn MBC>),
-        0x01 ..= 0x03 => mbc1::MBC1::new(data).map(|v| Box::new(v) as Box<dyn MBC>),
-        0x05 ..= 0x06 => mbc2::MBC2::new(data).map(|v| Box::new(v) as Box<dyn MBC>),
-        0x0F ..= 0x13 => mbc3::MBC3::new(data).map(|v| Box::new(v) as Box<dyn MBC>),
-        0x19 ..= 0x1E => mbc5::MBC5::new(data).map(|v| Box::new(v) as Box<dyn MBC>),
+    match mapper.unwrap_or_else(|| detect_mapper(&data)) {
+        Mapper::RomOnly => mbc0::MBC0::new(data).map(|v| Box::new(v) as Box<dyn MBC>),
+        Mapper::MBC1 => mbc1::MBC1::new(data).map(|v| Box::new(v) as Box<dyn MBC>),
+        Mapper::MBC2 => mbc2::MBC2::new(data).map(|v| Box::new(v) as Box<dyn MBC>),
+        Mapper::MMM01 => mmm01::MMM01::new(data).map(|v| Box::new(v) as Box<dyn MBC>),
+        Mapper::MBC3 => mbc3::MBC3::new(data).map(|v| Box::new(v) as Box<dyn MBC>),
+        Mapper::MBC5 => mbc5::MBC5::new(data).map(|v| Box::new(v) as Box<dyn MBC>),
+        Mapper::MBC6 => mbc6::MBC6::new(data).map(|v| Box::new(v) as Box<dyn MBC>),
+        Mapper::MBC7 => mbc7::MBC7::new(data).map(|v| Box::new(v) as Box<dyn MBC>),
+        Mapper::PocketCamera => camera::PocketCamera::new(data).map(|v| Box::new(v) as Box<dyn MBC>),
+        Mapper::TAMA5 => tama5::TAMA5::new(data).map(|v| Box::new(v) as Box<dyn MBC>),
+        Mapper::HuC3 => huc3::HuC3::new(data).map(|v| Box::new(v) as Box<dyn MBC>),
+        Mapper::HuC1 => huc1::HuC1::new(data).map(|v| Box::new(v) as Box<dyn MBC>),
+        Mapper::WisdomTree => wisdomtree::WisdomTree::new(data).map(|v| Box::new(v) as Box<dyn MBC>),
+        Mapper::M161 => m161::M161::new(data).map(|v| Box::new(v) as Box<dyn MBC>),
+        Mapper::ScrambledMBC1(order) => mbc1::MBC1::new_scrambled(data, order).map(|v| Box::new(v) as Box<dyn MBC>),
         _ => { Err("Unsupported MBC type") },
     }
 }
 
+/// Determines the mapper of a cartridge. This is the mapper declared by the cartridge type,
+/// except for multicarts and unlicensed cartridges that are recognised by their contents.
+pub fn detect_mapper(data: &[u8]) -> Mapper {
+    let declared = header::CartridgeHeader::parse(data).map(|h| h.mapper).unwrap_or(Mapper::Unknown);
+
+    // MMM01 dumps may have the menu, and so the MMM01 header, at the end of the ROM
+    if data.len() >= 0x10000 {
+        let menu = data.len() - 0x8000;
+        let logo_matches = data[LOGO_START .. LOGO_START + NINTENDO_LOGO.len()]
+            == data[menu + LOGO_START .. menu + LOGO_START + NINTENDO_LOGO.len()];
+        if logo_matches && (mmm01::is_mmm01_type(data[menu + 0x147]) || data[menu + 0x147] == 0x11) {
+            return Mapper::MMM01;
+        }
+    }
+
+    match data[0x147] {
+        0x00 if data.len() > 0x8000 && is_wisdom_tree(data) => Mapper::WisdomTree,
+        0x10 if data.len() == 0x40000 && data[0x134 .. 0x13E] == *b"TETRIS SET" => Mapper::M161,
+        0x01 ..= 0x03 => mbc1::scrambled_bit_order(data).map_or(declared, Mapper::ScrambledMBC1),
+        _ => declared,
+    }
+}
+
+fn is_wisdom_tree(data: &[u8]) -> bool {
+    let bank0 = &data[.. ::std::cmp::min(data.len(), 0x8000)];
+    bank0.windows(11).any(|w| w == b"WISDOM TREE" || w == b"WISDOM\0TREE")
+}
+
+/// Options used when loading a cartridge from a file
+#[derive(Default, Clone)]
+pub struct RomOptions {
+    /// Skips verification of the cartridge header checksum
+    pub skip_checksum: bool,
+    /// IPS, UPS or BPS patch to apply. When not set, a patch with the same name as the ROM is
+    /// used if it exists (e.g. `rom.ips` for `rom.gb`)
+    pub patch: Option<path::PathBuf>,
+    /// Name of the ROM to load from a zip archive. When not set, the first `.gb` or `.gbc` file
+    /// in the archive is used
+    pub archive_entry: Option<String>,
+    /// Mapper to use instead of the one detected from the ROM
+    pub mapper: Option<Mapper>,
+    /// Clock followed by the cartridge RTC
+    pub rtc_mode: RtcMode,
+}
+
+/// Clock followed by a cartridge RTC
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RtcMode {
+    /// Follows the system clock, so time also passes while the emulator is not running
+    #[default]
+    WallClock,
+    /// Advances with the emulated clock cycles, so it is paused and fast forwarded with the
+    /// emulation and runs the same on every run
+    Emulated,
+}
+
 pub struct FileBackedMBC {
     rampath: path::PathBuf,
     mbc: Box<dyn MBC>,
+    warnings: Vec<CartridgeWarning>,
 }
 
 impl FileBackedMBC {
-    pub fn new(rompath: path::PathBuf, skip_checksum: bool) -> StrResult<FileBackedMBC> {
-        let mut data = vec![];
-        File::open(&rompath).and_then(|mut f| f.read_to_end(&mut data)).map_err(|_| "Could not read ROM")?;
-        let mut mbc = get_mbc(data, skip_checksum)?;
-
-        let rampath = rompath.with_extension("gbsave");
+    pub fn new(rompath: path::PathBuf, options: &RomOptions) -> StrResult<FileBackedMBC> {
+        let (data, rampath) = read_rom_and_rampath(&rompath, options)?;
+        let warnings = header::validate_rom(&data);
+        let mut mbc = get_mbc_with_mapper(data, options.skip_checksum, options.mapper)?;
+        mbc.set_rtc_mode(options.rtc_mode);
 
         if mbc.is_battery_backed() {
             match fs::File::open(&rampath) {
@@ -85,7 +199,20 @@ impl FileBackedMBC {
             }
         }
 
-        Ok(FileBackedMBC { rampath, mbc })
+        Ok(FileBackedMBC { rampath, mbc, warnings })
+    }
+
+    /// Problems found in the ROM while loading it, see `header::validate_rom`
+    pub fn warnings(&self) -> &[CartridgeWarning] {
+        &self.warnings
+    }
+
+    /// File next to the save in which the emulator settings are recorded, if the cartridge saves
+    pub fn settings_path(&self) -> Option<path::PathBuf> {
+        match self.mbc.is_battery_backed() {
+            true => Some(self.rampath.with_extension("gbsettings")),
+            false => None,
+        }
     }
 }
 
@@ -122,6 +249,37 @@ impl MBC for FileBackedMBC {
     fn check_and_reset_ram_updated(&mut self) -> bool {
         self.mbc.check_and_reset_ram_updated()
     }
+
+    fn set_accelerometer(&mut self, x: f32, y: f32) {
+        self.mbc.set_accelerometer(x, y)
+    }
+
+    fn speaker_on(&self) -> bool {
+        self.mbc.speaker_on()
+    }
+    fn rumble_on(&self) -> bool {
+        self.mbc.rumble_on()
+    }
+    fn set_rtc_mode(&mut self, mode: RtcMode) {
+        self.mbc.set_rtc_mode(mode)
+    }
+    fn set_rtc_time(&mut self, seconds: u64) {
+        self.mbc.set_rtc_time(seconds)
+    }
+    fn offset_rtc_days(&mut self, days: i64) {
+        self.mbc.offset_rtc_days(days)
+    }
+
+    fn do_cycle(&mut self, ticks: u32) {
+        self.mbc.do_cycle(ticks)
+    }
+
+    fn set_image_source(&mut self, source: Box<dyn ImageSource>) {
+        self.mbc.set_image_source(source)
+    }
+    fn flash_writable(&self) -> bool {
+        self.mbc.flash_writable()
+    }
 }
 
 
@@ -138,6 +296,45 @@ impl Drop for FileBackedMBC {
     }
 }
 
+/// Reads a ROM from a file. Archives are unpacked and patches are applied as specified in options.
+pub fn read_rom(rompath: &path::Path, options: &RomOptions) -> StrResult<Vec<u8>> {
+    read_rom_and_rampath(rompath, options).map(|(data, _)| data)
+}
+
+fn read_rom_and_rampath(rompath: &path::Path, options: &RomOptions) -> StrResult<(Vec<u8>, path::PathBuf)> {
+    let filedata = read_file(rompath).map_err(|_| "Could not read ROM")?;
+    let data = archive::extract_rom(filedata, options.archive_entry.as_deref())?;
+
+    let patchpath = match options.patch {
+        Some(ref p) => Some(p.clone()),
+        None => find_patch(rompath),
+    };
+
+    // A patched ROM gets its own save file, as the save layout may differ from the original
+    match patchpath {
+        Some(p) => {
+            let patchdata = read_file(&p).map_err(|_| "Could not read patch")?;
+            let data = patch::apply(&data, &patchdata)?;
+            let mut name = p.into_os_string();
+            name.push(".gbsave");
+            Ok((data, path::PathBuf::from(name)))
+        },
+        None => Ok((data, rompath.with_extension("gbsave"))),
+    }
+}
+
+fn read_file(filepath: &path::Path) -> io::Result<Vec<u8>> {
+    let mut data = vec![];
+    File::open(filepath).and_then(|mut f| f.read_to_end(&mut data))?;
+    Ok(data)
+}
+
+fn find_patch(rompath: &path::Path) -> Option<path::PathBuf> {
+    patch::PATCH_EXTENSIONS.iter()
+        .map(|ext| rompath.with_extension(ext))
+        .find(|p| p.is_file())
+}
+
 fn ram_banks(v: u8) -> usize {
     match v {
         1 =>
@@ -163,11 +360,7 @@ fn rom_banks(v: u8) -> usize {
 }
 
 fn check_checksum(data: &[u8]) -> StrResult<()> {
-    let mut value: u8 = 0;
-    for i in 0x134 .. 0x14D {
-        value = value.wrapping_sub(data[i]).wrapping_sub(1);
-    }
-    match data[0x14D] == value
+    match data[0x14D] == header::compute_header_checksum(data)
     {
         true => Ok(()),
         false => Err("Cartridge checksum is invalid"),
@@ -189,4 +382,40 @@ mod test {
         data[0x14D] = (-(0x14D_i32 - 0x134_i32) * 2) as u8;
         super::check_checksum(&data).unwrap();
     }
+
+    #[test]
+    fn detect_unlicensed_mappers() {
+        use super::detect_mapper;
+        use super::header::{Mapper, NINTENDO_LOGO, LOGO_START};
+
+        let mut data = vec![0u8; 0x20000];
+        data[0x1000 .. 0x100B].copy_from_slice(b"WISDOM TREE");
+        assert_eq!(detect_mapper(&data), Mapper::WisdomTree);
+
+        // MMM01 menu at the end of the ROM
+        let mut data = vec![0u8; 0x20000];
+        data[0x147] = 0x01;
+        for start in [0, 0x18000] {
+            data[start + LOGO_START .. start + LOGO_START + NINTENDO_LOGO.len()].copy_from_slice(&NINTENDO_LOGO);
+        }
+        data[0x18147] = 0x0D;
+        assert_eq!(detect_mapper(&data), Mapper::MMM01);
+        data[0x18147] = 0x01;
+        assert_eq!(detect_mapper(&data), Mapper::MBC1);
+
+        // Collection whose menu selects banks 2 and 6 with bits 0 and 1 of the register swapped
+        let mut data = vec![0u8; 0x40000];
+        data[0x147] = 0x01;
+        data[0x148] = 0x03;
+        data[0x200 .. 0x20A].copy_from_slice(&[0x3E, 0x01, 0xEA, 0x00, 0x20, 0x3E, 0x05, 0xEA, 0x00, 0x21]);
+        for bank in [0, 2, 6] {
+            let start = bank * 0x4000 + LOGO_START;
+            data[start .. start + NINTENDO_LOGO.len()].copy_from_slice(&NINTENDO_LOGO);
+        }
+        assert_eq!(detect_mapper(&data), Mapper::ScrambledMBC1([1, 0, 2, 3, 4]));
+        // The same menu on a normal MBC1 collection
+        data[0x201] = 0x02;
+        data[0x206] = 0x06;
+        assert_eq!(detect_mapper(&data), Mapper::MBC1);
+    }
 }
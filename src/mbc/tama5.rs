@@ -0,0 +1,462 @@
+use crate::mbc::{MBC, rom_banks};
+use crate::StrResult;
+
+use std::time;
+use std::convert::TryInto;
+
+// Registers selected by writing to A001, values are written as nibbles to A000
+const REG_BANK_LO: u8 = 0x0;
+const REG_BANK_HI: u8 = 0x1;
+const REG_WRITE_LO: u8 = 0x4;
+const REG_WRITE_HI: u8 = 0x5;
+const REG_ADDR_HI: u8 = 0x6;
+const REG_ADDR_LO: u8 = 0x7;
+const REG_COUNT: usize = 0x8;
+const REG_ACTIVE: u8 = 0xA;
+const REG_READ_LO: u8 = 0xC;
+const REG_READ_HI: u8 = 0xD;
+
+// Commands in the upper bits of ADDR_HI, started by writing ADDR_LO
+const CMD_RAM_WRITE: u8 = 0x0;
+const CMD_RAM_READ: u8 = 0x1;
+const CMD_MISC: u8 = 0x2;
+const CMD_RTC: u8 = 0x4;
+
+// Miscellaneous commands, selected by the address
+const MISC_DISABLE_TIMER: u8 = 0x00;
+const MISC_ENABLE_TIMER: u8 = 0x01;
+const MISC_MINUTE_WRITE: u8 = 0x04;
+const MISC_HOUR_WRITE: u8 = 0x05;
+const MISC_MINUTE_READ: u8 = 0x06;
+const MISC_HOUR_READ: u8 = 0x07;
+const MISC_DISABLE_ALARM: u8 = 0x10;
+const MISC_ENABLE_ALARM: u8 = 0x11;
+const MISC_ALARM_READ: u8 = 0x12;
+
+const RAM_SIZE: usize = 0x20;
+
+// The RTC has four pages of 13 nibble registers: the time, the alarm and two pages of free RAM.
+// The mode register selects the page and enables the alarm and timer.
+const RTC_PAGES: usize = 4;
+const RTC_PAGE_SIZE: usize = 13;
+const MODE_ALARM: u8 = 0x4;
+const MODE_TIMER: u8 = 0x8;
+// Set once the time reaches the hour and minute of the alarm page while the alarm is enabled
+const MODE_ALARM_FIRED: u8 = 0x10;
+
+// Registers in the time page, each a BCD digit except for the weekday
+const SECOND: usize = 0x0;
+const MINUTE: usize = 0x2;
+const HOUR: usize = 0x4;
+const WEEKDAY: usize = 0x6;
+const DAY: usize = 0x7;
+const MONTH: usize = 0x9;
+const YEAR: usize = 0xB;
+// Years since the last leap year, in the alarm page
+const LEAP_YEAR: usize = 0xB;
+
+const SECONDS_PER_DAY: u64 = 24 * 3600;
+
+const SAVE_SIZE: usize = 8 + RTC_PAGES * RTC_PAGE_SIZE + 1 + RAM_SIZE;
+
+/// TAMA5 mapper with a TAMA6 RTC and a small EEPROM, all accessed through nibble registers.
+/// The save holds the time the RTC was last updated as an 8-byte unix time, followed by the RTC
+/// pages, the RTC mode register and the EEPROM.
+pub struct TAMA5 {
+    rom: Vec<u8>,
+    ram: [u8; RAM_SIZE],
+    rombanks: usize,
+    reg: u8,
+    registers: [u8; REG_COUNT],
+    rtc: [[u8; RTC_PAGE_SIZE]; RTC_PAGES],
+    rtc_mode: u8,
+    rtc_last: u64,
+    ram_updated: bool,
+}
+
+impl TAMA5 {
+    pub fn new(data: Vec<u8>) -> StrResult<TAMA5> {
+        let rombanks = rom_banks(data[0x148]);
+        if rombanks == 0 { return Err("Unsupported ROM size for TAMA5"); }
+
+        let mut rtc = [[0u8; RTC_PAGE_SIZE]; RTC_PAGES];
+        rtc[0][DAY] = 1;
+        rtc[0][MONTH] = 1;
+
+        let res = TAMA5 {
+            rom: data,
+            ram: [0; RAM_SIZE],
+            rombanks,
+            reg: 0,
+            registers: [0; REG_COUNT],
+            rtc,
+            rtc_mode: MODE_TIMER,
+            rtc_last: unix_now(),
+            ram_updated: false,
+        };
+
+        Ok(res)
+    }
+
+    fn rombank(&self) -> usize {
+        let bank = self.registers[REG_BANK_LO as usize] | (self.registers[REG_BANK_HI as usize] << 4);
+        (bank as usize) % self.rombanks
+    }
+
+    fn command(&self) -> u8 {
+        self.registers[REG_ADDR_HI as usize] >> 1
+    }
+
+    fn address(&self) -> u8 {
+        ((self.registers[REG_ADDR_HI as usize] << 4) & 0x10) | self.registers[REG_ADDR_LO as usize]
+    }
+
+    fn write_value(&self) -> u8 {
+        (self.registers[REG_WRITE_HI as usize] << 4) | self.registers[REG_WRITE_LO as usize]
+    }
+
+    fn write_register(&mut self, v: u8) {
+        if self.reg as usize >= REG_COUNT { return }
+        self.registers[self.reg as usize] = v & 0x0F;
+        if self.reg == REG_ADDR_LO {
+            self.run_command();
+        }
+    }
+
+    fn run_command(&mut self) {
+        let address = self.address();
+        let value = self.write_value();
+        match self.command() {
+            CMD_RAM_WRITE => {
+                self.ram[address as usize] = value;
+                self.ram_updated = true;
+            },
+            CMD_MISC => self.misc_command(address, value),
+            CMD_RTC => {
+                // WRITE_LO holds the register, WRITE_HI the value and ADDR_LO the page times two
+                let index = self.registers[REG_WRITE_LO as usize] as usize;
+                let page = self.registers[REG_ADDR_LO as usize] as usize;
+                if index < RTC_PAGE_SIZE && page.is_multiple_of(2) && page / 2 < RTC_PAGES {
+                    self.sync_rtc();
+                    self.rtc[page / 2][index] = self.registers[REG_WRITE_HI as usize];
+                    self.ram_updated = true;
+                }
+            },
+            _ => {},
+        }
+    }
+
+    fn misc_command(&mut self, address: u8, value: u8) {
+        self.sync_rtc();
+        match address {
+            MISC_DISABLE_TIMER => self.rtc_mode &= !MODE_TIMER,
+            MISC_ENABLE_TIMER => {
+                self.rtc_mode |= MODE_TIMER;
+                self.rtc[0][SECOND] = 0;
+                self.rtc[0][SECOND + 1] = 0;
+            },
+            MISC_MINUTE_WRITE => {
+                self.rtc[0][MINUTE] = value & 0xF;
+                self.rtc[0][MINUTE + 1] = value >> 4;
+            },
+            MISC_HOUR_WRITE => {
+                self.rtc[0][HOUR] = value & 0xF;
+                self.rtc[0][HOUR + 1] = value >> 4;
+            },
+            MISC_DISABLE_ALARM => self.rtc_mode &= !(MODE_ALARM | MODE_ALARM_FIRED),
+            MISC_ENABLE_ALARM => self.rtc_mode = (self.rtc_mode | MODE_ALARM) & !MODE_ALARM_FIRED,
+            _ => return,
+        }
+        self.ram_updated = true;
+    }
+
+    fn read_value(&self) -> u8 {
+        let address = self.address();
+        match self.command() {
+            CMD_RAM_READ => self.ram[address as usize],
+            CMD_MISC => {
+                let time = self.current_time();
+                match address {
+                    MISC_MINUTE_READ => (time[MINUTE + 1] << 4) | time[MINUTE],
+                    MISC_HOUR_READ => (time[HOUR + 1] << 4) | time[HOUR],
+                    MISC_ALARM_READ => {
+                        let elapsed = unix_now().saturating_sub(self.rtc_last);
+                        let fired = self.rtc_mode & MODE_ALARM_FIRED != 0 || self.alarm_reached(&self.rtc[0], elapsed);
+                        fired as u8
+                    },
+                    _ => address,
+                }
+            },
+            CMD_RTC => {
+                // Reads use the odd page numbers
+                let index = self.registers[REG_WRITE_LO as usize] as usize;
+                let page = (self.registers[REG_ADDR_LO as usize] / 2) as usize;
+                match (index < RTC_PAGE_SIZE && page < RTC_PAGES, page) {
+                    (true, 0) => self.current_time()[index],
+                    (true, _) => self.rtc[page][index],
+                    (false, _) => 0,
+                }
+            },
+            _ => 0,
+        }
+    }
+
+    // The time page advanced to the current time
+    fn current_time(&self) -> [u8; RTC_PAGE_SIZE] {
+        let mut time = self.rtc[0];
+        if self.rtc_mode & MODE_TIMER == MODE_TIMER {
+            let mut leap = self.rtc[1][LEAP_YEAR];
+            advance_time(&mut time, &mut leap, unix_now().saturating_sub(self.rtc_last));
+        }
+        time
+    }
+
+    // Whether the hour and minute of the alarm come up within the given seconds after the time
+    fn alarm_reached(&self, time: &[u8; RTC_PAGE_SIZE], seconds: u64) -> bool {
+        if self.rtc_mode & (MODE_ALARM | MODE_TIMER) != MODE_ALARM | MODE_TIMER { return false }
+        let alarm = &self.rtc[1];
+        let now = get_bcd(time, HOUR) * 3600 + get_bcd(time, MINUTE) * 60 + get_bcd(time, SECOND);
+        let target = (get_bcd(alarm, HOUR) * 3600 + get_bcd(alarm, MINUTE) * 60) % SECONDS_PER_DAY;
+        let until = (target + SECONDS_PER_DAY - now % SECONDS_PER_DAY - 1) % SECONDS_PER_DAY + 1;
+        seconds >= until
+    }
+
+    fn sync_rtc(&mut self) {
+        let now = unix_now();
+        if self.alarm_reached(&self.rtc[0], now.saturating_sub(self.rtc_last)) {
+            self.rtc_mode |= MODE_ALARM_FIRED;
+        }
+        if self.rtc_mode & MODE_TIMER == MODE_TIMER {
+            let mut leap = self.rtc[1][LEAP_YEAR];
+            advance_time(&mut self.rtc[0], &mut leap, now.saturating_sub(self.rtc_last));
+            self.rtc[1][LEAP_YEAR] = leap;
+        }
+        self.rtc_last = now;
+    }
+}
+
+fn unix_now() -> u64 {
+    match time::SystemTime::now().duration_since(time::UNIX_EPOCH) {
+        Ok(t) => t.as_secs(),
+        Err(_) => panic!("System clock is set to a time before the unix epoch (1970-01-01)"),
+    }
+}
+
+fn get_bcd(page: &[u8], i: usize) -> u64 {
+    page[i] as u64 + page[i + 1] as u64 * 10
+}
+
+fn set_bcd(page: &mut [u8], i: usize, v: u64) {
+    page[i] = (v % 10) as u8;
+    page[i + 1] = (v / 10) as u8;
+}
+
+fn advance_time(time: &mut [u8; RTC_PAGE_SIZE], leap: &mut u8, seconds: u64) {
+    if seconds == 0 { return }
+
+    let total = get_bcd(time, SECOND) + seconds;
+    set_bcd(time, SECOND, total % 60);
+    let total = get_bcd(time, MINUTE) + total / 60;
+    set_bcd(time, MINUTE, total % 60);
+    let total = get_bcd(time, HOUR) + total / 60;
+    set_bcd(time, HOUR, total % 24);
+
+    for _ in 0 .. total / 24 {
+        time[WEEKDAY] = (time[WEEKDAY] + 1) % 7;
+        let month = get_bcd(time, MONTH);
+        let days_in_month = match month {
+            2 if *leap == 0 => 29,
+            2 => 28,
+            4 | 6 | 9 | 11 => 30,
+            _ => 31,
+        };
+        let day = get_bcd(time, DAY) + 1;
+        if day <= days_in_month {
+            set_bcd(time, DAY, day);
+            continue;
+        }
+        set_bcd(time, DAY, 1);
+        if month < 12 {
+            set_bcd(time, MONTH, month + 1);
+            continue;
+        }
+        set_bcd(time, MONTH, 1);
+        let year = get_bcd(time, YEAR);
+        set_bcd(time, YEAR, (year + 1) % 100);
+        *leap = (*leap + 1) % 4;
+    }
+}
+
+impl MBC for TAMA5 {
+    fn readrom(&self, a: u16) -> u8 {
+        let idx = if a < 0x4000 { a as usize }
+        else { (self.rombank() * 0x4000) | ((a as usize) & 0x3FFF) };
+        *self.rom.get(idx).unwrap_or(&0xFF)
+    }
+    fn readram(&self, a: u16) -> u8 {
+        if a & 1 == 1 { return 0xFF }
+        match self.reg {
+            // Games wait for this before using the mapper
+            REG_ACTIVE => 0xF1,
+            REG_READ_LO => 0xF0 | (self.read_value() & 0x0F),
+            REG_READ_HI => 0xF0 | (self.read_value() >> 4),
+            _ => 0xF0,
+        }
+    }
+    fn writerom(&mut self, a: u16, _v: u8) {
+        if a > 0x7FFF {
+            panic!("Could not write to {:04X} (TAMA5)", a);
+        }
+    }
+    fn writeram(&mut self, a: u16, v: u8) {
+        if a & 1 == 1 {
+            self.reg = v & 0x0F;
+        } else {
+            self.write_register(v);
+        }
+    }
+
+    fn is_battery_backed(&self) -> bool {
+        true
+    }
+
+    fn loadram(&mut self, ramdata: &[u8]) -> StrResult<()> {
+        if ramdata.len() != SAVE_SIZE {
+            return Err("Loaded ram has incorrect length");
+        }
+
+        let (int_bytes, rest) = ramdata.split_at(8);
+        self.rtc_last = u64::from_be_bytes(int_bytes.try_into().unwrap());
+        let (pages, rest) = rest.split_at(RTC_PAGES * RTC_PAGE_SIZE);
+        for (page, data) in self.rtc.iter_mut().zip(pages.chunks(RTC_PAGE_SIZE)) {
+            page.copy_from_slice(data);
+        }
+        self.rtc_mode = rest[0];
+        self.ram.copy_from_slice(&rest[1 ..]);
+        Ok(())
+    }
+
+    fn dumpram(&self) -> Vec<u8> {
+        let mut file = self.rtc_last.to_be_bytes().to_vec();
+        for page in &self.rtc {
+            file.extend_from_slice(page);
+        }
+        file.push(self.rtc_mode);
+        file.extend_from_slice(&self.ram);
+        file
+    }
+
+    fn check_and_reset_ram_updated(&mut self) -> bool {
+        let result = self.ram_updated;
+        self.ram_updated = false;
+        result
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{TAMA5, advance_time, unix_now, RTC_PAGE_SIZE};
+    use crate::mbc::MBC;
+
+    fn write(mbc: &mut TAMA5, reg: u8, v: u8) {
+        mbc.writeram(0xA001, reg);
+        mbc.writeram(0xA000, v);
+    }
+
+    fn read(mbc: &mut TAMA5) -> u8 {
+        mbc.writeram(0xA001, 0x0C);
+        let lo = mbc.readram(0xA000) & 0x0F;
+        mbc.writeram(0xA001, 0x0D);
+        let hi = mbc.readram(0xA000) & 0x0F;
+        (hi << 4) | lo
+    }
+
+    fn mbc() -> TAMA5 {
+        let mut data = vec![0u8; 0x8000];
+        data[0x147] = 0xFD;
+        TAMA5::new(data).unwrap()
+    }
+
+    #[test]
+    fn eeprom_write_and_read() {
+        let mut mbc = mbc();
+        mbc.writeram(0xA001, 0x0A);
+        assert_eq!(mbc.readram(0xA000), 0xF1);
+
+        // Write 0xA5 to address 0x13
+        write(&mut mbc, 0x4, 0x5);
+        write(&mut mbc, 0x5, 0xA);
+        write(&mut mbc, 0x6, 0x1);
+        write(&mut mbc, 0x7, 0x3);
+        assert!(mbc.check_and_reset_ram_updated());
+
+        write(&mut mbc, 0x6, 0x3);
+        write(&mut mbc, 0x7, 0x3);
+        assert_eq!(read(&mut mbc), 0xA5);
+
+        let mut loaded = self::mbc();
+        loaded.loadram(&mbc.dumpram()).unwrap();
+        assert_eq!(read(&mut loaded), 0x00);
+        write(&mut loaded, 0x6, 0x3);
+        write(&mut loaded, 0x7, 0x3);
+        assert_eq!(read(&mut loaded), 0xA5);
+    }
+
+    #[test]
+    fn rtc_hour_write_and_calendar() {
+        let mut mbc = mbc();
+        // Hour write of 0x21
+        write(&mut mbc, 0x4, 0x1);
+        write(&mut mbc, 0x5, 0x2);
+        write(&mut mbc, 0x6, 0x4);
+        write(&mut mbc, 0x7, 0x5);
+        write(&mut mbc, 0x7, 0x7);
+        let hour = read(&mut mbc);
+        assert!(hour == 0x21 || hour == 0x22);
+
+        // 23:59:59 on 28 February of a leap year rolls over to the 29th
+        let mut time = [9, 5, 9, 5, 3, 2, 0, 8, 2, 2, 0, 0, 0];
+        let mut leap = 0;
+        advance_time(&mut time, &mut leap, 1);
+        assert_eq!(time, [0, 0, 0, 0, 0, 0, 1, 9, 2, 2, 0, 0, 0]);
+
+        // 31 December 1999 rolls over to 1 January 2000
+        let mut time: [u8; RTC_PAGE_SIZE] = [0, 0, 0, 0, 0, 0, 6, 1, 3, 2, 1, 9, 9];
+        advance_time(&mut time, &mut leap, 24 * 3600);
+        assert_eq!(time, [0, 0, 0, 0, 0, 0, 0, 1, 0, 1, 0, 0, 0]);
+        assert_eq!(leap, 1);
+    }
+
+    #[test]
+    fn rtc_alarm() {
+        let mut mbc = mbc();
+        // 10:00 on the time page
+        mbc.rtc[0] = [0, 0, 0, 0, 0, 1, 0, 1, 0, 1, 0, 0, 0];
+        mbc.rtc_last = unix_now();
+        // Alarm at 10:30 on page 1
+        for (index, value) in [(0x2, 0), (0x3, 3), (0x4, 0), (0x5, 1)] {
+            write(&mut mbc, 0x4, index);
+            write(&mut mbc, 0x5, value);
+            write(&mut mbc, 0x6, 0x8);
+            write(&mut mbc, 0x7, 0x2);
+        }
+        // Enable the alarm, then select the alarm flag
+        write(&mut mbc, 0x6, 0x5);
+        write(&mut mbc, 0x7, 0x1);
+        write(&mut mbc, 0x7, 0x2);
+        assert_eq!(read(&mut mbc), 0);
+
+        mbc.rtc_last -= 29 * 60;
+        assert_eq!(read(&mut mbc), 0);
+        mbc.rtc_last -= 2 * 60;
+        assert_eq!(read(&mut mbc), 1);
+
+        // The flag stays set once the time is synced past the alarm, until the alarm is disabled
+        write(&mut mbc, 0x7, 0x2);
+        assert_eq!(read(&mut mbc), 1);
+        write(&mut mbc, 0x7, 0x0);
+        write(&mut mbc, 0x6, 0x5);
+        write(&mut mbc, 0x7, 0x2);
+        assert_eq!(read(&mut mbc), 0);
+    }
+}
@@ -0,0 +1,33 @@
+use crate::StrResult;
+use crate::mbc::MBC;
+
+/// Wisdom Tree mapper. A write to 0000-3FFF maps the 32 KiB bank given by the low bits of the
+/// address, the written value is ignored.
+pub struct WisdomTree {
+    rom: Vec<u8>,
+    bank: usize,
+}
+
+impl WisdomTree {
+    pub fn new(data: Vec<u8>) -> StrResult<WisdomTree> {
+        Ok(WisdomTree { rom: data, bank: 0 })
+    }
+}
+
+impl MBC for WisdomTree {
+    fn readrom(&self, a: u16) -> u8 {
+        *self.rom.get((self.bank * 0x8000) | (a as usize)).unwrap_or(&0xFF)
+    }
+    fn readram(&self, _a: u16) -> u8 { 0xFF }
+    fn writerom(&mut self, a: u16, _v: u8) {
+        if a < 0x4000 {
+            self.bank = (a & 0x3F) as usize;
+        }
+    }
+    fn writeram(&mut self, _a: u16, _v: u8) {}
+
+    fn is_battery_backed(&self) -> bool { false }
+    fn loadram(&mut self, _ramdata: &[u8]) -> StrResult<()> { Ok(()) }
+    fn dumpram(&self) -> Vec<u8> { Vec::new() }
+    fn check_and_reset_ram_updated(&mut self) -> bool { false }
+}
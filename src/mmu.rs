@@ -1,7 +1,7 @@
 use crate::serial::{Serial, SerialCallback};
 use crate::timer::Timer;
 use crate::keypad::Keypad;
-use crate::gpu::GPU;
+use crate::gpu::{GPU, title_colors};
 use crate::sound::Sound;
 use crate::gbmode::{GbMode, GbSpeed};
 use crate::StrResult;
@@ -163,6 +163,10 @@ impl<'a> MMU<'a> {
         };
         self.gbmode = mode;
         self.gpu.gbmode = mode;
+        if mode == GbMode::ColorAsClassic {
+            let header: Vec<u8> = (0 .. 0x150).map(|a| self.rb(a)).collect();
+            self.gpu.set_compat_colors(title_colors(&header));
+        }
     }
 
     pub fn do_cycle(&mut self, ticks: u32) -> u32 {
@@ -185,7 +189,12 @@ impl<'a> MMU<'a> {
         self.intf |= self.gpu.interrupt;
         self.gpu.interrupt = 0;
 
-        let _ = self.sound.as_mut().map_or((), |s| s.do_cycle(gputicks));
+        self.mbc.do_cycle(gputicks);
+
+        if let Some(sound) = self.sound.as_mut() {
+            sound.set_speaker(self.mbc.speaker_on());
+            sound.do_cycle(gputicks);
+        }
 
         self.intf |= self.serial.interrupt;
         self.serial.interrupt = 0;
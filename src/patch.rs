@@ -0,0 +1,364 @@
+use crate::StrResult;
+
+const IPS_MAGIC: &[u8] = b"PATCH";
+const IPS_EOF: &[u8] = b"EOF";
+const UPS_MAGIC: &[u8] = b"UPS1";
+const BPS_MAGIC: &[u8] = b"BPS1";
+
+// Size of the largest cartridges. Larger target sizes in a patch are rejected, so a corrupt
+// patch cannot make us allocate an absurd amount of memory.
+const MAX_TARGET_SIZE: usize = 8 * 1024 * 1024;
+
+// Extensions that are checked next to the ROM, in order of preference
+pub const PATCH_EXTENSIONS: [&str; 3] = ["ips", "ups", "bps"];
+
+/// Applies an IPS, UPS or BPS patch to a ROM. The format is determined by the header of the patch.
+/// The input ROM is never modified, a new buffer is returned instead.
+pub fn apply(rom: &[u8], patch: &[u8]) -> StrResult<Vec<u8>> {
+    if patch.starts_with(IPS_MAGIC) {
+        apply_ips(rom, patch)
+    }
+    else if patch.starts_with(UPS_MAGIC) {
+        apply_ups(rom, patch)
+    }
+    else if patch.starts_with(BPS_MAGIC) {
+        apply_bps(rom, patch)
+    }
+    else {
+        Err("Unknown patch format")
+    }
+}
+
+struct PatchReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> PatchReader<'a> {
+    fn new(data: &'a [u8], pos: usize) -> PatchReader<'a> {
+        PatchReader { data, pos }
+    }
+
+    fn byte(&mut self) -> StrResult<u8> {
+        let v = *self.data.get(self.pos).ok_or("Patch is truncated")?;
+        self.pos += 1;
+        Ok(v)
+    }
+
+    fn bytes(&mut self, len: usize) -> StrResult<&'a [u8]> {
+        let end = self.pos.checked_add(len).ok_or("Patch is truncated")?;
+        let v = self.data.get(self.pos .. end).ok_or("Patch is truncated")?;
+        self.pos = end;
+        Ok(v)
+    }
+
+    fn be(&mut self, len: usize) -> StrResult<usize> {
+        Ok(self.bytes(len)?.iter().fold(0, |acc, &v| (acc << 8) | v as usize))
+    }
+
+    // Variable length integer as used by both UPS and BPS
+    fn varint(&mut self) -> StrResult<usize> {
+        let mut value: usize = 0;
+        let mut shift: usize = 1;
+        loop {
+            let x = self.byte()?;
+            value = (x as usize & 0x7F).checked_mul(shift)
+                .and_then(|v| v.checked_add(value))
+                .ok_or("Patch contains an invalid number")?;
+            if x & 0x80 != 0 { break; }
+            shift = shift.checked_shl(7).ok_or("Patch contains an invalid number")?;
+            value = value.checked_add(shift).ok_or("Patch contains an invalid number")?;
+        }
+        Ok(value)
+    }
+}
+
+fn apply_ips(rom: &[u8], patch: &[u8]) -> StrResult<Vec<u8>> {
+    let mut result = rom.to_vec();
+    let mut reader = PatchReader::new(patch, IPS_MAGIC.len());
+
+    loop {
+        if reader.data[reader.pos ..].starts_with(IPS_EOF) {
+            reader.pos += IPS_EOF.len();
+            break;
+        }
+
+        let offset = reader.be(3)?;
+        let size = reader.be(2)?;
+        let (size, rle_value) = match size {
+            0 => (reader.be(2)?, Some(reader.byte()?)),
+            n => (n, None),
+        };
+
+        if result.len() < offset + size {
+            result.resize(offset + size, 0);
+        }
+        match rle_value {
+            Some(v) => result[offset .. offset + size].iter_mut().for_each(|b| *b = v),
+            None => result[offset .. offset + size].copy_from_slice(reader.bytes(size)?),
+        }
+    }
+
+    // Optional truncation extension
+    if patch.len() - reader.pos == 3 {
+        let size = reader.be(3)?;
+        result.truncate(size);
+    }
+
+    Ok(result)
+}
+
+fn check_footer(source: &[u8], target: &[u8], patch: &[u8]) -> StrResult<()> {
+    let footer = PatchReader::new(patch, patch.len() - 12).bytes(12)?;
+    let read_crc = |i: usize| u32::from_le_bytes([footer[i], footer[i + 1], footer[i + 2], footer[i + 3]]);
+
+    if crc32(&patch[.. patch.len() - 4]) != read_crc(8) {
+        return Err("Patch checksum is invalid");
+    }
+    if crc32(source) != read_crc(0) {
+        return Err("Patch does not match the ROM");
+    }
+    if crc32(target) != read_crc(4) {
+        return Err("Patched ROM checksum is invalid");
+    }
+    Ok(())
+}
+
+fn apply_ups(rom: &[u8], patch: &[u8]) -> StrResult<Vec<u8>> {
+    if patch.len() < UPS_MAGIC.len() + 12 { return Err("Patch is truncated"); }
+
+    let mut reader = PatchReader::new(&patch[.. patch.len() - 12], UPS_MAGIC.len());
+    let source_size = reader.varint()?;
+    let target_size = reader.varint()?;
+    if target_size > MAX_TARGET_SIZE {
+        return Err("Patch target size is too large");
+    }
+    if source_size != rom.len() {
+        return Err("Patch does not match the ROM");
+    }
+
+    let mut result = rom.to_vec();
+    result.resize(target_size, 0);
+
+    let mut pos: usize = 0;
+    while reader.pos < reader.data.len() {
+        pos = pos.checked_add(reader.varint()?).ok_or("Patch contains an invalid offset")?;
+        loop {
+            let x = reader.byte()?;
+            if pos < result.len() {
+                result[pos] ^= x;
+            }
+            pos = pos.checked_add(1).ok_or("Patch contains an invalid offset")?;
+            if x == 0 { break; }
+        }
+    }
+
+    check_footer(rom, &result, patch)?;
+    Ok(result)
+}
+
+fn apply_bps(rom: &[u8], patch: &[u8]) -> StrResult<Vec<u8>> {
+    if patch.len() < BPS_MAGIC.len() + 12 { return Err("Patch is truncated"); }
+
+    let mut reader = PatchReader::new(&patch[.. patch.len() - 12], BPS_MAGIC.len());
+    let source_size = reader.varint()?;
+    let target_size = reader.varint()?;
+    let metadata_size = reader.varint()?;
+    reader.bytes(metadata_size)?;
+    if target_size > MAX_TARGET_SIZE {
+        return Err("Patch target size is too large");
+    }
+    if source_size != rom.len() {
+        return Err("Patch does not match the ROM");
+    }
+
+    let mut result = Vec::with_capacity(target_size);
+    let mut source_offset: usize = 0;
+    let mut target_offset: usize = 0;
+
+    let relative = |offset: usize, data: usize| -> StrResult<usize> {
+        let delta = data >> 1;
+        match data & 1 {
+            0 => offset.checked_add(delta),
+            _ => offset.checked_sub(delta),
+        }.ok_or("Patch contains an invalid offset")
+    };
+
+    while reader.pos < reader.data.len() {
+        let data = reader.varint()?;
+        let length = (data >> 2) + 1;
+        let end = result.len().checked_add(length).ok_or("Patch contains an invalid offset")?;
+        if end > target_size {
+            return Err("Patch writes beyond the target size");
+        }
+
+        match data & 3 {
+            // SourceRead
+            0 => {
+                let start = result.len();
+                result.extend_from_slice(rom.get(start .. end).ok_or("Patch reads beyond the ROM")?);
+            },
+            // TargetRead
+            1 => result.extend_from_slice(reader.bytes(length)?),
+            // SourceCopy
+            2 => {
+                source_offset = relative(source_offset, reader.varint()?)?;
+                let src_end = source_offset.checked_add(length).ok_or("Patch contains an invalid offset")?;
+                let src = rom.get(source_offset .. src_end).ok_or("Patch reads beyond the ROM")?;
+                result.extend_from_slice(src);
+                source_offset += length;
+            },
+            // TargetCopy, may overlap with the data being written
+            _ => {
+                target_offset = relative(target_offset, reader.varint()?)?;
+                for _ in 0 .. length {
+                    let v = *result.get(target_offset).ok_or("Patch reads beyond the target")?;
+                    result.push(v);
+                    target_offset += 1;
+                }
+            },
+        }
+    }
+
+    if result.len() != target_size {
+        return Err("Patch did not produce a ROM of the expected size");
+    }
+
+    check_footer(rom, &result, patch)?;
+    Ok(result)
+}
+
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFFFFFFu32;
+    for &b in data {
+        crc ^= b as u32;
+        for _ in 0 .. 8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0xEDB88320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod test {
+    use super::crc32;
+
+    fn varint(mut v: usize, out: &mut Vec<u8>) {
+        loop {
+            let x = (v & 0x7F) as u8;
+            v >>= 7;
+            if v == 0 {
+                out.push(0x80 | x);
+                break;
+            }
+            out.push(x);
+            v -= 1;
+        }
+    }
+
+    fn footer(source: &[u8], target: &[u8], mut patch: Vec<u8>) -> Vec<u8> {
+        patch.extend_from_slice(&crc32(source).to_le_bytes());
+        patch.extend_from_slice(&crc32(target).to_le_bytes());
+        let patchcrc = crc32(&patch);
+        patch.extend_from_slice(&patchcrc.to_le_bytes());
+        patch
+    }
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xCBF43926);
+    }
+
+    #[test]
+    fn ips() {
+        let rom = vec![0u8; 8];
+        let mut patch = b"PATCH".to_vec();
+        patch.extend_from_slice(&[0, 0, 1, 0, 2, 0xAA, 0xBB]);
+        patch.extend_from_slice(&[0, 0, 6, 0, 0, 0, 4, 0xCC]);
+        patch.extend_from_slice(b"EOF");
+
+        let result = super::apply(&rom, &patch).unwrap();
+        assert_eq!(result, vec![0, 0xAA, 0xBB, 0, 0, 0, 0xCC, 0xCC, 0xCC, 0xCC]);
+        assert_eq!(rom, vec![0u8; 8]);
+    }
+
+    #[test]
+    fn ups() {
+        let rom = vec![1u8, 2, 3, 4];
+        let target = vec![1u8, 5, 3, 4, 9];
+
+        let mut patch = b"UPS1".to_vec();
+        varint(rom.len(), &mut patch);
+        varint(target.len(), &mut patch);
+        varint(1, &mut patch);
+        patch.extend_from_slice(&[2 ^ 5, 0]);
+        varint(1, &mut patch);
+        patch.extend_from_slice(&[9, 0]);
+        let patch = footer(&rom, &target, patch);
+
+        assert_eq!(super::apply(&rom, &patch).unwrap(), target);
+        assert!(super::apply(&[1u8, 2, 3, 5], &patch).is_err());
+    }
+
+    #[test]
+    fn bps() {
+        let rom = vec![1u8, 2, 3, 4];
+        let target = vec![1u8, 2, 7, 7, 7, 3, 4];
+
+        let mut patch = b"BPS1".to_vec();
+        varint(rom.len(), &mut patch);
+        varint(target.len(), &mut patch);
+        varint(0, &mut patch);
+        // SourceRead 2 bytes
+        varint((2 - 1) << 2, &mut patch);
+        // TargetRead 1 byte
+        varint(1, &mut patch);
+        patch.push(7);
+        // TargetCopy 2 bytes from target offset 2
+        varint(((2 - 1) << 2) | 3, &mut patch);
+        varint(2 << 1, &mut patch);
+        // SourceCopy 2 bytes from source offset 2
+        varint(((2 - 1) << 2) | 2, &mut patch);
+        varint(2 << 1, &mut patch);
+        let patch = footer(&rom, &target, patch);
+
+        assert_eq!(super::apply(&rom, &patch).unwrap(), target);
+
+        let mut corrupt = patch.clone();
+        corrupt[6] ^= 1;
+        assert!(super::apply(&rom, &corrupt).is_err());
+    }
+
+    #[test]
+    fn invalid_sizes_and_offsets() {
+        let rom = vec![1u8, 2, 3, 4];
+        for magic in [b"UPS1", b"BPS1"] {
+            let mut patch = magic.to_vec();
+            varint(rom.len(), &mut patch);
+            varint(usize::MAX / 2, &mut patch);
+            varint(0, &mut patch);
+            let patch = footer(&rom, &rom, patch);
+            assert_eq!(super::apply(&rom, &patch), Err("Patch target size is too large"));
+        }
+
+        let mut patch = b"UPS1".to_vec();
+        varint(rom.len(), &mut patch);
+        varint(rom.len(), &mut patch);
+        varint(usize::MAX / 2, &mut patch);
+        patch.push(0);
+        varint(usize::MAX / 2, &mut patch);
+        patch.push(0);
+        let patch = footer(&rom, &rom, patch);
+        assert_eq!(super::apply(&rom, &patch), Err("Patch contains an invalid offset"));
+
+        let mut patch = b"BPS1".to_vec();
+        varint(rom.len(), &mut patch);
+        varint(rom.len(), &mut patch);
+        varint(0, &mut patch);
+        // SourceCopy from an offset near the end of the address space
+        varint(((2 - 1) << 2) | 2, &mut patch);
+        varint((usize::MAX >> 1) << 1, &mut patch);
+        let patch = footer(&rom, &rom, patch);
+        assert!(super::apply(&rom, &patch).is_err());
+    }
+}
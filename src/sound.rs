@@ -6,6 +6,11 @@ const CLOCKS_PER_FRAME : u32 = CLOCKS_PER_SECOND / 512;
 const OUTPUT_SAMPLE_COUNT : usize = 2000; // this should be less than blip_buf::MAX_FRAME
 const SWEEP_DELAY_ZERO_PERIOD : u8 = 8;
 
+// Pitch and volume of the cartridge speaker (HuC3). The pitches it can produce are not
+// documented, so a single square wave is used.
+const SPEAKER_FREQUENCY : u32 = 2048;
+const SPEAKER_VOLUME : i32 = 8;
+
 // Additional delay on trigger of the wave channel (channel 3). In other emulators it is 6, but we
 // need 4 since we run the wave after delay == 0, instead of at delay == 0
 const WAVE_INITIAL_DELAY : u32 = 4;
@@ -678,6 +683,45 @@ impl NoiseChannel {
     }
 }
 
+// Speaker on the cartridge, mixed into both outputs regardless of the sound registers
+struct SpeakerChannel {
+    on: bool,
+    last_amp: i32,
+    delay: u32,
+    blip: BlipBuf,
+}
+
+impl SpeakerChannel {
+    fn new(blip: BlipBuf) -> SpeakerChannel {
+        SpeakerChannel {
+            on: false,
+            last_amp: 0,
+            delay: 0,
+            blip,
+        }
+    }
+
+    fn run(&mut self, start_time: u32, end_time: u32) {
+        if !self.on {
+            if self.last_amp != 0 {
+                self.blip.add_delta(start_time, -self.last_amp);
+                self.last_amp = 0;
+                self.delay = 0;
+            }
+        }
+        else {
+            let mut time = start_time + self.delay;
+            while time < end_time {
+                let amp = if self.last_amp > 0 { -SPEAKER_VOLUME } else { SPEAKER_VOLUME };
+                self.blip.add_delta(time, amp - self.last_amp);
+                self.last_amp = amp;
+                time += CLOCKS_PER_SECOND / SPEAKER_FREQUENCY / 2;
+            }
+            self.delay = time - end_time;
+        }
+    }
+}
+
 pub struct Sound {
     on: bool,
     time: u32,
@@ -689,6 +733,7 @@ pub struct Sound {
     channel2: SquareChannel,
     channel3: WaveChannel,
     channel4: NoiseChannel,
+    speaker: SpeakerChannel,
     volume_left: u8,
     volume_right: u8,
     reg_vin_to_so: u8,
@@ -712,6 +757,7 @@ impl Sound {
         let blipbuf2 = create_blipbuf(player.samples_rate());
         let blipbuf3 = create_blipbuf(player.samples_rate());
         let blipbuf4 = create_blipbuf(player.samples_rate());
+        let blipbuf5 = create_blipbuf(player.samples_rate());
 
         let output_period = (OUTPUT_SAMPLE_COUNT as u64 * CLOCKS_PER_SECOND as u64) / player.samples_rate() as u64;
 
@@ -726,6 +772,7 @@ impl Sound {
             channel2: SquareChannel::new(blipbuf2, false),
             channel3: WaveChannel::new(blipbuf3, dmg_mode),
             channel4: NoiseChannel::new(blipbuf4),
+            speaker: SpeakerChannel::new(blipbuf5),
             volume_left: 7,
             volume_right: 7,
             reg_vin_to_so: 0x00,
@@ -817,6 +864,13 @@ impl Sound {
         }
     }
 
+    pub fn set_speaker(&mut self, on: bool) {
+        if self.speaker.on != on {
+            self.run();
+            self.speaker.on = on;
+        }
+    }
+
     pub fn sync(&mut self) {
         self.need_sync = true;
     }
@@ -828,6 +882,7 @@ impl Sound {
         self.channel2.blip.end_frame(self.time);
         self.channel3.blip.end_frame(self.time);
         self.channel4.blip.end_frame(self.time);
+        self.speaker.blip.end_frame(self.time);
         self.next_time -= self.time;
         self.time = 0;
         self.prev_time = 0;
@@ -848,6 +903,7 @@ impl Sound {
             self.channel2.run(self.prev_time, self.next_time);
             self.channel3.run(self.prev_time, self.next_time);
             self.channel4.run(self.prev_time, self.next_time);
+            self.speaker.run(self.prev_time, self.next_time);
 
             if self.frame_step % 2 == 0 {
                 self.channel1.step_length();
@@ -875,6 +931,7 @@ impl Sound {
             self.channel2.run(self.prev_time, self.time);
             self.channel3.run(self.prev_time, self.time);
             self.channel4.run(self.prev_time, self.time);
+            self.speaker.run(self.prev_time, self.time);
 
             self.prev_time = self.time;
         }
@@ -885,6 +942,7 @@ impl Sound {
         debug_assert!(sample_count == self.channel2.blip.samples_avail() as usize);
         debug_assert!(sample_count == self.channel3.blip.samples_avail() as usize);
         debug_assert!(sample_count == self.channel4.blip.samples_avail() as usize);
+        debug_assert!(sample_count == self.speaker.blip.samples_avail() as usize);
 
         let mut outputted = 0;
 
@@ -938,9 +996,16 @@ impl Sound {
                 }
             }
 
+            let count5 = self.speaker.blip.read_samples(buf, false);
+            for (i, v) in buf[..count5].iter().enumerate() {
+                buf_left[i] += *v as f32 * left_vol;
+                buf_right[i] += *v as f32 * right_vol;
+            }
+
             debug_assert!(count1 == count2);
             debug_assert!(count1 == count3);
             debug_assert!(count1 == count4);
+            debug_assert!(count1 == count5);
 
             self.player.play(&buf_left[..count1], &buf_right[..count1]);
 
@@ -953,6 +1018,7 @@ impl Sound {
         self.channel2.blip.clear();
         self.channel3.blip.clear();
         self.channel4.blip.clear();
+        self.speaker.blip.clear();
     }
 }
 